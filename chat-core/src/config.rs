@@ -1,17 +1,24 @@
 use anyhow::{bail, Result};
 use serde::Deserialize;
-use std::fs::File;
+use std::{fs::File, path::PathBuf};
 
 #[derive(Debug, Deserialize)]
 pub struct AppConfig {
     pub server: ServerConfig,
     pub db: DbConfig,
     pub auth: AuthConfig,
+    pub storage: StorageConfig,
+    #[serde(default)]
+    pub mail: MailConfig,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct ServerConfig {
     pub port: u16,
+    // reverse proxies in front of the server which append to X-Forwarded-For,
+    // the header is ignored if there are none
+    #[serde(default)]
+    pub trusted_proxies: usize,
 }
 
 #[derive(Debug, Deserialize)]
@@ -21,8 +28,211 @@ pub struct DbConfig {
 
 #[derive(Debug, Deserialize)]
 pub struct AuthConfig {
+    // active signing key, tokens are signed with it
     pub private_key: String,
     pub public_key: String,
+    // key id in token headers, the JWK thumbprint of the public key if absent
+    #[serde(default)]
+    pub kid: Option<String>,
+    // keys which signed tokens before, kept until those tokens expire
+    #[serde(default)]
+    pub retired_keys: Vec<JwtKeyConfig>,
+    #[serde(default)]
+    pub argon2: Argon2Config,
+    // OpenID Connect providers users can sign in with
+    #[serde(default)]
+    pub oidc: Vec<OidcProviderConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct JwtKeyConfig {
+    pub kid: Option<String>,
+    pub public_key: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct OidcProviderConfig {
+    // name in the sign in urls, e.g. `google` for `/api/oidc/google/authorize`
+    pub name: String,
+    // metadata is discovered from `{issuer}/.well-known/openid-configuration`
+    pub issuer: String,
+    pub client_id: String,
+    // absent for public clients, which only rely on PKCE
+    pub client_secret: Option<String>,
+    // page of the web client which receives the authorization code
+    pub redirect_uri: String,
+    #[serde(default = "default_oidc_scopes")]
+    pub scopes: Vec<String>,
+}
+
+fn default_oidc_scopes() -> Vec<String> {
+    vec![
+        "openid".to_string(),
+        "email".to_string(),
+        "profile".to_string(),
+    ]
+}
+
+// cost of password hashes, stored hashes of a lower cost are upgraded on sign in
+#[derive(Debug, Clone, Deserialize)]
+pub struct Argon2Config {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+// the defaults of the argon2 crate, as recommended by OWASP
+impl Default for Argon2Config {
+    fn default() -> Self {
+        Self {
+            memory_kib: 19 * 1024,
+            iterations: 2,
+            parallelism: 1,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StorageConfig {
+    // working dir for files being uploaded
+    pub base_dir: PathBuf,
+    // max size in bytes of a single uploaded file
+    pub max_file_size: u64,
+    // HMAC key of signed download urls
+    pub signing_key: String,
+    pub backend: StorageBackend,
+    #[serde(default)]
+    pub thumbnail: ThumbnailConfig,
+    #[serde(default)]
+    pub avatar: AvatarConfig,
+    #[serde(default)]
+    pub quota: QuotaConfig,
+    // content types accepted for uploads, detected from the file bytes, e.g. `image/*`
+    #[serde(default = "default_allowed_types")]
+    pub allowed_types: Vec<String>,
+    // virus scanner of uploaded files, no scanning if absent
+    #[serde(default)]
+    pub scanner: Option<ScannerConfig>,
+}
+
+fn default_allowed_types() -> Vec<String> {
+    vec!["*/*".to_string()]
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ScannerConfig {
+    // clamd listening on tcp, e.g. `127.0.0.1:3310`
+    #[serde(rename = "clamav")]
+    ClamAv { addr: String },
+}
+
+// max bytes stored by each uploader and in each workspace, unlimited if absent
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct QuotaConfig {
+    pub user_bytes: Option<u64>,
+    pub workspace_bytes: Option<u64>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ThumbnailConfig {
+    // longest edge in pixels of the generated thumbnails
+    pub sizes: Vec<u32>,
+    // max number of images processed at the same time
+    pub workers: usize,
+}
+
+impl Default for ThumbnailConfig {
+    fn default() -> Self {
+        Self {
+            sizes: vec![160, 640],
+            workers: 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct AvatarConfig {
+    // edge in pixels of the rendered square avatars, the largest one is served by default
+    pub sizes: Vec<u32>,
+}
+
+impl Default for AvatarConfig {
+    fn default() -> Self {
+        Self {
+            sizes: vec![64, 128, 256],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum StorageBackend {
+    Local { dir: PathBuf },
+    S3(S3Config),
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct S3Config {
+    pub endpoint: String,
+    pub bucket: String,
+    pub region: String,
+    pub access_key: String,
+    pub secret_key: String,
+    // use `{endpoint}/{bucket}/{key}` instead of `{bucket}.{endpoint}/{key}`, required by MinIO
+    #[serde(default)]
+    pub path_style: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MailConfig {
+    // sender of the mails, e.g. `easy-chat <noreply@acme.com>`
+    pub from: String,
+    // url of the web client, links in the mails are relative to it
+    pub base_url: String,
+    pub transport: MailTransport,
+}
+
+impl Default for MailConfig {
+    fn default() -> Self {
+        Self {
+            from: "easy-chat <noreply@localhost>".to_string(),
+            base_url: "http://localhost:3000".to_string(),
+            transport: MailTransport::File {
+                dir: PathBuf::from("/tmp/easy-chat/mail"),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MailTransport {
+    Smtp(SmtpConfig),
+    // write the mails as .eml files instead of sending them, for development
+    File { dir: PathBuf },
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SmtpConfig {
+    pub host: String,
+    // default port of the tls mode if absent
+    pub port: Option<u16>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    #[serde(default)]
+    pub tls: SmtpTls,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SmtpTls {
+    // plaintext, only for a relay on localhost
+    None,
+    #[default]
+    Starttls,
+    // tls from the start of the connection, usually on port 465
+    Wrapper,
 }
 
 impl AppConfig {
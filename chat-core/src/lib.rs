@@ -1,3 +1,7 @@
 mod config;
 
-pub use config::AppConfig;
+pub use config::{
+    AppConfig, Argon2Config, AuthConfig, AvatarConfig, DbConfig, JwtKeyConfig, MailConfig,
+    MailTransport, OidcProviderConfig, QuotaConfig, S3Config, ScannerConfig, ServerConfig,
+    SmtpConfig, SmtpTls, StorageBackend, StorageConfig, ThumbnailConfig,
+};
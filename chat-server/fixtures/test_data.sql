@@ -0,0 +1,17 @@
+-- users 1 to 4, the password of all of them is changed123
+INSERT INTO t_user (username, email, passwd, role, email_verified_at) VALUES
+    ('alice', 'alice@acme.com', '$argon2id$v=19$m=19456,t=2,p=1$NbjcvgNqeIh0bQ5YRYRIfw$aIn3IRmXVzQfGOGKyZ3J3b9CMpNN5K2Q/9Sd2TgeTiU', 'member', NOW()),
+    ('bob', 'bob@acme.com', '$argon2id$v=19$m=19456,t=2,p=1$NbjcvgNqeIh0bQ5YRYRIfw$aIn3IRmXVzQfGOGKyZ3J3b9CMpNN5K2Q/9Sd2TgeTiU', 'member', NOW()),
+    ('carol', 'carol@acme.com', '$argon2id$v=19$m=19456,t=2,p=1$NbjcvgNqeIh0bQ5YRYRIfw$aIn3IRmXVzQfGOGKyZ3J3b9CMpNN5K2Q/9Sd2TgeTiU', 'moderator', NOW()),
+    ('dave', 'dave@acme.com', '$argon2id$v=19$m=19456,t=2,p=1$NbjcvgNqeIh0bQ5YRYRIfw$aIn3IRmXVzQfGOGKyZ3J3b9CMpNN5K2Q/9Sd2TgeTiU', 'admin', NOW());
+
+-- chat 1 of alice and bob, chat 2 of alice and carol
+INSERT INTO t_chat (id, name, type, members) VALUES
+    (1, '', 'single', '{1,2}'),
+    (2, '', 'single', '{1,3}');
+
+-- messages 1 and 2 in chat 1, message 3 in chat 2
+INSERT INTO t_message (chat_id, sender_id, content, images) VALUES
+    (1, 1, 'hello bob', '{}'),
+    (1, 2, 'hello alice', '{}'),
+    (2, 3, 'hi alice', '{}');
@@ -0,0 +1,41 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+
+use crate::{
+    model::{CreateApiToken, ListApiTokens, SessionUser},
+    AppErr, AppState,
+};
+
+pub(crate) async fn create_api_token_handler(
+    user: SessionUser,
+    State(state): State<AppState>,
+    Json(input): Json<CreateApiToken>,
+) -> Result<impl IntoResponse, AppErr> {
+    let token = state.create_api_token(input, user.id).await?;
+
+    Ok((StatusCode::CREATED, Json(token)))
+}
+
+pub(crate) async fn list_api_token_handler(
+    user: SessionUser,
+    State(state): State<AppState>,
+    Query(input): Query<ListApiTokens>,
+) -> Result<impl IntoResponse, AppErr> {
+    let tokens = state.list_api_tokens(input, user.id).await?;
+
+    Ok(Json(tokens))
+}
+
+pub(crate) async fn revoke_api_token_handler(
+    user: SessionUser,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, AppErr> {
+    state.revoke_api_token(id, user.id).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -1,35 +1,61 @@
-use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use std::net::SocketAddr;
+
+use axum::{
+    extract::{ConnectInfo, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
 use serde::Serialize;
+use tracing::warn;
 
 use crate::{
     error::ErrOutput,
-    model::{SignInForm, SignUpForm},
+    model::{ClientInfo, RefreshForm, SessionUser, SignInForm, SignUpForm, TwoFactorForm, User},
+    util::{AccessToken, LOGIN_CHALLENGE_EXPIRATION_TIME},
     AppErr, AppState,
 };
 
+// other services refresh the keys at most this often, retired keys stay published longer than that
+const JWKS_CACHE_CONTROL: &str = "public, max-age=300";
+
 #[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
 pub struct AuthOutput {
     token: String,
+    refresh_token: String,
+}
+
+/// Returned by sign in instead of a session when 2FA is on
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChallengeOutput {
+    two_factor_required: bool,
+    challenge_token: String,
+    /// Seconds until the challenge token expires
+    expires_in: i64,
 }
 
 impl AuthOutput {
-    pub fn new(token: String) -> Self {
-        Self { token }
+    pub fn new(token: String, refresh_token: String) -> Self {
+        Self {
+            token,
+            refresh_token,
+        }
     }
 }
 
 pub(crate) async fn sign_in_handler(
     State(state): State<AppState>,
+    headers: HeaderMap,
+    addr: Option<ConnectInfo<SocketAddr>>,
     Json(input): Json<SignInForm>,
 ) -> Result<impl IntoResponse, AppErr> {
+    let client = client_info(&state, input.device_name.clone(), &headers, addr);
     let user = state.verify_user(input).await?;
 
     match user {
-        Some(user) => {
-            let token = state.ek.sign(user)?;
-            let body = Json(AuthOutput::new(token));
-            Ok((StatusCode::OK, body).into_response())
-        }
+        Some(user) => start_session(&state, user, client).await,
         None => {
             let body = Json(ErrOutput::new("Invalid email or password".to_string()));
             Ok((StatusCode::UNAUTHORIZED, body).into_response())
@@ -37,14 +63,144 @@ pub(crate) async fn sign_in_handler(
     }
 }
 
+/// Sign in a user whose identity is verified, the second factor is asked first when 2FA is on
+pub(super) async fn start_session(
+    state: &AppState,
+    user: User,
+    client: ClientInfo,
+) -> Result<Response, AppErr> {
+    if state.totp_enabled(user.id).await? {
+        let challenge_token = state
+            .create_login_challenge(user.id, &client.device_name)
+            .await?;
+        let body = Json(ChallengeOutput {
+            two_factor_required: true,
+            challenge_token,
+            expires_in: LOGIN_CHALLENGE_EXPIRATION_TIME,
+        });
+        return Ok((StatusCode::OK, body).into_response());
+    }
+
+    let (session_id, refresh_token) = state.create_session(user.id, client).await?;
+    let token = state.ek.sign(user, &session_id)?;
+    let body = Json(AuthOutput::new(token, refresh_token));
+    Ok((StatusCode::OK, body).into_response())
+}
+
+/// Finish a sign in of a user with 2FA, no access token is required
+pub(crate) async fn two_factor_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    addr: Option<ConnectInfo<SocketAddr>>,
+    Json(input): Json<TwoFactorForm>,
+) -> Result<impl IntoResponse, AppErr> {
+    let completed = state.complete_login_challenge(input).await?;
+    let client = client_info(&state, Some(completed.device_name), &headers, addr);
+    let (session_id, refresh_token) = state.create_session(completed.user.id, client).await?;
+    let token = state.ek.sign(completed.user, &session_id)?;
+
+    Ok(Json(AuthOutput::new(token, refresh_token)))
+}
+
 pub(crate) async fn sign_up_handler(
     State(state): State<AppState>,
+    headers: HeaderMap,
+    addr: Option<ConnectInfo<SocketAddr>>,
     Json(input): Json<SignUpForm>,
 ) -> Result<impl IntoResponse, AppErr> {
+    let client = client_info(&state, input.device_name.clone(), &headers, addr);
     let user = state.create_user(input).await?;
-    let token = state.ek.sign(user)?;
+    // the account is usable with limited access, the link can be resent if this fails
+    if let Err(e) = state.send_verification_email(&user).await {
+        warn!(
+            "failed to send verification email to user {}: {}",
+            user.id, e
+        );
+    }
+    let (session_id, refresh_token) = state.create_session(user.id, client).await?;
+    let token = state.ek.sign(user, &session_id)?;
 
-    let body = Json(AuthOutput::new(token));
+    let body = Json(AuthOutput::new(token, refresh_token));
 
     Ok((StatusCode::CREATED, body))
 }
+
+/// Renew an expired access token, the refresh token is rotated.
+/// No access token is required.
+pub(crate) async fn refresh_handler(
+    State(state): State<AppState>,
+    Json(input): Json<RefreshForm>,
+) -> Result<impl IntoResponse, AppErr> {
+    let refreshed = state.rotate_refresh_token(&input.refresh_token).await?;
+    let token = state.ek.sign(refreshed.user, &refreshed.session_id)?;
+
+    Ok(Json(AuthOutput::new(token, refreshed.refresh_token)))
+}
+
+/// Revoke the access token of the request and sign out its session
+pub(crate) async fn logout_handler(
+    user: SessionUser,
+    Extension(access_token): Extension<AccessToken>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppErr> {
+    state.revoke_access_token(&access_token, user.id).await?;
+    if let Some(sid) = &access_token.sid {
+        state.revoke_session(user.id, sid).await?;
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Public keys access tokens are signed with, for other services to verify them
+pub(crate) async fn jwks_handler(State(state): State<AppState>) -> impl IntoResponse {
+    (
+        [(header::CACHE_CONTROL, JWKS_CACHE_CONTROL)],
+        Json(state.dk.jwks().clone()),
+    )
+}
+
+/// Device info of the sign in request, see `forwarded_ip` for the client address behind proxies
+pub(super) fn client_info(
+    state: &AppState,
+    device_name: Option<String>,
+    headers: &HeaderMap,
+    addr: Option<ConnectInfo<SocketAddr>>,
+) -> ClientInfo {
+    let user_agent = headers
+        .get(header::USER_AGENT)
+        .and_then(|v| v.to_str().ok());
+    let forwarded = headers
+        .get("x-forwarded-for")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| forwarded_ip(v, state.config.server.trusted_proxies));
+    let ip = forwarded.or_else(|| addr.map(|ConnectInfo(addr)| addr.ip().to_string()));
+
+    ClientInfo::new(device_name, user_agent, ip)
+}
+
+/// Client address in X-Forwarded-For, each proxy appends the address it got the request from.
+/// Entries left of the ones of trusted proxies are set by the client and may be spoofed.
+fn forwarded_ip(forwarded_for: &str, trusted_proxies: usize) -> Option<String> {
+    let hop = trusted_proxies.checked_sub(1)?;
+    forwarded_for
+        .rsplit(',')
+        .nth(hop)
+        .map(|ip| ip.trim().to_string())
+        .filter(|ip| ip.parse::<std::net::IpAddr>().is_ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_forwarded_ip() {
+        let forwarded_for = "1.1.1.1, 2.2.2.2,3.3.3.3";
+        assert_eq!(forwarded_ip(forwarded_for, 0), None);
+        assert_eq!(forwarded_ip(forwarded_for, 1).as_deref(), Some("3.3.3.3"));
+        assert_eq!(forwarded_ip(forwarded_for, 2).as_deref(), Some("2.2.2.2"));
+        // more proxies than entries, the request did not pass all of them
+        assert_eq!(forwarded_ip(forwarded_for, 4), None);
+        assert_eq!(forwarded_ip("unknown", 1), None);
+    }
+}
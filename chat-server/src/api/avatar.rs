@@ -0,0 +1,85 @@
+use axum::{
+    extract::{Multipart, Path, Query, State},
+    http::header,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Deserialize;
+
+use crate::{
+    media::identicon,
+    model::{parse_avatar_name, parse_identicon_name, AvatarOutput, SessionUser},
+    storage::avatar_key,
+    AppErr, AppState,
+};
+
+use super::serve_object;
+
+// avatars are content addressed, a new upload gets a new reference
+const AVATAR_CACHE_CONTROL: &str = "public, max-age=31536000, immutable";
+const IDENTICON_CACHE_CONTROL: &str = "public, max-age=86400";
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct AvatarQuery {
+    /// Edge in pixels, one of the configured avatar sizes
+    size: Option<u32>,
+}
+
+pub(crate) async fn upload_avatar_handler(
+    user: SessionUser,
+    State(state): State<AppState>,
+    mut multipart: Multipart,
+) -> Result<impl IntoResponse, AppErr> {
+    while let Some(field) = multipart.next_field().await? {
+        if field.file_name().is_none() {
+            continue;
+        }
+
+        let avatar = state.save_avatar(user.id, field).await?;
+        return Ok(Json(AvatarOutput { avatar }));
+    }
+
+    Err(AppErr::InvalidInputErr(
+        "avatar file is missing".to_string(),
+    ))
+}
+
+pub(crate) async fn delete_avatar_handler(
+    user: SessionUser,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppErr> {
+    let avatar = state.delete_avatar(user.id).await?;
+    Ok(Json(AvatarOutput { avatar }))
+}
+
+/// Avatars are public so that they can be shown in `img` tags, no session token is required
+pub(crate) async fn avatar_handler(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Query(query): Query<AvatarQuery>,
+) -> Result<Response, AppErr> {
+    let not_found = || AppErr::NotFoundErr(format!("avatar {} not found", name));
+    let hash = parse_avatar_name(&name).ok_or_else(not_found)?;
+    let size = state.avatar_size(query.size)?;
+
+    let response = serve_object(&state, &avatar_key(hash, size), "image/jpeg")
+        .await?
+        .ok_or_else(not_found)?;
+    Ok(([(header::CACHE_CONTROL, AVATAR_CACHE_CONTROL)], response).into_response())
+}
+
+pub(crate) async fn identicon_handler(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Query(query): Query<AvatarQuery>,
+) -> Result<Response, AppErr> {
+    let user_id = parse_identicon_name(&name)
+        .ok_or_else(|| AppErr::NotFoundErr(format!("identicon {} not found", name)))?;
+    let size = state.avatar_size(query.size)?;
+
+    let headers = [
+        (header::CONTENT_TYPE, "image/png"),
+        (header::CACHE_CONTROL, IDENTICON_CACHE_CONTROL),
+    ];
+    Ok((headers, identicon(&user_id.to_string(), size)?).into_response())
+}
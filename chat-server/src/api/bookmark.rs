@@ -0,0 +1,51 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+
+use crate::{
+    model::{CreateBookmark, SessionUser, UpdateBookmark},
+    AppErr, AppState,
+};
+
+pub(crate) async fn list_bookmark_handler(
+    user: SessionUser,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppErr> {
+    let bookmarks = state.list_bookmarks(user.id).await?;
+
+    Ok(Json(bookmarks))
+}
+
+pub(crate) async fn create_bookmark_handler(
+    user: SessionUser,
+    State(state): State<AppState>,
+    Json(input): Json<CreateBookmark>,
+) -> Result<impl IntoResponse, AppErr> {
+    let bookmark = state.create_bookmark(input, user.id).await?;
+
+    Ok((StatusCode::CREATED, Json(bookmark)))
+}
+
+pub(crate) async fn update_bookmark_handler(
+    user: SessionUser,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    Json(input): Json<UpdateBookmark>,
+) -> Result<impl IntoResponse, AppErr> {
+    let bookmark = state.update_bookmark(input, id, user.id).await?;
+
+    Ok(Json(bookmark))
+}
+
+pub(crate) async fn delete_bookmark_handler(
+    user: SessionUser,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, AppErr> {
+    state.delete_bookmark(id, user.id).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
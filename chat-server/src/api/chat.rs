@@ -1,7 +1,14 @@
-use axum::response::IntoResponse;
+use axum::{extract::State, response::IntoResponse, Json};
 
-pub(crate) async fn list_chat_handler() -> impl IntoResponse {
-    "list_chat"
+use crate::{model::SessionUser, AppErr, AppState};
+
+pub(crate) async fn list_chat_handler(
+    user: SessionUser,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppErr> {
+    let chats = state.list_chats(user.id).await?;
+
+    Ok(Json(chats))
 }
 
 pub(crate) async fn create_chat_handler() -> impl IntoResponse {
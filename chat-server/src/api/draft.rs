@@ -0,0 +1,44 @@
+use axum::{
+    extract::{Path, Query, State},
+    response::IntoResponse,
+    Json,
+};
+
+use crate::{
+    model::{GetDraft, SaveDraft, SessionUser},
+    AppErr, AppState,
+};
+
+pub(crate) async fn get_draft_handler(
+    user: SessionUser,
+    State(state): State<AppState>,
+    Path(chat_id): Path<i64>,
+    Query(input): Query<GetDraft>,
+) -> Result<impl IntoResponse, AppErr> {
+    let draft = state.get_draft(input, chat_id, user.id).await?;
+
+    Ok(Json(draft))
+}
+
+pub(crate) async fn save_draft_handler(
+    user: SessionUser,
+    State(state): State<AppState>,
+    Path(chat_id): Path<i64>,
+    Json(input): Json<SaveDraft>,
+) -> Result<impl IntoResponse, AppErr> {
+    let draft = state.save_draft(input, chat_id, user.id).await?;
+
+    Ok(Json(draft))
+}
+
+pub(crate) async fn clear_draft_handler(
+    user: SessionUser,
+    State(state): State<AppState>,
+    Path(chat_id): Path<i64>,
+    Query(mut input): Query<SaveDraft>,
+) -> Result<impl IntoResponse, AppErr> {
+    input.content.clear();
+    let draft = state.save_draft(input, chat_id, user.id).await?;
+
+    Ok(Json(draft))
+}
@@ -0,0 +1,26 @@
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+
+use crate::{
+    model::{SessionUser, VerifyEmailForm},
+    AppErr, AppState,
+};
+
+/// Confirm the email with the token of the mailed link.
+/// No access token is required, the link may be opened on another device.
+pub(crate) async fn verify_email_handler(
+    State(state): State<AppState>,
+    Json(input): Json<VerifyEmailForm>,
+) -> Result<impl IntoResponse, AppErr> {
+    state.verify_email(&input.token).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub(crate) async fn resend_verification_handler(
+    user: SessionUser,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppErr> {
+    state.resend_verification_email(user.id).await?;
+
+    Ok(StatusCode::ACCEPTED)
+}
@@ -0,0 +1,145 @@
+use std::time::Duration;
+
+use axum::{
+    body::Body,
+    extract::{Multipart, Path, Query, State},
+    http::header,
+    response::{IntoResponse, Redirect, Response},
+    Json,
+};
+use serde::Deserialize;
+use tracing::warn;
+
+use crate::{
+    model::{ChatFile, SessionUser, SignFileUrls},
+    storage::thumbnail_key,
+    AppErr, AppState,
+};
+
+const PRESIGN_EXPIRES_IN: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct FileQuery {
+    /// Download the thumbnail of this size instead of the original image
+    size: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct SignedFileQuery {
+    size: Option<u32>,
+    expires: i64,
+    sig: String,
+}
+
+pub(crate) async fn upload_handler(
+    user: SessionUser,
+    State(state): State<AppState>,
+    Path(chat_id): Path<i64>,
+    mut multipart: Multipart,
+) -> Result<impl IntoResponse, AppErr> {
+    state.ensure_chat_member(chat_id, user.id).await?;
+
+    let mut files = vec![];
+    while let Some(field) = multipart.next_field().await? {
+        let Some(filename) = field.file_name().map(|name| name.to_string()) else {
+            warn!("skip multipart field without filename: {:?}", field.name());
+            continue;
+        };
+
+        let file = state.save_file(chat_id, user.id, &filename, field).await?;
+        files.push(file.url());
+    }
+
+    Ok(Json(files))
+}
+
+pub(crate) async fn sign_file_handler(
+    user: SessionUser,
+    State(state): State<AppState>,
+    Path(chat_id): Path<i64>,
+    Json(input): Json<SignFileUrls>,
+) -> Result<impl IntoResponse, AppErr> {
+    let signed = state.sign_file_urls(input, chat_id, user.id).await?;
+    Ok(Json(signed))
+}
+
+pub(crate) async fn file_handler(
+    user: SessionUser,
+    State(state): State<AppState>,
+    Path((chat_id, path)): Path<(i64, String)>,
+    Query(query): Query<FileQuery>,
+) -> Result<Response, AppErr> {
+    let file: ChatFile = format!("/files/{}/{}", chat_id, path).parse()?;
+    state.ensure_file_access(&file, user.id).await?;
+    if let Some(size) = query.size {
+        state.ensure_thumbnail(&file, size).await?;
+    }
+
+    serve_file(&state, &file, query.size).await
+}
+
+/// Download with a signed url, no session token is required
+pub(crate) async fn signed_file_handler(
+    State(state): State<AppState>,
+    Path((chat_id, path)): Path<(i64, String)>,
+    Query(query): Query<SignedFileQuery>,
+) -> Result<Response, AppErr> {
+    let file: ChatFile = format!("/files/{}/{}", chat_id, path).parse()?;
+    state
+        .verify_signed_file(&file, query.size, query.expires, &query.sig)
+        .await?;
+
+    serve_file(&state, &file, query.size).await
+}
+
+async fn serve_file(
+    state: &AppState,
+    file: &ChatFile,
+    size: Option<u32>,
+) -> Result<Response, AppErr> {
+    let (key, mime) = match size {
+        Some(size) => (
+            thumbnail_key(&file.hash, size),
+            mime_guess::mime::IMAGE_JPEG,
+        ),
+        None => (
+            file.key(),
+            mime_guess::from_ext(&file.ext).first_or_octet_stream(),
+        ),
+    };
+
+    match serve_object(state, &key, mime.as_ref()).await? {
+        Some(response) => Ok(response),
+        None => Err(AppErr::NotFoundErr(format!(
+            "file {} not found",
+            file.variant_url(size)
+        ))),
+    }
+}
+
+/// Redirect to the backend or stream the object, `None` if it does not exist
+pub(crate) async fn serve_object(
+    state: &AppState,
+    key: &str,
+    mime: &str,
+) -> Result<Option<Response>, AppErr> {
+    // let the client download from the backend directly when it supports it
+    if let Some(url) = state.storage.presign(key, PRESIGN_EXPIRES_IN).await? {
+        return Ok(Some(Redirect::temporary(&url).into_response()));
+    }
+
+    let Some(object) = state.storage.get(key).await? else {
+        return Ok(None);
+    };
+
+    let headers = [
+        (header::CONTENT_TYPE, mime.to_string()),
+        (header::CONTENT_LENGTH, object.size.to_string()),
+        // the extension was checked against the content, browsers must not guess another type
+        (header::X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+    ];
+
+    Ok(Some(
+        (headers, Body::from_stream(object.stream)).into_response(),
+    ))
+}
@@ -1,9 +1,54 @@
-use axum::response::IntoResponse;
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
 
-pub(crate) async fn send_message_handler() -> impl IntoResponse {
-    "send_message"
+use crate::{
+    model::{CreateMessage, ForwardMessage, ListMessages, SessionUser},
+    AppErr, AppState,
+};
+
+pub(crate) async fn send_message_handler(
+    user: SessionUser,
+    State(state): State<AppState>,
+    Path(chat_id): Path<i64>,
+    Json(input): Json<CreateMessage>,
+) -> Result<impl IntoResponse, AppErr> {
+    let msg = state.create_message(input, chat_id, user.id).await?;
+
+    Ok((StatusCode::CREATED, Json(msg)))
+}
+
+pub(crate) async fn forward_message_handler(
+    user: SessionUser,
+    State(state): State<AppState>,
+    Path(chat_id): Path<i64>,
+    Json(input): Json<ForwardMessage>,
+) -> Result<impl IntoResponse, AppErr> {
+    let msg = state.forward_message(input, chat_id, user.id).await?;
+
+    Ok((StatusCode::CREATED, Json(msg)))
 }
 
-pub(crate) async fn list_message_handler() -> impl IntoResponse {
-    "list_message"
+pub(crate) async fn list_message_handler(
+    user: SessionUser,
+    State(state): State<AppState>,
+    Path(chat_id): Path<i64>,
+    Query(input): Query<ListMessages>,
+) -> Result<impl IntoResponse, AppErr> {
+    let msgs = state.list_messages(input, chat_id, user.id).await?;
+
+    Ok(Json(msgs))
+}
+
+pub(crate) async fn delete_message_handler(
+    user: SessionUser,
+    State(state): State<AppState>,
+    Path((chat_id, msg_id)): Path<(i64, i64)>,
+) -> Result<impl IntoResponse, AppErr> {
+    state.delete_message(msg_id, chat_id, user.id).await?;
+
+    Ok(StatusCode::NO_CONTENT)
 }
@@ -1,12 +1,42 @@
+mod api_token;
 mod auth;
+mod avatar;
+mod bookmark;
 mod chat;
+mod draft;
+mod email;
+mod file;
 mod message;
+mod moderation;
+mod oidc;
+mod passwd;
+mod profile;
+mod review;
+mod session;
+mod two_factor;
+mod upload;
+mod usage;
 
 use axum::response::IntoResponse;
 
+pub(crate) use api_token::*;
 pub(crate) use auth::*;
+pub(crate) use avatar::*;
+pub(crate) use bookmark::*;
 pub(crate) use chat::*;
+pub(crate) use draft::*;
+pub(crate) use email::*;
+pub(crate) use file::*;
 pub(crate) use message::*;
+pub(crate) use moderation::*;
+pub(crate) use oidc::*;
+pub(crate) use passwd::*;
+pub(crate) use profile::*;
+pub(crate) use review::*;
+pub(crate) use session::*;
+pub(crate) use two_factor::*;
+pub(crate) use upload::*;
+pub(crate) use usage::*;
 
 pub(crate) async fn index_handler() -> impl IntoResponse {
     "index"
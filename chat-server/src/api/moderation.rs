@@ -0,0 +1,52 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+
+use crate::{
+    model::{CreateModerationRule, ListModerationRules, SessionUser, UpdateModerationRule},
+    AppErr, AppState,
+};
+
+pub(crate) async fn list_moderation_rule_handler(
+    user: SessionUser,
+    State(state): State<AppState>,
+    Query(input): Query<ListModerationRules>,
+) -> Result<impl IntoResponse, AppErr> {
+    let rules = state.list_moderation_rules(input, user.id).await?;
+
+    Ok(Json(rules))
+}
+
+pub(crate) async fn create_moderation_rule_handler(
+    user: SessionUser,
+    State(state): State<AppState>,
+    Json(input): Json<CreateModerationRule>,
+) -> Result<impl IntoResponse, AppErr> {
+    let rule = state.create_moderation_rule(input, user.id).await?;
+
+    Ok((StatusCode::CREATED, Json(rule)))
+}
+
+pub(crate) async fn update_moderation_rule_handler(
+    user: SessionUser,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    Json(input): Json<UpdateModerationRule>,
+) -> Result<impl IntoResponse, AppErr> {
+    let rule = state.update_moderation_rule(input, id, user.id).await?;
+
+    Ok(Json(rule))
+}
+
+pub(crate) async fn delete_moderation_rule_handler(
+    user: SessionUser,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, AppErr> {
+    state.delete_moderation_rule(id, user.id).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
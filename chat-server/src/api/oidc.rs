@@ -0,0 +1,42 @@
+use std::net::SocketAddr;
+
+use axum::{
+    extract::{ConnectInfo, Path, State},
+    http::HeaderMap,
+    response::IntoResponse,
+    Json,
+};
+
+use crate::{model::OidcCallbackForm, AppErr, AppState};
+
+use super::{client_info, start_session};
+
+pub(crate) async fn list_oidc_provider_handler(
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppErr> {
+    Ok(Json(state.list_oidc_providers()))
+}
+
+/// Url of the provider's login page, the web client redirects to it
+pub(crate) async fn oidc_authorize_handler(
+    State(state): State<AppState>,
+    Path(provider): Path<String>,
+) -> Result<impl IntoResponse, AppErr> {
+    let authorization = state.start_oidc_login(&provider).await?;
+
+    Ok(Json(authorization))
+}
+
+/// Sign in with the code the provider redirected to the web client with
+pub(crate) async fn oidc_callback_handler(
+    State(state): State<AppState>,
+    Path(provider): Path<String>,
+    headers: HeaderMap,
+    addr: Option<ConnectInfo<SocketAddr>>,
+    Json(input): Json<OidcCallbackForm>,
+) -> Result<impl IntoResponse, AppErr> {
+    let client = client_info(&state, input.device_name.clone(), &headers, addr);
+    let user = state.finish_oidc_login(&provider, &input).await?;
+
+    start_session(&state, user, client).await
+}
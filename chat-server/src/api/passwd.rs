@@ -0,0 +1,55 @@
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Extension, Json};
+use serde::Serialize;
+use tracing::warn;
+
+use crate::{
+    model::{ChangePasswdForm, ForgotPasswdForm, ResetPasswdForm, SessionUser},
+    util::AccessToken,
+    AppErr, AppState,
+};
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ChangedPasswd {
+    /// Number of the other sessions which are signed out
+    revoked: u64,
+}
+
+/// Change the password with the current one, the session of the request stays signed in
+pub(crate) async fn change_passwd_handler(
+    user: SessionUser,
+    Extension(access_token): Extension<AccessToken>,
+    State(state): State<AppState>,
+    Json(input): Json<ChangePasswdForm>,
+) -> Result<impl IntoResponse, AppErr> {
+    let revoked = state
+        .change_passwd(user.id, input, access_token.sid.as_deref())
+        .await?;
+
+    Ok(Json(ChangedPasswd { revoked }))
+}
+
+/// Mail a reset link in the background, the response is the same whether the email exists or not
+pub(crate) async fn forgot_passwd_handler(
+    State(state): State<AppState>,
+    Json(input): Json<ForgotPasswdForm>,
+) -> Result<impl IntoResponse, AppErr> {
+    // sending takes a while only for existing emails, do not let the response time tell
+    tokio::spawn(async move {
+        if let Err(e) = state.request_passwd_reset(&input.email).await {
+            warn!("failed to request password reset: {}", e);
+        }
+    });
+
+    Ok(StatusCode::ACCEPTED)
+}
+
+/// Set a new password with the token of the mailed link, no access token is required
+pub(crate) async fn reset_passwd_handler(
+    State(state): State<AppState>,
+    Json(input): Json<ResetPasswdForm>,
+) -> Result<impl IntoResponse, AppErr> {
+    state.reset_passwd(input).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
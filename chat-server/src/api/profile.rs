@@ -0,0 +1,16 @@
+use axum::{extract::State, response::IntoResponse, Json};
+
+use crate::{
+    model::{SessionUser, UpdateProfileForm},
+    AppErr, AppState,
+};
+
+pub(crate) async fn update_profile_handler(
+    user: SessionUser,
+    State(state): State<AppState>,
+    Json(form): Json<UpdateProfileForm>,
+) -> Result<impl IntoResponse, AppErr> {
+    let user = state.update_profile(user.id, form).await?;
+
+    Ok(Json(user))
+}
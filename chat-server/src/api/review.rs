@@ -0,0 +1,55 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+
+use crate::{
+    model::{ListReviewItems, ReportMessage, ResolveReviewItem, SessionUser},
+    AppErr, AppState,
+};
+
+pub(crate) async fn report_message_handler(
+    user: SessionUser,
+    State(state): State<AppState>,
+    Path((chat_id, msg_id)): Path<(i64, i64)>,
+    Json(input): Json<ReportMessage>,
+) -> Result<impl IntoResponse, AppErr> {
+    let item = state
+        .report_message(input, chat_id, msg_id, user.id)
+        .await?;
+
+    Ok((StatusCode::CREATED, Json(item)))
+}
+
+pub(crate) async fn list_review_handler(
+    user: SessionUser,
+    State(state): State<AppState>,
+    Query(input): Query<ListReviewItems>,
+) -> Result<impl IntoResponse, AppErr> {
+    let items = state.list_review_items(input, user.id).await?;
+
+    Ok(Json(items))
+}
+
+pub(crate) async fn resolve_review_handler(
+    user: SessionUser,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    Json(input): Json<ResolveReviewItem>,
+) -> Result<impl IntoResponse, AppErr> {
+    let audit = state.resolve_review_item(input, id, user.id).await?;
+
+    Ok(Json(audit))
+}
+
+pub(crate) async fn list_review_audit_handler(
+    user: SessionUser,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, AppErr> {
+    let audits = state.list_review_audits(id, user.id).await?;
+
+    Ok(Json(audits))
+}
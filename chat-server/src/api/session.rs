@@ -0,0 +1,47 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use serde::Serialize;
+
+use crate::{model::SessionUser, util::AccessToken, AppErr, AppState};
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct RevokedSessions {
+    revoked: u64,
+}
+
+pub(crate) async fn list_session_handler(
+    user: SessionUser,
+    Extension(access_token): Extension<AccessToken>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppErr> {
+    let sessions = state
+        .list_sessions(user.id, access_token.sid.as_deref())
+        .await?;
+    Ok(Json(sessions))
+}
+
+pub(crate) async fn revoke_session_handler(
+    user: SessionUser,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, AppErr> {
+    state.revoke_session(user.id, &id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Sign out all other devices
+pub(crate) async fn revoke_other_sessions_handler(
+    user: SessionUser,
+    Extension(access_token): Extension<AccessToken>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppErr> {
+    let revoked = state
+        .revoke_other_sessions(user.id, access_token.sid.as_deref())
+        .await?;
+    Ok(Json(RevokedSessions { revoked }))
+}
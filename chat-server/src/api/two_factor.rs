@@ -0,0 +1,36 @@
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+
+use crate::{
+    model::{SessionUser, TotpCodeForm},
+    AppErr, AppState,
+};
+
+/// Start enrolling TOTP, 2FA is not on until the enrollment is confirmed
+pub(crate) async fn enroll_totp_handler(
+    user: SessionUser,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppErr> {
+    let enrollment = state.enroll_totp(&user).await?;
+
+    Ok(Json(enrollment))
+}
+
+pub(crate) async fn confirm_totp_handler(
+    user: SessionUser,
+    State(state): State<AppState>,
+    Json(input): Json<TotpCodeForm>,
+) -> Result<impl IntoResponse, AppErr> {
+    let recovery_codes = state.confirm_totp(user.id, &input.code).await?;
+
+    Ok(Json(recovery_codes))
+}
+
+pub(crate) async fn disable_totp_handler(
+    user: SessionUser,
+    State(state): State<AppState>,
+    Json(input): Json<TotpCodeForm>,
+) -> Result<impl IntoResponse, AppErr> {
+    state.disable_totp(user.id, &input.code).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
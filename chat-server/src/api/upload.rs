@@ -0,0 +1,130 @@
+use axum::{
+    body::Body,
+    extract::{Path, State},
+    http::{header, HeaderMap, HeaderName, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+
+use crate::{
+    model::{parse_upload_metadata, CreateUpload, SessionUser, Upload},
+    AppErr, AppState,
+};
+
+const UPLOAD_LENGTH: &str = "upload-length";
+const UPLOAD_OFFSET: &str = "upload-offset";
+const UPLOAD_METADATA: &str = "upload-metadata";
+const UPLOAD_EXPIRES: &str = "upload-expires";
+// reference of the chat file once the upload is complete, not part of tus
+const X_FILE_URL: &str = "x-file-url";
+const OFFSET_OCTET_STREAM: &str = "application/offset+octet-stream";
+
+pub(crate) async fn create_upload_handler(
+    user: SessionUser,
+    State(state): State<AppState>,
+    Path(chat_id): Path<i64>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, AppErr> {
+    let length = parse_header(&headers, UPLOAD_LENGTH)?;
+    let metadata = match headers.get(UPLOAD_METADATA) {
+        Some(value) => parse_upload_metadata(value.to_str().unwrap_or_default())?,
+        None => Default::default(),
+    };
+
+    let input = CreateUpload { length, metadata };
+    let upload = state.create_upload(input, chat_id, user.id).await?;
+
+    let location = format!("/api/chat/{}/tus/{}", chat_id, upload.id);
+    Ok((
+        StatusCode::CREATED,
+        [
+            (header::LOCATION, location),
+            (
+                HeaderName::from_static(UPLOAD_EXPIRES),
+                http_date(upload.expires_at),
+            ),
+        ],
+    ))
+}
+
+pub(crate) async fn head_upload_handler(
+    user: SessionUser,
+    State(state): State<AppState>,
+    Path((chat_id, id)): Path<(i64, String)>,
+) -> Result<impl IntoResponse, AppErr> {
+    let upload = state.get_upload(&id, chat_id, user.id).await?;
+
+    let mut res = upload_response(StatusCode::OK, &upload);
+    let headers = res.headers_mut();
+    headers.insert(UPLOAD_LENGTH, HeaderValue::from(upload.length));
+    headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+
+    Ok(res)
+}
+
+pub(crate) async fn patch_upload_handler(
+    user: SessionUser,
+    State(state): State<AppState>,
+    Path((chat_id, id)): Path<(i64, String)>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<Response, AppErr> {
+    if headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        != Some(OFFSET_OCTET_STREAM)
+    {
+        return Ok((
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            format!("content type must be {}", OFFSET_OCTET_STREAM),
+        )
+            .into_response());
+    }
+    let offset = parse_header(&headers, UPLOAD_OFFSET)?;
+
+    let upload = state
+        .append_upload(&id, chat_id, user.id, offset, body.into_data_stream())
+        .await?;
+
+    Ok(upload_response(StatusCode::NO_CONTENT, &upload))
+}
+
+pub(crate) async fn delete_upload_handler(
+    user: SessionUser,
+    State(state): State<AppState>,
+    Path((chat_id, id)): Path<(i64, String)>,
+) -> Result<impl IntoResponse, AppErr> {
+    state.delete_upload(&id, chat_id, user.id).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+fn upload_response(status: StatusCode, upload: &Upload) -> Response {
+    let mut res = status.into_response();
+    let headers = res.headers_mut();
+    headers.insert(UPLOAD_OFFSET, HeaderValue::from(upload.upload_offset));
+    if let Ok(expires) = HeaderValue::from_str(&http_date(upload.expires_at)) {
+        headers.insert(UPLOAD_EXPIRES, expires);
+    }
+    if let Some(url) = upload
+        .file_url
+        .as_deref()
+        .and_then(|url| HeaderValue::from_str(url).ok())
+    {
+        headers.insert(X_FILE_URL, url);
+    }
+
+    res
+}
+
+fn parse_header(headers: &HeaderMap, name: &str) -> Result<u64, AppErr> {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok())
+        .ok_or_else(|| AppErr::InvalidInputErr(format!("missing or invalid {} header", name)))
+}
+
+fn http_date(time: DateTime<Utc>) -> String {
+    time.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
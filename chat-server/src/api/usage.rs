@@ -0,0 +1,21 @@
+use axum::{extract::State, response::IntoResponse, Json};
+
+use crate::{model::SessionUser, AppErr, AppState};
+
+pub(crate) async fn get_usage_handler(
+    user: SessionUser,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppErr> {
+    let usage = state.storage_usage(user.id).await?;
+
+    Ok(Json(usage))
+}
+
+pub(crate) async fn recompute_usage_handler(
+    user: SessionUser,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppErr> {
+    let recomputed = state.recompute_usage(user.id).await?;
+
+    Ok(Json(recomputed))
+}
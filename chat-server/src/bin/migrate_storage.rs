@@ -0,0 +1,30 @@
+use std::{env, fs::File};
+
+use anyhow::{bail, Result};
+use chat_core::{AppConfig, StorageBackend};
+use chat_server::AppState;
+use tracing::{info, level_filters::LevelFilter};
+use tracing_subscriber::{fmt::Layer, layer::SubscriberExt, util::SubscriberInitExt, Layer as _};
+
+/// Copy attachments and avatars from the configured storage backend to another one, e.g. from local disk to S3.
+/// Usage: migrate_storage <target-backend.yaml> [--delete-source]
+#[tokio::main]
+async fn main() -> Result<()> {
+    let layer = Layer::new().with_filter(LevelFilter::INFO);
+    tracing_subscriber::registry().with(layer).init();
+
+    let args: Vec<String> = env::args().skip(1).collect();
+    let Some(target) = args.first() else {
+        bail!("usage: migrate_storage <target-backend.yaml> [--delete-source]");
+    };
+    let delete_source = args.iter().any(|arg| arg == "--delete-source");
+
+    let target: StorageBackend = serde_yaml::from_reader(File::open(target)?)?;
+    let config = AppConfig::try_load()?;
+    let state = AppState::try_new(config).await?;
+
+    let copied = state.migrate_storage(&target, delete_source).await?;
+    info!("migrated {} objects", copied);
+
+    Ok(())
+}
@@ -6,9 +6,14 @@ use axum::{
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
+use crate::util::FieldErrors;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ErrOutput {
     pub err_msg: String,
+    /// Errors of each invalid field of the form
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fields: Option<FieldErrors>,
 }
 
 #[derive(Debug, Error)]
@@ -25,11 +30,62 @@ pub enum AppErr {
     #[error("password hash error: {0}")]
     PasswdHashErr(#[from] argon2::password_hash::Error),
 
+    #[error("serde json error: {0}")]
+    SerdeJsonErr(#[from] serde_json::Error),
+
     #[error("jwt error: {0}")]
     JwtErr(#[from] jsonwebtoken::errors::Error),
 
     #[error("auth error: {0}")]
     AuthErr(String),
+
+    #[error("not found: {0}")]
+    NotFoundErr(String),
+
+    #[error("permission denied: {0}")]
+    PermissionDeniedErr(String),
+
+    #[error("multipart error: {0}")]
+    MultipartErr(#[from] axum::extract::multipart::MultipartError),
+
+    #[error("payload too large: {0}")]
+    PayloadTooLargeErr(String),
+
+    #[error("conflict: {0}")]
+    ConflictErr(String),
+
+    #[error("storage quota exceeded: {0}")]
+    QuotaExceededErr(String),
+
+    #[error("storage error: {0}")]
+    StorageErr(String),
+
+    #[error("unsupported file type: {0}")]
+    UnsupportedFileTypeErr(String),
+
+    #[error("file quarantined: {0}")]
+    FileQuarantinedErr(String),
+
+    #[error("mail error: {0}")]
+    MailErr(String),
+
+    #[error("too many requests: {0}")]
+    TooManyRequestsErr(String),
+
+    #[error("oidc provider error: {0}")]
+    OidcErr(String),
+
+    #[error("invalid input: {0}")]
+    InvalidInputErr(String),
+
+    #[error("invalid fields: {0}")]
+    ValidationErr(FieldErrors),
+
+    #[error("message rejected by {rule}: {reason}")]
+    ModerationErr { rule: String, reason: String },
+
+    #[error("create message error: {0}")]
+    CreateMessageErr(String),
 }
 
 impl IntoResponse for AppErr {
@@ -39,11 +95,32 @@ impl IntoResponse for AppErr {
             Self::SqlXErr(_) => StatusCode::INTERNAL_SERVER_ERROR,
             Self::AnyhowErr(_) => StatusCode::INTERNAL_SERVER_ERROR,
             Self::PasswdHashErr(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::SerdeJsonErr(_) => StatusCode::INTERNAL_SERVER_ERROR,
             Self::JwtErr(_) => StatusCode::UNAUTHORIZED,
             Self::AuthErr(_) => StatusCode::UNAUTHORIZED,
+            Self::NotFoundErr(_) => StatusCode::NOT_FOUND,
+            Self::PermissionDeniedErr(_) => StatusCode::FORBIDDEN,
+            Self::MultipartErr(_) => StatusCode::BAD_REQUEST,
+            Self::PayloadTooLargeErr(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::ConflictErr(_) => StatusCode::CONFLICT,
+            Self::QuotaExceededErr(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::StorageErr(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::UnsupportedFileTypeErr(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Self::FileQuarantinedErr(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::MailErr(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::TooManyRequestsErr(_) => StatusCode::TOO_MANY_REQUESTS,
+            Self::OidcErr(_) => StatusCode::BAD_GATEWAY,
+            Self::InvalidInputErr(_) => StatusCode::BAD_REQUEST,
+            Self::ValidationErr(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::ModerationErr { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::CreateMessageErr(_) => StatusCode::BAD_REQUEST,
         };
 
-        (status, Json(ErrOutput::new(self.to_string()))).into_response()
+        let mut output = ErrOutput::new(self.to_string());
+        if let Self::ValidationErr(errors) = self {
+            output.fields = Some(errors);
+        }
+        (status, Json(output)).into_response()
     }
 }
 
@@ -51,6 +128,7 @@ impl ErrOutput {
     pub fn new(error: impl Into<String>) -> Self {
         Self {
             err_msg: error.into(),
+            fields: None,
         }
     }
 }
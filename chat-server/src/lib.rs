@@ -1,24 +1,41 @@
 mod api;
 mod error;
+mod mailer;
+mod media;
 mod middleware;
 mod model;
+mod moderation;
+mod notif;
+mod oidc;
+mod scanner;
+mod storage;
+mod task;
 mod util;
 
 use anyhow::Context;
 use api::*;
 use axum::{
+    extract::DefaultBodyLimit,
     http::Method,
-    routing::{get, patch, post},
+    middleware::from_fn_with_state,
+    routing::{delete, get, head, patch, post, put},
     Router,
 };
 use chat_core::AppConfig;
-use middleware::set_layer;
+use mailer::{new_mailer, Mailer};
+use media::{check_avatar_sizes, ImagePool};
+use middleware::{require_role, set_layer, tus_resumable, verify_token, RequireScope};
+use model::{Role, Scope};
+use oidc::{new_oidc_providers, OidcProvider};
+use scanner::{new_scanner, Scanner};
 use sqlx::PgPool;
-use std::{fmt::Debug, ops::Deref, sync::Arc};
+use std::{collections::HashMap, fmt::Debug, ops::Deref, sync::Arc};
+use storage::{new_storage, Storage};
 use tower_http::cors::{Any, CorsLayer};
-use util::{JwtDecodingKey, JwtEncodingKey};
+use util::{JwtEncodingKey, JwtKeyRing, PasswdHasher, TokenDenylist, UrlSigner};
 
 pub use error::AppErr;
+pub use task::spawn_tasks;
 
 #[derive(Debug, Clone)]
 pub struct AppState {
@@ -29,7 +46,15 @@ pub struct AppStateInner {
     pub(crate) config: AppConfig,
     pub(crate) pg: PgPool,
     pub(crate) ek: JwtEncodingKey,
-    pub(crate) dk: JwtDecodingKey,
+    pub(crate) dk: JwtKeyRing,
+    pub(crate) passwd_hasher: PasswdHasher,
+    pub(crate) storage: Arc<dyn Storage>,
+    pub(crate) image_pool: ImagePool,
+    pub(crate) url_signer: UrlSigner,
+    pub(crate) scanner: Option<Arc<dyn Scanner>>,
+    pub(crate) denylist: TokenDenylist,
+    pub(crate) mailer: Arc<dyn Mailer>,
+    pub(crate) oidc: HashMap<String, OidcProvider>,
 }
 
 /// Deref to AppStateInner
@@ -43,30 +68,177 @@ impl Deref for AppState {
 
 pub async fn init_app(state: AppState) -> Result<Router, AppErr> {
     let cors = CorsLayer::new()
-        .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE])
+        .allow_methods([
+            Method::GET,
+            Method::POST,
+            Method::PUT,
+            Method::PATCH,
+            Method::HEAD,
+            Method::DELETE,
+        ])
         .allow_origin(Any)
-        .allow_headers(Any);
+        .allow_headers(Any)
+        .expose_headers(Any);
 
-    let api = Router::new()
-        .route("/signup", post(sign_up_handler))
-        .route("/signin", post(sign_in_handler))
-        .route("/chat", get(list_chat_handler).post(create_chat_handler))
+    // resumable uploads, tus_resumable wraps cors to describe the server in OPTIONS responses
+    let tus = Router::new()
+        .route("/chat/:id/tus", post(create_upload_handler))
+        .route(
+            "/chat/:id/tus/:upload_id",
+            head(head_upload_handler)
+                .patch(patch_upload_handler)
+                .delete(delete_upload_handler)
+                .layer(DefaultBodyLimit::disable()),
+        )
+        .route_layer(RequireScope(Scope::WriteFiles))
+        .route_layer(from_fn_with_state(state.clone(), verify_token))
+        .layer(cors.clone())
+        .layer(from_fn_with_state(state.clone(), tus_resumable));
+
+    let admin = Router::new()
+        .route("/admin/usage/recompute", post(recompute_usage_handler))
+        .route(
+            "/admin/moderation/rules",
+            get(list_moderation_rule_handler).post(create_moderation_rule_handler),
+        )
+        .route(
+            "/admin/moderation/rules/:id",
+            patch(update_moderation_rule_handler).delete(delete_moderation_rule_handler),
+        )
+        .route_layer(from_fn_with_state(
+            (state.clone(), Role::Admin),
+            require_role,
+        ));
+
+    let moderation = Router::new()
+        .route("/review", get(list_review_handler))
+        .route("/review/:id/resolve", post(resolve_review_handler))
+        .route("/review/:id/audit", get(list_review_audit_handler))
+        .route_layer(from_fn_with_state(
+            (state.clone(), Role::Moderator),
+            require_role,
+        ));
+
+    // routes of signed in users, and of api tokens where the route has their scope
+    let authed = Router::new()
+        .route("/logout", post(logout_handler))
+        .route("/password", put(change_passwd_handler))
+        .route("/profile", patch(update_profile_handler))
+        .route(
+            "/2fa/totp",
+            post(enroll_totp_handler).delete(disable_totp_handler),
+        )
+        .route("/2fa/totp/confirm", post(confirm_totp_handler))
+        .route("/email/verify/resend", post(resend_verification_handler))
+        .route(
+            "/tokens",
+            get(list_api_token_handler).post(create_api_token_handler),
+        )
+        .route("/tokens/:id", delete(revoke_api_token_handler))
+        .route(
+            "/avatar",
+            post(upload_avatar_handler)
+                .delete(delete_avatar_handler)
+                .layer(DefaultBodyLimit::disable()),
+        )
+        .route(
+            "/bookmark",
+            get(list_bookmark_handler).post(create_bookmark_handler),
+        )
+        .route(
+            "/bookmark/:id",
+            patch(update_bookmark_handler).delete(delete_bookmark_handler),
+        )
+        .route(
+            "/chat",
+            get(list_chat_handler).route_layer(RequireScope(Scope::ReadChats)),
+        )
+        .route(
+            "/chat",
+            post(create_chat_handler).route_layer(RequireScope(Scope::WriteChats)),
+        )
         .route(
             "/chat/:id",
             patch(update_chat_handler)
                 .delete(delete_chat_handler)
-                .post(send_message_handler),
+                .route_layer(RequireScope(Scope::WriteChats)),
+        )
+        .route(
+            "/chat/:id",
+            post(send_message_handler).route_layer(RequireScope(Scope::WriteMessages)),
+        )
+        .route(
+            "/chat/:id/draft",
+            get(get_draft_handler)
+                .put(save_draft_handler)
+                .delete(clear_draft_handler),
+        )
+        .route(
+            "/chat/:id/upload",
+            post(upload_handler)
+                .layer(DefaultBodyLimit::disable())
+                .route_layer(RequireScope(Scope::WriteFiles)),
+        )
+        .route(
+            "/chat/:id/file-url",
+            post(sign_file_handler).route_layer(RequireScope(Scope::ReadFiles)),
+        )
+        .route(
+            "/chat/:id/forward",
+            post(forward_message_handler).route_layer(RequireScope(Scope::WriteMessages)),
+        )
+        .route(
+            "/chat/:id/message",
+            get(list_message_handler).route_layer(RequireScope(Scope::ReadMessages)),
         )
-        .route("/chat/:id/message", get(list_message_handler))
-        .layer(cors);
+        .route(
+            "/chat/:id/message/:msg_id",
+            delete(delete_message_handler).route_layer(RequireScope(Scope::WriteMessages)),
+        )
+        .route(
+            "/chat/:id/message/:msg_id/report",
+            post(report_message_handler),
+        )
+        .route(
+            "/sessions",
+            get(list_session_handler).delete(revoke_other_sessions_handler),
+        )
+        .route("/sessions/:id", delete(revoke_session_handler))
+        .route("/usage", get(get_usage_handler))
+        .merge(admin)
+        .merge(moderation)
+        .route_layer(from_fn_with_state(state.clone(), verify_token));
+
+    let public = Router::new()
+        .route("/signup", post(sign_up_handler))
+        .route("/signin", post(sign_in_handler))
+        .route("/signin/2fa", post(two_factor_handler))
+        .route("/refresh", post(refresh_handler))
+        .route("/email/verify", post(verify_email_handler))
+        .route("/password/forgot", post(forgot_passwd_handler))
+        .route("/password/reset", post(reset_passwd_handler))
+        .route("/oidc", get(list_oidc_provider_handler))
+        .route("/oidc/:provider/authorize", get(oidc_authorize_handler))
+        .route("/oidc/:provider/callback", post(oidc_callback_handler));
+
+    let api = public.merge(authed).layer(cors).merge(tus);
 
-    let state_cloned = state.clone();
     let app = Router::new()
         .route("/", get(index_handler))
+        .route(
+            "/files/:chat_id/*path",
+            get(file_handler)
+                .route_layer(RequireScope(Scope::ReadFiles))
+                .route_layer(from_fn_with_state(state.clone(), verify_token)),
+        )
         .nest("/api", api)
+        .route("/.well-known/jwks.json", get(jwks_handler))
+        .route("/download/:chat_id/*path", get(signed_file_handler))
+        .route("/avatars/:name", get(avatar_handler))
+        .route("/identicons/:name", get(identicon_handler))
         .with_state(state);
 
-    Ok(set_layer(state_cloned, app))
+    Ok(set_layer(app))
 }
 
 impl AppState {
@@ -75,11 +247,33 @@ impl AppState {
             .await
             .context("connectg to db fail")?;
 
-        let ek = JwtEncodingKey::load(&config.auth.private_key)?;
-        let dk = JwtDecodingKey::load(&config.auth.public_key)?;
+        let dk = JwtKeyRing::load(&config.auth)?;
+        let ek = JwtEncodingKey::load(&config.auth.private_key, dk.active_kid())?;
+        let passwd_hasher = PasswdHasher::new(&config.auth.argon2)?;
+        let storage = new_storage(&config.storage.backend)?;
+        check_avatar_sizes(&config.storage.avatar.sizes)?;
+        let thumbnail = &config.storage.thumbnail;
+        let image_pool = ImagePool::new(thumbnail.workers, &thumbnail.sizes);
+        let url_signer = UrlSigner::new(&config.storage.signing_key);
+        let scanner = new_scanner(config.storage.scanner.as_ref());
+        let mailer = new_mailer(&config.mail)?;
+        let oidc = new_oidc_providers(&config.auth.oidc);
 
         Ok(Self {
-            inner: Arc::new(AppStateInner { config, pg, ek, dk }),
+            inner: Arc::new(AppStateInner {
+                config,
+                pg,
+                ek,
+                dk,
+                passwd_hasher,
+                storage,
+                image_pool,
+                url_signer,
+                scanner,
+                denylist: TokenDenylist::default(),
+                mailer,
+                oidc,
+            }),
         })
     }
 }
@@ -102,8 +296,18 @@ mod test_util {
         pub async fn new_for_test() -> Result<(TestPg, Self), AppErr> {
             let config = AppConfig::try_load()?;
 
-            let ek = JwtEncodingKey::load(&config.auth.private_key)?;
-            let dk = JwtDecodingKey::load(&config.auth.public_key)?;
+            let dk = JwtKeyRing::load(&config.auth)?;
+            let ek = JwtEncodingKey::load(&config.auth.private_key, dk.active_kid())?;
+            let passwd_hasher = PasswdHasher::new(&config.auth.argon2)?;
+            let storage = new_storage(&config.storage.backend)?;
+            check_avatar_sizes(&config.storage.avatar.sizes)?;
+            let thumbnail = &config.storage.thumbnail;
+            let image_pool = ImagePool::new(thumbnail.workers, &thumbnail.sizes);
+            let url_signer = UrlSigner::new(&config.storage.signing_key);
+            let scanner = new_scanner(config.storage.scanner.as_ref());
+            // mails are kept in memory instead of being sent
+            let mailer = Arc::new(mailer::MemoryMailer::default());
+            let oidc = new_oidc_providers(&config.auth.oidc);
 
             let dsn_post = config.db.dsn.rfind('/').expect("invalid db dsn");
             let db_server_url = &config.db.dsn[..dsn_post];
@@ -111,7 +315,20 @@ mod test_util {
             let (test_db, pg) = init_test_pool(Some(db_server_url)).await;
 
             let state = Self {
-                inner: Arc::new(AppStateInner { config, pg, ek, dk }),
+                inner: Arc::new(AppStateInner {
+                    config,
+                    pg,
+                    ek,
+                    dk,
+                    passwd_hasher,
+                    storage,
+                    image_pool,
+                    url_signer,
+                    scanner,
+                    denylist: TokenDenylist::default(),
+                    mailer,
+                    oidc,
+                }),
             };
 
             Ok((test_db, state))
@@ -142,3 +359,119 @@ mod test_util {
         (test_db, pg_pool)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{CreateApiToken, SessionUser};
+    use anyhow::Result;
+    use axum::{
+        body::{to_bytes, Body},
+        http::{header, Request, StatusCode},
+    };
+    use serde_json::{json, Value};
+    use tower::ServiceExt;
+
+    async fn send(
+        app: &Router,
+        method: Method,
+        uri: &str,
+        token: Option<&str>,
+        body: Option<Value>,
+    ) -> Result<(StatusCode, Value)> {
+        let mut req = Request::builder().method(method).uri(uri);
+        if let Some(token) = token {
+            req = req.header(header::AUTHORIZATION, format!("Bearer {}", token));
+        }
+        let req = match body {
+            Some(body) => req
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string()))?,
+            None => req.body(Body::empty())?,
+        };
+
+        let res = app.clone().oneshot(req).await?;
+        let status = res.status();
+        let bytes = to_bytes(res.into_body(), usize::MAX).await?;
+        Ok((
+            status,
+            serde_json::from_slice(&bytes).unwrap_or(Value::Null),
+        ))
+    }
+
+    #[tokio::test]
+    async fn test_signup_route() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let app = init_app(state).await?;
+
+        let (status, _) = send(&app, Method::GET, "/api/usage", None, None).await?;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let form = json!({ "username": "erin", "email": "erin@acme.com", "passwd": "erin12345" });
+        let (status, body) = send(&app, Method::POST, "/api/signup", None, Some(form)).await?;
+        assert_eq!(status, StatusCode::CREATED);
+        let token = body["token"].as_str().expect("token of the new session");
+
+        // the email is not verified yet, only a few routes are open
+        let (status, _) = send(&app, Method::GET, "/api/usage", Some(token), None).await?;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, _) = send(&app, Method::GET, "/api/sessions", Some(token), None).await?;
+        assert_eq!(status, StatusCode::OK);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_role_gated_route() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let sign = |id, name: &str| {
+            let user = SessionUser::new(id, name.to_string(), format!("{}@acme.com", name), true);
+            state.ek.sign(user, "test-session")
+        };
+        let member = sign(1, "alice")?;
+        let moderator = sign(3, "carol")?;
+        let app = init_app(state.clone()).await?;
+
+        let (status, _) = send(&app, Method::GET, "/api/review", Some(&member), None).await?;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let uri = "/api/admin/moderation/rules";
+        let (status, _) = send(&app, Method::GET, uri, Some(&member), None).await?;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, _) = send(&app, Method::GET, "/api/review", Some(&moderator), None).await?;
+        assert_eq!(status, StatusCode::OK);
+        // the moderator is not an admin
+        let uri = "/api/admin/usage/recompute";
+        let (status, _) = send(&app, Method::POST, uri, Some(&moderator), None).await?;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_api_token_scope_routes() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreateApiToken {
+            name: "bot".to_string(),
+            scopes: vec![Scope::ReadMessages],
+            expires_in_days: None,
+            user_id: None,
+        };
+        let token = state.create_api_token(input, 1).await?.token;
+        let app = init_app(state).await?;
+
+        let uri = "/api/chat/1/message";
+        let (status, _) = send(&app, Method::GET, uri, Some(&token), None).await?;
+        assert_eq!(status, StatusCode::OK);
+        // routes without a scope are only open to sessions
+        for (method, uri) in [
+            (Method::GET, "/api/usage"),
+            (Method::GET, "/api/tokens"),
+            (Method::GET, "/api/chat"),
+        ] {
+            let (status, _) = send(&app, method, uri, Some(&token), None).await?;
+            assert_eq!(status, StatusCode::FORBIDDEN, "{}", uri);
+        }
+
+        Ok(())
+    }
+}
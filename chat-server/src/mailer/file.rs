@@ -0,0 +1,67 @@
+use std::path::PathBuf;
+
+use async_trait::async_trait;
+use lettre::message::Mailbox;
+use tokio::fs;
+use tracing::info;
+
+use crate::AppErr;
+
+use super::{build_message, Mail, Mailer};
+
+/// Write every mail to an .eml file of the directory instead of sending it
+pub struct FileMailer {
+    from: Mailbox,
+    dir: PathBuf,
+}
+
+impl FileMailer {
+    pub fn new(from: Mailbox, dir: PathBuf) -> Self {
+        Self { from, dir }
+    }
+}
+
+#[async_trait]
+impl Mailer for FileMailer {
+    async fn send(&self, mail: &Mail) -> Result<(), AppErr> {
+        let message = build_message(&self.from, mail)?;
+
+        fs::create_dir_all(&self.dir).await?;
+        let path = self.dir.join(format!("{}.eml", uuid::Uuid::now_v7()));
+        fs::write(&path, message.formatted()).await?;
+        info!("mail to {} is written to {}", mail.to, path.display());
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_file_mailer() {
+        let dir = std::env::temp_dir().join(format!("mail-{}", uuid::Uuid::now_v7()));
+        let mailer = FileMailer::new("easy-chat <noreply@acme.com>".parse().unwrap(), dir.clone());
+        let mail = Mail {
+            to: "foo@acme.com".to_string(),
+            subject: "hello".to_string(),
+            body: "world".to_string(),
+        };
+        mailer.send(&mail).await.unwrap();
+
+        let mut entries = std::fs::read_dir(&dir).unwrap();
+        let eml = std::fs::read_to_string(entries.next().unwrap().unwrap().path()).unwrap();
+        assert!(eml.contains("From: easy-chat <noreply@acme.com>"));
+        assert!(eml.contains("To: foo@acme.com"));
+        assert!(eml.contains("Subject: hello"));
+        assert!(eml.ends_with("world"));
+
+        let invalid = Mail {
+            to: "not an address".to_string(),
+            ..mail
+        };
+        assert!(mailer.send(&invalid).await.is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,48 @@
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+
+use crate::AppErr;
+
+use super::{Mail, Mailer};
+
+/// Keep the mails in memory, so that tests can read the links sent to users
+#[derive(Default)]
+pub struct MemoryMailer {
+    sent: Mutex<Vec<Mail>>,
+}
+
+impl MemoryMailer {
+    // only read by tests, the test-util build of the library just sends
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn sent(&self) -> Vec<Mail> {
+        self.sent.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+}
+
+#[async_trait]
+impl Mailer for MemoryMailer {
+    async fn send(&self, mail: &Mail) -> Result<(), AppErr> {
+        let mut sent = self.sent.lock().unwrap_or_else(|e| e.into_inner());
+        sent.push(mail.clone());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_memory_mailer() {
+        let mailer = MemoryMailer::default();
+        let mail = Mail {
+            to: "foo@acme.com".to_string(),
+            subject: "hello".to_string(),
+            body: "world".to_string(),
+        };
+        mailer.send(&mail).await.unwrap();
+
+        assert_eq!(mailer.sent(), vec![mail]);
+    }
+}
@@ -0,0 +1,59 @@
+mod file;
+#[cfg(any(test, feature = "test-util"))]
+mod memory;
+mod smtp;
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use chat_core::{MailConfig, MailTransport};
+use lettre::{
+    message::{header::ContentType, Mailbox},
+    Message,
+};
+
+use crate::AppErr;
+
+pub(crate) use file::FileMailer;
+#[cfg(any(test, feature = "test-util"))]
+pub(crate) use memory::MemoryMailer;
+pub(crate) use smtp::SmtpMailer;
+
+/// Plain text mail to a single recipient
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// Delivery of transactional mails, e.g. email verification links
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, mail: &Mail) -> Result<(), AppErr>;
+}
+
+pub fn new_mailer(config: &MailConfig) -> Result<Arc<dyn Mailer>, AppErr> {
+    let from = parse_mailbox(&config.from)?;
+    let mailer: Arc<dyn Mailer> = match &config.transport {
+        MailTransport::Smtp(smtp) => Arc::new(SmtpMailer::new(from, smtp)?),
+        MailTransport::File { dir } => Arc::new(FileMailer::new(from, dir.clone())),
+    };
+
+    Ok(mailer)
+}
+
+fn parse_mailbox(addr: &str) -> Result<Mailbox, AppErr> {
+    addr.parse()
+        .map_err(|e| AppErr::MailErr(format!("invalid address {}: {}", addr, e)))
+}
+
+fn build_message(from: &Mailbox, mail: &Mail) -> Result<Message, AppErr> {
+    Message::builder()
+        .from(from.clone())
+        .to(parse_mailbox(&mail.to)?)
+        .subject(&mail.subject)
+        .header(ContentType::TEXT_PLAIN)
+        .body(mail.body.clone())
+        .map_err(|e| AppErr::MailErr(e.to_string()))
+}
@@ -0,0 +1,53 @@
+use async_trait::async_trait;
+use chat_core::{SmtpConfig, SmtpTls};
+use lettre::{
+    message::Mailbox, transport::smtp::authentication::Credentials, AsyncSmtpTransport,
+    AsyncTransport, Tokio1Executor,
+};
+
+use crate::AppErr;
+
+use super::{build_message, Mail, Mailer};
+
+pub struct SmtpMailer {
+    from: Mailbox,
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+}
+
+impl SmtpMailer {
+    pub fn new(from: Mailbox, config: &SmtpConfig) -> Result<Self, AppErr> {
+        let smtp_err = |e: lettre::transport::smtp::Error| AppErr::MailErr(e.to_string());
+        let mut builder = match config.tls {
+            SmtpTls::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&config.host),
+            SmtpTls::Starttls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.host)
+                .map_err(smtp_err)?,
+            SmtpTls::Wrapper => {
+                AsyncSmtpTransport::<Tokio1Executor>::relay(&config.host).map_err(smtp_err)?
+            }
+        };
+        if let Some(port) = config.port {
+            builder = builder.port(port);
+        }
+        if let (Some(username), Some(password)) = (&config.username, &config.password) {
+            builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+        }
+
+        Ok(Self {
+            from,
+            transport: builder.build(),
+        })
+    }
+}
+
+#[async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, mail: &Mail) -> Result<(), AppErr> {
+        let message = build_message(&self.from, mail)?;
+        self.transport
+            .send(message)
+            .await
+            .map_err(|e| AppErr::MailErr(e.to_string()))?;
+
+        Ok(())
+    }
+}
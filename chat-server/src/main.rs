@@ -1,6 +1,8 @@
+use std::net::SocketAddr;
+
 use anyhow::Result;
 use chat_core::AppConfig;
-use chat_server::{init_app, AppState};
+use chat_server::{init_app, spawn_tasks, AppState};
 use tokio::net::TcpListener;
 use tracing::level_filters::LevelFilter;
 use tracing_subscriber::{fmt::Layer, layer::SubscriberExt, util::SubscriberInitExt, Layer as _};
@@ -14,10 +16,16 @@ async fn main() -> Result<()> {
     let addr = format!("0.0.0.0:{}", config.server.port);
 
     let app_state = AppState::try_new(config).await?;
+    spawn_tasks(&app_state);
 
     let app = init_app(app_state).await?;
     let listener = TcpListener::bind(addr).await?;
 
-    axum::serve(listener, app.into_make_service()).await?;
+    // client addresses are recorded with sessions
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await?;
     Ok(())
 }
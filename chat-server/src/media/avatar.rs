@@ -0,0 +1,140 @@
+use std::io::Cursor;
+
+use image::{imageops::FilterType, DynamicImage, GenericImageView, ImageFormat, RgbImage};
+use sha2::{Digest, Sha256};
+
+use crate::AppErr;
+
+use super::encode_jpeg;
+
+const IDENTICON_GRID: u32 = 5;
+const IDENTICON_BACKGROUND: [u8; 3] = [240, 240, 240];
+
+/// Avatars are rendered at the configured sizes, each large enough for an identicon
+pub(crate) fn check_avatar_sizes(sizes: &[u32]) -> Result<(), AppErr> {
+    if sizes.is_empty() || sizes.iter().any(|&size| size < IDENTICON_GRID) {
+        return Err(AppErr::AnyhowErr(anyhow::anyhow!(
+            "avatar sizes must be given and at least {}, got {:?}",
+            IDENTICON_GRID,
+            sizes
+        )));
+    }
+
+    Ok(())
+}
+
+/// Crop the image to a centered square and render it at each size as JPEG
+pub(super) fn render_avatar(
+    img: &DynamicImage,
+    sizes: &[u32],
+) -> Result<Vec<(u32, Vec<u8>)>, AppErr> {
+    let (width, height) = img.dimensions();
+    let edge = width.min(height);
+    let square = img.crop_imm((width - edge) / 2, (height - edge) / 2, edge, edge);
+
+    sizes
+        .iter()
+        .filter(|&&size| size > 0)
+        .map(|&size| {
+            let resized = square.resize_exact(size, size, FilterType::Lanczos3);
+            Ok((size, encode_jpeg(&resized)?))
+        })
+        .collect()
+}
+
+/// PNG identicon derived from the seed: a symmetric 5x5 pattern in a color picked by its hash
+pub fn identicon(seed: &str, size: u32) -> Result<Vec<u8>, AppErr> {
+    let hash = Sha256::digest(seed.as_bytes());
+    let color = hsl_to_rgb(
+        u16::from_be_bytes([hash[0], hash[1]]) as f32 / u16::MAX as f32 * 360.0,
+        0.5,
+        0.55,
+    );
+    // left half and middle column, the right half mirrors the left
+    let half = IDENTICON_GRID.div_ceil(2);
+    let filled = |col: u32, row: u32| {
+        let col = col.min(IDENTICON_GRID - 1 - col);
+        hash[(2 + row * half + col) as usize] & 1 == 1
+    };
+
+    let padding = size / 10;
+    let cell = ((size - 2 * padding) / IDENTICON_GRID).max(1);
+    let offset = size.saturating_sub(cell * IDENTICON_GRID) / 2;
+    let img = RgbImage::from_fn(size, size, |x, y| {
+        let (x, y) = (x.wrapping_sub(offset), y.wrapping_sub(offset));
+        let inside = x < cell * IDENTICON_GRID && y < cell * IDENTICON_GRID;
+        if inside && filled(x / cell, y / cell) {
+            image::Rgb(color)
+        } else {
+            image::Rgb(IDENTICON_BACKGROUND)
+        }
+    });
+
+    let mut buf = Cursor::new(Vec::new());
+    img.write_to(&mut buf, ImageFormat::Png)
+        .map_err(|e| AppErr::AnyhowErr(e.into()))?;
+    Ok(buf.into_inner())
+}
+
+fn hsl_to_rgb(h: f32, s: f32, l: f32) -> [u8; 3] {
+    let c = (1.0 - (2.0 * l - 1.0).abs()) * s;
+    let x = c * (1.0 - ((h / 60.0) % 2.0 - 1.0).abs());
+    let (r, g, b) = match (h / 60.0) as u32 {
+        0 => (c, x, 0.0),
+        1 => (x, c, 0.0),
+        2 => (0.0, c, x),
+        3 => (0.0, x, c),
+        4 => (x, 0.0, c),
+        _ => (c, 0.0, x),
+    };
+    let m = l - c / 2.0;
+    [r, g, b].map(|v| ((v + m) * 255.0).round() as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_avatar() {
+        let img = DynamicImage::ImageRgb8(RgbImage::from_fn(300, 200, |x, _| {
+            image::Rgb([(x % 256) as u8, 0, 0])
+        }));
+
+        let rendered = render_avatar(&img, &[64, 128]).unwrap();
+        assert_eq!(rendered.len(), 2);
+        let (size, data) = &rendered[1];
+        assert_eq!(*size, 128);
+        assert_eq!(
+            image::load_from_memory(data).unwrap().dimensions(),
+            (128, 128)
+        );
+    }
+
+    #[test]
+    fn test_identicon() {
+        let a = identicon("1", 64).unwrap();
+        assert_eq!(a, identicon("1", 64).unwrap());
+        assert_ne!(a, identicon("2", 64).unwrap());
+
+        let img = image::load_from_memory(&a).unwrap().to_rgb8();
+        assert_eq!(img.dimensions(), (64, 64));
+        // mirrored around the vertical axis
+        for y in 0..64 {
+            for x in 0..32 {
+                assert_eq!(img.get_pixel(x, y), img.get_pixel(63 - x, y));
+            }
+        }
+
+        // smaller than the grid, cropped but not failing
+        let img = image::load_from_memory(&identicon("1", 3).unwrap()).unwrap();
+        assert_eq!(img.dimensions(), (3, 3));
+    }
+
+    #[test]
+    fn test_check_avatar_sizes() {
+        assert!(check_avatar_sizes(&[64, 128]).is_ok());
+        assert!(check_avatar_sizes(&[]).is_err());
+        assert!(check_avatar_sizes(&[64, 0]).is_err());
+    }
+}
@@ -0,0 +1,205 @@
+use std::{io::Cursor, path::Path, sync::Arc};
+
+use image::{
+    codecs::jpeg::JpegEncoder, DynamicImage, GenericImageView, ImageError, ImageReader, Limits,
+    RgbImage,
+};
+use tokio::sync::Semaphore;
+
+use crate::AppErr;
+
+mod avatar;
+mod sniff;
+mod strip;
+
+pub(crate) use avatar::{check_avatar_sizes, identicon};
+pub(crate) use sniff::*;
+pub(crate) use strip::*;
+
+// images larger than this are not decoded, to avoid decompression bombs
+const MAX_IMAGE_DIMENSION: u32 = 16384;
+const MAX_IMAGE_ALLOC: u64 = 512 * 1024 * 1024;
+const THUMBNAIL_QUALITY: u8 = 80;
+const BLURHASH_SOURCE_SIZE: u32 = 64;
+const BLURHASH_COMPONENTS: (u32, u32) = (4, 3);
+
+/// Metadata and thumbnails of an uploaded image
+#[derive(Debug)]
+pub struct ProcessedImage {
+    pub width: u32,
+    pub height: u32,
+    pub blurhash: String,
+    /// JPEG encoded thumbnails by their longest edge
+    pub thumbnails: Vec<(u32, Vec<u8>)>,
+}
+
+/// Runs image processing on the blocking thread pool,
+/// at most `workers` images at a time so that uploads cannot starve other work
+#[derive(Debug, Clone)]
+pub struct ImagePool {
+    permits: Arc<Semaphore>,
+    sizes: Arc<[u32]>,
+}
+
+impl ImagePool {
+    pub fn new(workers: usize, sizes: &[u32]) -> Self {
+        Self {
+            permits: Arc::new(Semaphore::new(workers.max(1))),
+            sizes: sizes.into(),
+        }
+    }
+
+    /// Process the image file, return `None` if it is not a supported image
+    pub async fn process(&self, path: &Path) -> Result<Option<ProcessedImage>, AppErr> {
+        let path = path.to_path_buf();
+        let sizes = self.sizes.clone();
+        self.run(move || process_image(&path, &sizes)).await
+    }
+
+    /// Render a square avatar of each size as JPEG, fail if the file is not a supported image
+    pub async fn render_avatar(
+        &self,
+        path: &Path,
+        sizes: &[u32],
+    ) -> Result<Vec<(u32, Vec<u8>)>, AppErr> {
+        let path = path.to_path_buf();
+        let sizes = sizes.to_vec();
+        self.run(move || {
+            let img = decode(&path)?.ok_or_else(|| {
+                AppErr::UnsupportedFileTypeErr("avatar is not a supported image".to_string())
+            })?;
+            avatar::render_avatar(&img, &sizes)
+        })
+        .await
+    }
+
+    async fn run<T, F>(&self, task: F) -> Result<T, AppErr>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> Result<T, AppErr> + Send + 'static,
+    {
+        let _permit = self
+            .permits
+            .acquire()
+            .await
+            .map_err(|e| AppErr::AnyhowErr(e.into()))?;
+
+        tokio::task::spawn_blocking(task)
+            .await
+            .map_err(|e| AppErr::AnyhowErr(e.into()))?
+    }
+}
+
+/// Decode an image within the limits, `None` if it is not in a format we can read
+fn decode(path: &Path) -> Result<Option<DynamicImage>, AppErr> {
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_IMAGE_DIMENSION);
+    limits.max_image_height = Some(MAX_IMAGE_DIMENSION);
+    limits.max_alloc = Some(MAX_IMAGE_ALLOC);
+
+    let mut reader = ImageReader::open(path)?.with_guessed_format()?;
+    reader.limits(limits);
+    match reader.decode() {
+        Ok(img) => Ok(Some(img)),
+        Err(ImageError::IoError(e)) => Err(e.into()),
+        Err(_) => Ok(None),
+    }
+}
+
+fn process_image(path: &Path, sizes: &[u32]) -> Result<Option<ProcessedImage>, AppErr> {
+    // not an image or in a format we cannot read, keep it as a plain file
+    let Some(img) = decode(path)? else {
+        return Ok(None);
+    };
+
+    let (width, height) = img.dimensions();
+    let mut thumbnails = Vec::with_capacity(sizes.len());
+    for &size in sizes {
+        // never upscale
+        if size == 0 || size >= width.max(height) {
+            continue;
+        }
+
+        let thumb = img.thumbnail(size, size);
+        thumbnails.push((size, encode_jpeg(&thumb)?));
+    }
+
+    Ok(Some(ProcessedImage {
+        width,
+        height,
+        blurhash: blurhash(&img)?,
+        thumbnails,
+    }))
+}
+
+fn encode_jpeg(img: &DynamicImage) -> Result<Vec<u8>, AppErr> {
+    let rgb = flatten(img);
+    let mut buf = Cursor::new(Vec::new());
+    JpegEncoder::new_with_quality(&mut buf, THUMBNAIL_QUALITY)
+        .encode_image(&rgb)
+        .map_err(|e| AppErr::AnyhowErr(e.into()))?;
+
+    Ok(buf.into_inner())
+}
+
+/// Blend transparent pixels onto a white background, JPEG has no alpha channel
+fn flatten(img: &DynamicImage) -> RgbImage {
+    if !img.color().has_alpha() {
+        return img.to_rgb8();
+    }
+
+    let rgba = img.to_rgba8();
+    RgbImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+        let [r, g, b, a] = rgba.get_pixel(x, y).0;
+        let blend = |c: u8| ((c as u32 * a as u32 + 255 * (255 - a as u32)) / 255) as u8;
+        image::Rgb([blend(r), blend(g), blend(b)])
+    })
+}
+
+fn blurhash(img: &DynamicImage) -> Result<String, AppErr> {
+    // the hash only keeps a few components, a tiny image gives the same result much faster
+    let small = img
+        .thumbnail(BLURHASH_SOURCE_SIZE, BLURHASH_SOURCE_SIZE)
+        .to_rgba8();
+    let (x, y) = BLURHASH_COMPONENTS;
+
+    blurhash::encode(x, y, small.width(), small.height(), small.as_raw())
+        .map_err(|e| AppErr::AnyhowErr(anyhow::anyhow!("blurhash: {:?}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_process_image() {
+        let path = std::env::temp_dir().join(format!("{}.png", uuid::Uuid::now_v7()));
+        let img = RgbImage::from_fn(400, 200, |x, _| image::Rgb([(x % 256) as u8, 64, 128]));
+        img.save(&path).unwrap();
+
+        let pool = ImagePool::new(1, &[100, 1000]);
+        let ret = pool.process(&path).await;
+        std::fs::remove_file(&path).unwrap();
+
+        let processed = ret.unwrap().expect("should be an image");
+        assert_eq!((processed.width, processed.height), (400, 200));
+        assert!(!processed.blurhash.is_empty());
+        assert_eq!(processed.thumbnails.len(), 1);
+
+        let (size, data) = &processed.thumbnails[0];
+        assert_eq!(*size, 100);
+        let thumb = image::load_from_memory(data).unwrap();
+        assert_eq!(thumb.dimensions(), (100, 50));
+    }
+
+    #[tokio::test]
+    async fn test_process_non_image() {
+        let path = std::env::temp_dir().join(format!("{}.png", uuid::Uuid::now_v7()));
+        std::fs::write(&path, "not an image").unwrap();
+
+        let ret = ImagePool::new(1, &[100]).process(&path).await;
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(ret.unwrap().is_none());
+    }
+}
@@ -0,0 +1,142 @@
+use std::path::Path;
+
+use tokio::{fs, io::AsyncReadExt};
+
+use crate::AppErr;
+
+// magic numbers are at the beginning, a few KB is enough to tell text from binary
+const SNIFF_LEN: usize = 8192;
+const TEXT_PLAIN: &str = "text/plain";
+const OCTET_STREAM: &str = "application/octet-stream";
+
+// declared extensions kept for plain text content, browsers do not run them as active content
+const SAFE_TEXT_TYPES: &[&str] = &[
+    "text/plain",
+    "text/csv",
+    "text/markdown",
+    "text/x-markdown",
+    "text/x-log",
+    "application/json",
+];
+
+// detected types which browsers would run as active content, they are served as plain text
+const ACTIVE_TYPES: &[&str] = &["text/html", "text/xml", "application/xml", "image/svg+xml"];
+
+/// Content type detected from the bytes of a file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sniffed {
+    pub mime: &'static str,
+    pub ext: &'static str,
+}
+
+impl Sniffed {
+    /// Extension to store the file with: the declared one if it agrees with the content,
+    /// otherwise the one of the detected type so that it is never served as something else
+    pub fn ext_for(&self, declared: Option<&str>) -> String {
+        let agrees = declared.is_some_and(|ext| {
+            mime_guess::from_ext(ext).iter().any(|mime| {
+                let mime = mime.essence_str();
+                mime == self.mime || (self.mime == TEXT_PLAIN && SAFE_TEXT_TYPES.contains(&mime))
+            })
+        });
+
+        match declared {
+            Some(ext) if agrees => ext.to_string(),
+            _ => self.ext.to_string(),
+        }
+    }
+}
+
+pub async fn sniff_file(path: &Path) -> Result<Sniffed, AppErr> {
+    let mut file = fs::File::open(path).await?;
+    let mut buf = vec![0; SNIFF_LEN];
+    let mut len = 0;
+    while len < SNIFF_LEN {
+        let n = file.read(&mut buf[len..]).await?;
+        if n == 0 {
+            break;
+        }
+        len += n;
+    }
+
+    Ok(sniff(&buf[..len]))
+}
+
+pub fn sniff(buf: &[u8]) -> Sniffed {
+    if let Some(kind) = infer::get(buf).filter(|kind| !ACTIVE_TYPES.contains(&kind.mime_type())) {
+        return Sniffed {
+            mime: kind.mime_type(),
+            ext: kind.extension(),
+        };
+    }
+
+    if is_text(buf) {
+        Sniffed {
+            mime: TEXT_PLAIN,
+            ext: "txt",
+        }
+    } else {
+        Sniffed {
+            mime: OCTET_STREAM,
+            ext: "bin",
+        }
+    }
+}
+
+/// Check a content type against patterns like `image/*`, `application/pdf` or `*/*`
+pub fn is_allowed(mime: &str, allowlist: &[String]) -> bool {
+    let top_level = mime.split('/').next().unwrap_or_default();
+
+    allowlist
+        .iter()
+        .any(|pattern| match pattern.split_once('/') {
+            Some(("*", "*")) => true,
+            Some((ty, "*")) => ty.eq_ignore_ascii_case(top_level),
+            _ => pattern.eq_ignore_ascii_case(mime),
+        })
+}
+
+fn is_text(buf: &[u8]) -> bool {
+    if buf.contains(&0) {
+        return false;
+    }
+
+    match std::str::from_utf8(buf) {
+        Ok(_) => true,
+        // a multi-byte char may be cut at the end of the buffer
+        Err(e) => e.error_len().is_none(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sniff() {
+        let png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+        assert_eq!(sniff(png).mime, "image/png");
+        assert_eq!(sniff("你好 world".as_bytes()).mime, "text/plain");
+        assert_eq!(sniff(&"你好".as_bytes()[..4]).mime, "text/plain");
+        assert_eq!(sniff(b"\0\x01\x02garbage").mime, "application/octet-stream");
+
+        let sniffed = sniff(png);
+        assert_eq!(sniffed.ext_for(Some("png")), "png");
+        // a page is never served as html
+        assert_eq!(sniff(b"<html></html>").ext_for(Some("html")), "txt");
+        assert_eq!(sniff(b"a,b\n1,2").ext_for(Some("csv")), "csv");
+        assert_eq!(sniffed.ext_for(Some("jpg")), "png");
+        assert_eq!(sniffed.ext_for(None), "png");
+    }
+
+    #[test]
+    fn test_is_allowed() {
+        let allowlist = vec!["image/*".to_string(), "application/pdf".to_string()];
+        assert!(is_allowed("image/png", &allowlist));
+        assert!(is_allowed("application/pdf", &allowlist));
+        assert!(!is_allowed("application/zip", &allowlist));
+        assert!(!is_allowed("text/plain", &allowlist));
+        assert!(is_allowed("text/plain", &["*/*".to_string()]));
+        assert!(!is_allowed("text/plain", &[]));
+    }
+}
@@ -0,0 +1,327 @@
+//! Lossless removal of EXIF, XMP and text metadata from images, e.g. GPS location of photos.
+//! Only the container is rewritten, the pixel data is copied as is.
+
+use crate::AppErr;
+
+/// Images are stripped in memory, larger ones are rejected
+pub const MAX_STRIP_SIZE: u64 = 50 * 1024 * 1024;
+
+const JPEG_SOI: [u8; 2] = [0xFF, 0xD8];
+const JPEG_SOS: u8 = 0xDA;
+const JPEG_EOI: u8 = 0xD9;
+const JPEG_APP0: u8 = 0xE0;
+const JPEG_APP1: u8 = 0xE1;
+const JPEG_APP2: u8 = 0xE2;
+const JPEG_APP14: u8 = 0xEE;
+const JPEG_COM: u8 = 0xFE;
+const EXIF_HEADER: &[u8] = b"Exif\0\0";
+const ICC_HEADER: &[u8] = b"ICC_PROFILE\0";
+const EXIF_ORIENTATION: u16 = 0x0112;
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+const PNG_METADATA_CHUNKS: &[&[u8]] = &[b"eXIf", b"tEXt", b"zTXt", b"iTXt", b"tIME"];
+
+const WEBP_METADATA_CHUNKS: &[&[u8]] = &[b"EXIF", b"XMP "];
+// VP8X flags of the EXIF and XMP chunks
+const WEBP_METADATA_FLAGS: u8 = 0x08 | 0x04;
+
+// images of these types have no EXIF or XMP container, they are stored as is
+const METADATA_FREE_TYPES: &[&str] = &["image/gif", "image/bmp", "image/vnd.microsoft.icon"];
+
+/// Whether metadata of images of the type is stripped
+pub fn is_strippable(mime: &str) -> bool {
+    matches!(mime, "image/jpeg" | "image/png" | "image/webp")
+}
+
+/// Whether images of the type may carry metadata which is not stripped, e.g. EXIF of HEIC photos
+pub fn keeps_metadata(mime: &str) -> bool {
+    mime.starts_with("image/") && !is_strippable(mime) && !METADATA_FREE_TYPES.contains(&mime)
+}
+
+/// Strip metadata of a supported image, `None` if the type is not supported
+pub fn strip_metadata(data: &[u8], mime: &str) -> Option<Result<Vec<u8>, AppErr>> {
+    match mime {
+        "image/jpeg" => Some(strip_jpeg(data)),
+        "image/png" => Some(strip_png(data)),
+        "image/webp" => Some(strip_webp(data)),
+        _ => None,
+    }
+}
+
+/// Drop APPn and comment segments except JFIF, ICC profile and Adobe color info.
+/// The orientation is kept in a minimal EXIF segment, so that photos are not shown rotated.
+fn strip_jpeg(data: &[u8]) -> Result<Vec<u8>, AppErr> {
+    if !data.starts_with(&JPEG_SOI) {
+        return Err(malformed("jpeg"));
+    }
+
+    let mut header = vec![];
+    let mut segments = vec![];
+    let mut orientation = None;
+    let mut i = 2;
+    loop {
+        if i + 2 > data.len() || data[i] != 0xFF {
+            return Err(malformed("jpeg"));
+        }
+        let marker = data[i + 1];
+        match marker {
+            // fill byte
+            0xFF => {
+                i += 1;
+                continue;
+            }
+            // entropy coded data follows, copy the rest as is
+            JPEG_SOS | JPEG_EOI => break,
+            // markers without a payload
+            0x01 | 0xD0..=0xD7 => {
+                segments.extend_from_slice(&data[i..i + 2]);
+                i += 2;
+                continue;
+            }
+            _ => {}
+        }
+
+        if i + 4 > data.len() {
+            return Err(malformed("jpeg"));
+        }
+        let len = u16::from_be_bytes([data[i + 2], data[i + 3]]) as usize;
+        let end = i + 2 + len;
+        if len < 2 || end > data.len() {
+            return Err(malformed("jpeg"));
+        }
+        let segment = &data[i..end];
+        let payload = &segment[4..];
+
+        match marker {
+            JPEG_APP0 if segments.is_empty() => header.extend_from_slice(segment),
+            JPEG_APP0 | JPEG_APP14 => segments.extend_from_slice(segment),
+            JPEG_APP2 if payload.starts_with(ICC_HEADER) => segments.extend_from_slice(segment),
+            JPEG_APP1 if payload.starts_with(EXIF_HEADER) => {
+                orientation = orientation.or(exif_orientation(&payload[EXIF_HEADER.len()..]));
+            }
+            0xE0..=0xEF | JPEG_COM => {}
+            _ => segments.extend_from_slice(segment),
+        }
+        i = end;
+    }
+
+    let mut out = Vec::with_capacity(data.len());
+    out.extend_from_slice(&JPEG_SOI);
+    out.extend_from_slice(&header);
+    if let Some(orientation) = orientation.filter(|o| *o != 1) {
+        out.extend_from_slice(&orientation_segment(orientation));
+    }
+    out.extend_from_slice(&segments);
+    out.extend_from_slice(&data[i..]);
+
+    Ok(out)
+}
+
+/// Read the orientation tag of IFD0 from a TIFF structure
+fn exif_orientation(tiff: &[u8]) -> Option<u16> {
+    let big_endian = match tiff.get(..4)? {
+        b"MM\0\x2A" => true,
+        b"II\x2A\0" => false,
+        _ => return None,
+    };
+    let u16_at = |at: usize| -> Option<u16> {
+        let bytes = [*tiff.get(at)?, *tiff.get(at + 1)?];
+        Some(if big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    };
+    let u32_at = |at: usize| -> Option<u32> {
+        let bytes: [u8; 4] = tiff.get(at..at + 4)?.try_into().ok()?;
+        Some(if big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    };
+
+    let ifd = u32_at(4)? as usize;
+    let count = u16_at(ifd)? as usize;
+    (0..count)
+        .map(|n| ifd + 2 + n * 12)
+        .find(|&entry| u16_at(entry) == Some(EXIF_ORIENTATION))
+        .and_then(|entry| u16_at(entry + 8))
+        .filter(|orientation| (1..=8).contains(orientation))
+}
+
+/// APP1 segment with an EXIF structure which only has the orientation tag
+fn orientation_segment(orientation: u16) -> Vec<u8> {
+    let mut tiff = b"MM\0\x2A\0\0\0\x08".to_vec();
+    tiff.extend_from_slice(&1u16.to_be_bytes());
+    tiff.extend_from_slice(&EXIF_ORIENTATION.to_be_bytes());
+    // type SHORT, count 1, value left aligned
+    tiff.extend_from_slice(&3u16.to_be_bytes());
+    tiff.extend_from_slice(&1u32.to_be_bytes());
+    tiff.extend_from_slice(&orientation.to_be_bytes());
+    tiff.extend_from_slice(&[0, 0]);
+    // no next IFD
+    tiff.extend_from_slice(&0u32.to_be_bytes());
+
+    let len = (2 + EXIF_HEADER.len() + tiff.len()) as u16;
+    let mut segment = vec![0xFF, JPEG_APP1];
+    segment.extend_from_slice(&len.to_be_bytes());
+    segment.extend_from_slice(EXIF_HEADER);
+    segment.extend_from_slice(&tiff);
+    segment
+}
+
+/// Drop text, time and EXIF chunks, the CRC of other chunks is unchanged
+fn strip_png(data: &[u8]) -> Result<Vec<u8>, AppErr> {
+    if !data.starts_with(PNG_SIGNATURE) {
+        return Err(malformed("png"));
+    }
+
+    let mut out = Vec::with_capacity(data.len());
+    out.extend_from_slice(PNG_SIGNATURE);
+    let mut i = PNG_SIGNATURE.len();
+    loop {
+        if i + 8 > data.len() {
+            return Err(malformed("png"));
+        }
+        let len = u32::from_be_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]) as usize;
+        let kind = &data[i + 4..i + 8];
+        // length, type, data and CRC
+        let end = i + 12 + len;
+        if end > data.len() {
+            return Err(malformed("png"));
+        }
+
+        if !PNG_METADATA_CHUNKS.contains(&kind) {
+            out.extend_from_slice(&data[i..end]);
+        }
+        if kind == b"IEND" {
+            break;
+        }
+        i = end;
+    }
+
+    Ok(out)
+}
+
+/// Drop EXIF and XMP chunks and clear their flags in the VP8X header
+fn strip_webp(data: &[u8]) -> Result<Vec<u8>, AppErr> {
+    if data.len() < 12 || &data[..4] != b"RIFF" || &data[8..12] != b"WEBP" {
+        return Err(malformed("webp"));
+    }
+
+    let mut out = data[..12].to_vec();
+    let mut i = 12;
+    while i < data.len() {
+        if i + 8 > data.len() {
+            return Err(malformed("webp"));
+        }
+        let kind = &data[i..i + 4];
+        let len = u32::from_le_bytes([data[i + 4], data[i + 5], data[i + 6], data[i + 7]]) as usize;
+        // chunks are padded to an even size
+        let end = (i + 8 + len + (len & 1)).min(data.len());
+        if i + 8 + len > data.len() {
+            return Err(malformed("webp"));
+        }
+
+        if !WEBP_METADATA_CHUNKS.contains(&kind) {
+            let start = out.len();
+            out.extend_from_slice(&data[i..end]);
+            if kind == b"VP8X" && len > 0 {
+                out[start + 8] &= !WEBP_METADATA_FLAGS;
+            }
+        }
+        i = end;
+    }
+
+    let riff_len = (out.len() - 8) as u32;
+    out[4..8].copy_from_slice(&riff_len.to_le_bytes());
+
+    Ok(out)
+}
+
+fn malformed(format: &str) -> AppErr {
+    AppErr::UnsupportedFileTypeErr(format!("malformed {} image", format))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use image::{ImageFormat, RgbImage};
+
+    use super::*;
+
+    fn encode(format: ImageFormat) -> Vec<u8> {
+        let img = RgbImage::from_fn(8, 8, |x, y| image::Rgb([x as u8 * 30, y as u8 * 30, 0]));
+        let mut buf = Cursor::new(vec![]);
+        img.write_to(&mut buf, format).unwrap();
+        buf.into_inner()
+    }
+
+    fn exif_segment(orientation: u16) -> Vec<u8> {
+        let mut segment = orientation_segment(orientation);
+        // pretend there is a GPS tag as well
+        segment.extend_from_slice(b"GPS 31.2304N 121.4737E");
+        let len = (segment.len() - 2) as u16;
+        segment[2..4].copy_from_slice(&len.to_be_bytes());
+        segment
+    }
+
+    #[test]
+    fn test_strip_jpeg() {
+        let jpeg = encode(ImageFormat::Jpeg);
+        let mut tagged = JPEG_SOI.to_vec();
+        tagged.extend_from_slice(&exif_segment(6));
+        tagged.extend_from_slice(&[0xFF, JPEG_COM, 0, 7, b'h', b'e', b'l', b'l', b'o']);
+        tagged.extend_from_slice(&jpeg[2..]);
+
+        let stripped = strip_jpeg(&tagged).unwrap();
+        assert!(!stripped.windows(3).any(|w| w == b"GPS"));
+        assert!(!stripped.windows(5).any(|w| w == b"hello"));
+        let exif = &stripped[stripped.windows(6).position(|w| w == EXIF_HEADER).unwrap() + 6..];
+        assert_eq!(exif_orientation(exif), Some(6));
+        assert!(image::load_from_memory(&stripped).is_ok());
+
+        assert!(strip_jpeg(b"not a jpeg").is_err());
+    }
+
+    #[test]
+    fn test_strip_png_and_webp() {
+        let png = encode(ImageFormat::Png);
+        // insert a tEXt chunk after IHDR, its CRC is not checked when dropped
+        let mut tagged = png[..33].to_vec();
+        tagged.extend_from_slice(&[0, 0, 0, 8]);
+        tagged.extend_from_slice(b"tEXtGPS\0here");
+        tagged.extend_from_slice(&[0, 0, 0, 0]);
+        tagged.extend_from_slice(&png[33..]);
+
+        let stripped = strip_png(&tagged).unwrap();
+        assert_eq!(stripped, png);
+
+        let webp = encode(ImageFormat::WebP);
+        let mut tagged = webp.clone();
+        tagged.extend_from_slice(b"EXIF\x03\0\0\0GPS\0");
+        let riff_len = (tagged.len() - 8) as u32;
+        tagged[4..8].copy_from_slice(&riff_len.to_le_bytes());
+
+        let stripped = strip_webp(&tagged).unwrap();
+        assert_eq!(stripped, webp);
+    }
+
+    #[test]
+    fn test_keeps_metadata() {
+        for mime in ["image/heif", "image/avif", "image/tiff"] {
+            assert!(keeps_metadata(mime), "{}", mime);
+        }
+        for mime in [
+            "image/jpeg",
+            "image/png",
+            "image/webp",
+            "image/gif",
+            "video/mp4",
+        ] {
+            assert!(!keeps_metadata(mime), "{}", mime);
+        }
+    }
+}
@@ -1,6 +1,8 @@
 use axum::{
-    extract::{FromRequestParts, Query, Request, State},
-    http::StatusCode,
+    async_trait,
+    body::Body,
+    extract::{FromRequestParts, OriginalUri, Request, State},
+    http::{request::Parts, StatusCode},
     middleware::Next,
     response::{IntoResponse, Response},
 };
@@ -8,45 +10,50 @@ use axum_extra::{
     headers::{authorization::Bearer, Authorization},
     TypedHeader,
 };
-use serde::Deserialize;
+use chrono::Utc;
 use tracing::warn;
 
-use crate::AppState;
+use super::{ApiTokenScopes, ScopeGranted};
+use crate::{
+    model::{Role, SessionUser, API_TOKEN_PREFIX},
+    util::AccessToken,
+    AppErr, AppState,
+};
 
-#[derive(Debug, Deserialize)]
-struct Params {
-    token: String,
-}
+// routes open to users who have not verified their email yet
+const UNVERIFIED_ROUTES: &[&str] = &["/api/email/verify/resend", "/api/logout", "/api/sessions"];
 
 pub async fn verify_token(State(state): State<AppState>, req: Request, next: Next) -> Response {
     let (mut parts, body) = req.into_parts();
 
+    // tokens in the query string leak into logs, attachments are downloaded with signed urls instead
     let token =
         match TypedHeader::<Authorization<Bearer>>::from_request_parts(&mut parts, &state).await {
             Ok(TypedHeader(Authorization(bearer))) => bearer.token().to_string(),
             Err(e) => {
-                if e.is_missing() {
-                    match Query::<Params>::from_request_parts(&mut parts, &state).await {
-                        Ok(params) => params.token.clone(),
-                        Err(e) => {
-                            let err_msg = format!("Failed to parse token from query: {}", e);
-                            warn!(err_msg);
-                            return (StatusCode::UNAUTHORIZED, err_msg).into_response();
-                        }
-                    }
-                } else {
-                    let err_msg = format!("Invalid Authorization header: {}", e);
-                    warn!(err_msg);
-                    return (StatusCode::UNAUTHORIZED, err_msg).into_response();
-                }
+                let err_msg = format!("Invalid Authorization header: {}", e);
+                warn!(err_msg);
+                return (StatusCode::UNAUTHORIZED, err_msg).into_response();
             }
         };
 
+    if token.starts_with(API_TOKEN_PREFIX) {
+        return verify_api_token(state, parts, body, &token, next).await;
+    }
+
     // verify token and get user info
     let req = match state.dk.verify(&token) {
-        Ok(user) => {
+        Ok((_, access_token)) if is_revoked(&state, &access_token) => {
+            warn!("revoked token {} is used", access_token.jti);
+            return (StatusCode::UNAUTHORIZED, "Token is revoked").into_response();
+        }
+        Ok((user, _)) if !user.verified && !is_unverified_route(original_path(&parts)) => {
+            return (StatusCode::FORBIDDEN, "Email is not verified").into_response();
+        }
+        Ok((user, access_token)) => {
             let mut req = Request::from_parts(parts, body);
             req.extensions_mut().insert(user);
+            req.extensions_mut().insert(access_token);
             req
         }
         Err(e) => {
@@ -58,3 +65,136 @@ pub async fn verify_token(State(state): State<AppState>, req: Request, next: Nex
 
     next.run(req).await
 }
+
+/// Api tokens act as their user only on the routes their scopes grant, see `RequireScope`
+async fn verify_api_token(
+    state: AppState,
+    parts: Parts,
+    body: Body,
+    token: &str,
+    next: Next,
+) -> Response {
+    let (user, scopes) = match state.verify_api_token(token).await {
+        Ok(v) => v,
+        Err(e) => return e.into_response(),
+    };
+    if !user.verified {
+        return (StatusCode::FORBIDDEN, "Email is not verified").into_response();
+    }
+
+    let mut req = Request::from_parts(parts, body);
+    req.extensions_mut().insert(user);
+    req.extensions_mut().insert(ApiTokenScopes(scopes));
+    next.run(req).await
+}
+
+/// Let users of the role or a higher one through, api tokens can not use these routes
+pub async fn require_role(
+    State((state, role)): State<(AppState, Role)>,
+    user: SessionUser,
+    req: Request,
+    next: Next,
+) -> Result<Response, AppErr> {
+    state.ensure_role(user.id, role).await?;
+
+    Ok(next.run(req).await)
+}
+
+/// User authenticated by `verify_token`, api tokens are rejected unless the route grants their scope
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for SessionUser {
+    type Rejection = AppErr;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        let user = parts
+            .extensions
+            .get::<SessionUser>()
+            .cloned()
+            .ok_or_else(|| AppErr::AuthErr("route requires a signed in user".to_string()))?;
+        if parts.extensions.get::<ApiTokenScopes>().is_some()
+            && parts.extensions.get::<ScopeGranted>().is_none()
+        {
+            return Err(AppErr::PermissionDeniedErr(
+                "route is not open to api tokens".to_string(),
+            ));
+        }
+
+        Ok(user)
+    }
+}
+
+/// Path before nested routers strip their prefix
+fn original_path(parts: &Parts) -> &str {
+    parts
+        .extensions
+        .get::<OriginalUri>()
+        .map_or(parts.uri.path(), |uri| uri.path())
+}
+
+fn is_unverified_route(path: &str) -> bool {
+    UNVERIFIED_ROUTES.iter().any(|route| {
+        path.strip_prefix(route)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    })
+}
+
+/// Whether the token or its session is revoked
+fn is_revoked(state: &AppState, token: &AccessToken) -> bool {
+    let now = Utc::now().timestamp();
+    state.denylist.contains(&token.jti, now)
+        || token
+            .sid
+            .as_deref()
+            .is_some_and(|sid| state.denylist.contains(sid, now))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Scope;
+
+    #[test]
+    fn test_is_unverified_route() {
+        assert!(is_unverified_route("/api/logout"));
+        assert!(is_unverified_route("/api/sessions"));
+        assert!(is_unverified_route("/api/sessions/0192d1a0"));
+        assert!(is_unverified_route("/api/email/verify/resend"));
+
+        assert!(!is_unverified_route("/api/sessionsx"));
+        assert!(!is_unverified_route("/api/chat"));
+        assert!(!is_unverified_route("/files/1/a.png"));
+    }
+
+    #[tokio::test]
+    async fn test_session_user_extraction() {
+        let user = SessionUser::new(1, "foo".to_string(), "foo@acme.com".to_string(), true);
+        let extract = |extensions: &[&dyn Fn(&mut Request)]| {
+            let mut req = Request::new(Body::empty());
+            for insert in extensions {
+                insert(&mut req);
+            }
+            let (mut parts, _) = req.into_parts();
+            async move { SessionUser::from_request_parts(&mut parts, &()).await }
+        };
+        let with_user = |req: &mut Request| {
+            req.extensions_mut().insert(user.clone());
+        };
+        let with_token = |req: &mut Request| {
+            req.extensions_mut()
+                .insert(ApiTokenScopes(vec![Scope::ReadMessages]));
+        };
+        let with_grant = |req: &mut Request| {
+            req.extensions_mut().insert(ScopeGranted);
+        };
+
+        assert!(matches!(extract(&[]).await, Err(AppErr::AuthErr(_))));
+        assert_eq!(extract(&[&with_user]).await.unwrap().id, 1);
+        assert!(matches!(
+            extract(&[&with_user, &with_token]).await,
+            Err(AppErr::PermissionDeniedErr(_))
+        ));
+        assert!(extract(&[&with_user, &with_token, &with_grant])
+            .await
+            .is_ok());
+    }
+}
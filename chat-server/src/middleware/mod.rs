@@ -1,13 +1,13 @@
 mod auth;
 mod request_id;
+mod scope;
 mod server_time;
+mod tus;
 
 pub use auth::*;
-use axum::{
-    middleware::{from_fn, from_fn_with_state},
-    Router,
-};
+use axum::{middleware::from_fn, Router};
 use request_id::x_request_id;
+pub use scope::*;
 use server_time::ServerTimeLayer;
 use tower::ServiceBuilder;
 use tower_http::{
@@ -16,13 +16,12 @@ use tower_http::{
     LatencyUnit,
 };
 use tracing::Level;
-
-use crate::AppState;
+pub use tus::*;
 
 const X_REQUEST_ID: &str = "x-request-id";
 const X_SERVER_TIME: &str = "x-server-time";
 
-pub fn set_layer(state: AppState, app: Router) -> Router {
+pub fn set_layer(app: Router) -> Router {
     app.layer(
         ServiceBuilder::new()
             .layer(
@@ -37,7 +36,6 @@ pub fn set_layer(state: AppState, app: Router) -> Router {
             )
             .layer(CompressionLayer::new().gzip(true).br(true).deflate(true))
             .layer(from_fn(x_request_id))
-            .layer(from_fn_with_state(state, verify_token))
             .layer(ServerTimeLayer),
     )
 }
@@ -0,0 +1,71 @@
+use std::{future::Future, pin::Pin};
+
+use axum::{
+    extract::Request,
+    response::{IntoResponse, Response},
+};
+use tower::{Layer, Service};
+
+use crate::{model::Scope, AppErr};
+
+/// Scopes of the api token the request is authenticated with, absent for sessions
+#[derive(Debug, Clone)]
+pub(crate) struct ApiTokenScopes(pub Vec<Scope>);
+
+/// Marks requests whose api token has the scope of the route, see `SessionUser` extraction
+#[derive(Debug, Clone)]
+pub(crate) struct ScopeGranted;
+
+/// Opens the route to api tokens with the scope, sessions can use it either way
+#[derive(Clone)]
+pub struct RequireScope(pub Scope);
+
+impl<S> Layer<S> for RequireScope {
+    type Service = RequireScopeMiddleware<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RequireScopeMiddleware {
+            inner,
+            scope: self.0,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RequireScopeMiddleware<S> {
+    inner: S,
+    scope: Scope,
+}
+
+impl<S> Service<Request> for RequireScopeMiddleware<S>
+where
+    S: Service<Request, Response = Response> + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future =
+        Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'static>>;
+
+    fn poll_ready(
+        &mut self,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request) -> Self::Future {
+        if let Some(ApiTokenScopes(scopes)) = req.extensions().get() {
+            if !scopes.contains(&self.scope) {
+                let err = AppErr::PermissionDeniedErr(format!(
+                    "api token lacks the {} scope",
+                    self.scope
+                ));
+                return Box::pin(async move { Ok(err.into_response()) });
+            }
+            req.extensions_mut().insert(ScopeGranted);
+        }
+
+        Box::pin(self.inner.call(req))
+    }
+}
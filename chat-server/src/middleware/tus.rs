@@ -0,0 +1,52 @@
+use axum::{
+    extract::{Request, State},
+    http::{HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::AppState;
+
+pub const TUS_RESUMABLE: &str = "tus-resumable";
+pub const TUS_PROTOCOL_VERSION: &str = "1.0.0";
+const TUS_VERSION: &str = "tus-version";
+const TUS_EXTENSION: &str = "tus-extension";
+const TUS_MAX_SIZE: &str = "tus-max-size";
+const TUS_EXTENSIONS: &str = "creation,expiration,termination";
+
+/// Reject requests of other tus protocol versions and mark every response with the version.
+/// OPTIONS requests are answered by the CORS layer, the server capabilities are added to them.
+pub async fn tus_resumable(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let is_options = req.method() == Method::OPTIONS;
+    let version = req.headers().get(TUS_RESUMABLE);
+
+    let mut res =
+        if !is_options && version.and_then(|v| v.to_str().ok()) != Some(TUS_PROTOCOL_VERSION) {
+            let mut res = (
+                StatusCode::PRECONDITION_FAILED,
+                format!("unsupported tus version: {:?}", version),
+            )
+                .into_response();
+            res.headers_mut()
+                .insert(TUS_VERSION, HeaderValue::from_static(TUS_PROTOCOL_VERSION));
+            res
+        } else {
+            next.run(req).await
+        };
+
+    let headers = res.headers_mut();
+    headers.insert(
+        TUS_RESUMABLE,
+        HeaderValue::from_static(TUS_PROTOCOL_VERSION),
+    );
+    if is_options {
+        headers.insert(TUS_VERSION, HeaderValue::from_static(TUS_PROTOCOL_VERSION));
+        headers.insert(TUS_EXTENSION, HeaderValue::from_static(TUS_EXTENSIONS));
+        headers.insert(
+            TUS_MAX_SIZE,
+            HeaderValue::from(state.config.storage.max_file_size),
+        );
+    }
+
+    res
+}
@@ -0,0 +1,357 @@
+use std::{fmt, str::FromStr};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{prelude::FromRow, PgConnection};
+use tracing::info;
+
+use crate::{
+    util::{generate_token, hash_token},
+    AppErr, AppState,
+};
+
+use super::{Role, SessionUser};
+
+/// Api tokens are told apart from JWTs by this prefix
+pub(crate) const API_TOKEN_PREFIX: &str = "ecp_";
+// characters kept to recognize a token in the list, after the prefix
+const TOKEN_PREFIX_LEN: usize = 8;
+const MAX_TOKEN_NAME_LEN: usize = 64;
+// ten years, tokens without an expiration are created without expiresInDays
+const MAX_TOKEN_EXPIRES_DAYS: u32 = 3650;
+// last use is recorded at most once a minute
+const LAST_USED_INTERVAL: i64 = 60;
+
+/// What an api token may do, routes without a scope are only open to sessions
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Scope {
+    #[serde(rename = "read:chats")]
+    ReadChats,
+    #[serde(rename = "write:chats")]
+    WriteChats,
+    #[serde(rename = "read:messages")]
+    ReadMessages,
+    #[serde(rename = "write:messages")]
+    WriteMessages,
+    #[serde(rename = "read:files")]
+    ReadFiles,
+    #[serde(rename = "write:files")]
+    WriteFiles,
+}
+
+#[derive(Debug, Clone, FromRow, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiToken {
+    pub id: i64,
+    pub user_id: i64,
+    pub created_by: i64,
+    pub name: String,
+    /// First characters of the token, to recognize it
+    pub token_prefix: String,
+    pub scopes: Vec<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+// token with the user it acts as
+#[derive(FromRow)]
+struct ApiTokenOwner {
+    id: i64,
+    scopes: Vec<String>,
+    stale: bool,
+    user_id: i64,
+    username: String,
+    email: String,
+    verified: bool,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreatedApiToken {
+    #[serde(flatten)]
+    pub info: ApiToken,
+    /// Shown only once, only its digest is stored
+    pub token: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateApiToken {
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    /// Never expires if absent
+    pub expires_in_days: Option<u32>,
+    /// User the token acts as, e.g. a bot account, only admins can mint for other users
+    pub user_id: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ListApiTokens {
+    /// Only admins can list the tokens of other users
+    pub user_id: Option<i64>,
+}
+
+impl Scope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::ReadChats => "read:chats",
+            Self::WriteChats => "write:chats",
+            Self::ReadMessages => "read:messages",
+            Self::WriteMessages => "write:messages",
+            Self::ReadFiles => "read:files",
+            Self::WriteFiles => "write:files",
+        }
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Scope {
+    type Err = AppErr;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_value(serde_json::Value::String(s.to_string()))
+            .map_err(|_| AppErr::InvalidInputErr(format!("unknown scope {}", s)))
+    }
+}
+
+impl AppState {
+    /// Mint a token for the user, or for another user by an admin
+    pub async fn create_api_token(
+        &self,
+        input: CreateApiToken,
+        creator_id: i64,
+    ) -> Result<CreatedApiToken, AppErr> {
+        let user_id = input.user_id.unwrap_or(creator_id);
+        if user_id != creator_id {
+            self.ensure_role(creator_id, Role::Admin).await?;
+        }
+        let name = input.name.trim();
+        if name.is_empty() || name.chars().count() > MAX_TOKEN_NAME_LEN {
+            return Err(AppErr::InvalidInputErr(format!(
+                "token name must have 1 to {} characters",
+                MAX_TOKEN_NAME_LEN
+            )));
+        }
+        if input.scopes.is_empty() {
+            return Err(AppErr::InvalidInputErr(
+                "token must have at least one scope".to_string(),
+            ));
+        }
+
+        let mut scopes: Vec<String> = input.scopes.iter().map(|s| s.to_string()).collect();
+        scopes.sort();
+        scopes.dedup();
+        let token = format!("{}{}", API_TOKEN_PREFIX, generate_token());
+        let expires_at = match input.expires_in_days {
+            Some(days) => Some(expires_at(days, Utc::now())?),
+            None => None,
+        };
+
+        let info: ApiToken = sqlx::query_as(
+            r#"
+            INSERT INTO t_api_token (user_id, created_by, name, token_hash, token_prefix, scopes, expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            RETURNING id, user_id, created_by, name, token_prefix, scopes, expires_at, last_used_at, created_at
+            "#,
+        )
+        .bind(user_id)
+        .bind(creator_id)
+        .bind(name)
+        .bind(hash_token(&token))
+        .bind(&token[..API_TOKEN_PREFIX.len() + TOKEN_PREFIX_LEN])
+        .bind(&scopes)
+        .bind(expires_at)
+        .fetch_one(&self.pg)
+        .await?;
+        info!(
+            "api token {} of user {} is created by {}",
+            info.id, user_id, creator_id
+        );
+
+        Ok(CreatedApiToken { info, token })
+    }
+
+    /// Tokens which are not revoked, of the user or of another user for an admin
+    pub async fn list_api_tokens(
+        &self,
+        input: ListApiTokens,
+        user_id: i64,
+    ) -> Result<Vec<ApiToken>, AppErr> {
+        let owner_id = input.user_id.unwrap_or(user_id);
+        if owner_id != user_id {
+            self.ensure_role(user_id, Role::Admin).await?;
+        }
+
+        let tokens = sqlx::query_as(
+            r#"
+            SELECT id, user_id, created_by, name, token_prefix, scopes, expires_at, last_used_at, created_at
+            FROM t_api_token WHERE user_id = $1 AND revoked_at IS NULL
+            ORDER BY id DESC
+            "#,
+        )
+        .bind(owner_id)
+        .fetch_all(&self.pg)
+        .await?;
+
+        Ok(tokens)
+    }
+
+    /// Revoke a token of the user, admins can revoke any token
+    pub async fn revoke_api_token(&self, id: i64, user_id: i64) -> Result<(), AppErr> {
+        let owner_id: Option<i64> = sqlx::query_scalar(
+            "SELECT user_id FROM t_api_token WHERE id = $1 AND revoked_at IS NULL",
+        )
+        .bind(id)
+        .fetch_optional(&self.pg)
+        .await?;
+        match owner_id {
+            None => return Err(AppErr::NotFoundErr(format!("api token {} not found", id))),
+            Some(owner_id) if owner_id != user_id => {
+                // do not tell others' tokens from missing ones
+                self.ensure_role(user_id, Role::Admin)
+                    .await
+                    .map_err(|_| AppErr::NotFoundErr(format!("api token {} not found", id)))?;
+            }
+            Some(_) => {}
+        }
+
+        sqlx::query("UPDATE t_api_token SET revoked_at = NOW() WHERE id = $1")
+            .bind(id)
+            .execute(&self.pg)
+            .await?;
+        info!("api token {} is revoked by user {}", id, user_id);
+
+        Ok(())
+    }
+
+    /// User and scopes of a valid api token, checked against the database on every request
+    /// so that revocation takes effect at once
+    pub async fn verify_api_token(&self, token: &str) -> Result<(SessionUser, Vec<Scope>), AppErr> {
+        let row: Option<ApiTokenOwner> = sqlx::query_as(
+            r#"
+            SELECT t.id, t.scopes, t.last_used_at IS NULL OR t.last_used_at < NOW() - make_interval(secs => $2) AS stale,
+                u.id AS user_id, u.username, u.email, u.email_verified_at IS NOT NULL AS verified
+            FROM t_api_token t JOIN t_user u ON u.id = t.user_id
+            WHERE t.token_hash = $1 AND t.revoked_at IS NULL AND (t.expires_at IS NULL OR t.expires_at > NOW())
+            "#,
+        )
+        .bind(hash_token(token))
+        .bind(LAST_USED_INTERVAL as f64)
+        .fetch_optional(&self.pg)
+        .await?;
+        let Some(row) = row else {
+            return Err(AppErr::AuthErr("invalid api token".to_string()));
+        };
+
+        if row.stale {
+            sqlx::query("UPDATE t_api_token SET last_used_at = NOW() WHERE id = $1")
+                .bind(row.id)
+                .execute(&self.pg)
+                .await?;
+        }
+
+        // scopes which are no longer known are ignored
+        let scopes = row.scopes.iter().filter_map(|s| s.parse().ok()).collect();
+        Ok((
+            SessionUser::new(row.user_id, row.username, row.email, row.verified),
+            scopes,
+        ))
+    }
+}
+
+/// Revoke all api tokens of the user, when its password is reset or changed
+pub(super) async fn revoke_user_api_tokens(
+    conn: &mut PgConnection,
+    user_id: i64,
+) -> Result<u64, AppErr> {
+    let ret = sqlx::query(
+        "UPDATE t_api_token SET revoked_at = NOW() WHERE user_id = $1 AND revoked_at IS NULL",
+    )
+    .bind(user_id)
+    .execute(conn)
+    .await?;
+
+    Ok(ret.rows_affected())
+}
+
+fn expires_at(days: u32, now: DateTime<Utc>) -> Result<DateTime<Utc>, AppErr> {
+    if !(1..=MAX_TOKEN_EXPIRES_DAYS).contains(&days) {
+        return Err(AppErr::InvalidInputErr(format!(
+            "token must expire in 1 to {} days",
+            MAX_TOKEN_EXPIRES_DAYS
+        )));
+    }
+
+    TimeDelta::try_days(days as i64)
+        .and_then(|delta| now.checked_add_signed(delta))
+        .ok_or_else(|| AppErr::InvalidInputErr(format!("invalid expiration days {}", days)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::ChangePasswdForm;
+    use anyhow::Result;
+
+    #[test]
+    fn test_scope() {
+        assert_eq!(
+            "read:messages".parse::<Scope>().unwrap(),
+            Scope::ReadMessages
+        );
+        assert_eq!(Scope::WriteFiles.to_string(), "write:files");
+        assert!("admin".parse::<Scope>().is_err());
+
+        let scopes: Vec<Scope> =
+            serde_json::from_str(r#"["read:chats", "write:messages"]"#).unwrap();
+        assert_eq!(scopes, vec![Scope::ReadChats, Scope::WriteMessages]);
+    }
+
+    #[test]
+    fn test_expires_at() {
+        let now = Utc::now();
+        assert_eq!(expires_at(30, now).unwrap(), now + TimeDelta::days(30));
+        for days in [0, MAX_TOKEN_EXPIRES_DAYS + 1, u32::MAX] {
+            assert!(matches!(
+                expires_at(days, now),
+                Err(AppErr::InvalidInputErr(_))
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_api_token_revoked_on_passwd_change() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let create = |expires_in_days| CreateApiToken {
+            name: "bot".to_string(),
+            scopes: vec![Scope::ReadMessages],
+            expires_in_days,
+            user_id: None,
+        };
+
+        assert!(matches!(
+            state.create_api_token(create(Some(u32::MAX)), 1).await,
+            Err(AppErr::InvalidInputErr(_))
+        ));
+        let created = state.create_api_token(create(Some(7)), 1).await?;
+        let (user, scopes) = state.verify_api_token(&created.token).await?;
+        assert_eq!(user.id, 1);
+        assert_eq!(scopes, vec![Scope::ReadMessages]);
+
+        let form = ChangePasswdForm {
+            current_passwd: "changed123".to_string(),
+            new_passwd: "renewed456".to_string(),
+        };
+        state.change_passwd(1, form, None).await?;
+        assert!(state.verify_api_token(&created.token).await.is_err());
+
+        Ok(())
+    }
+}
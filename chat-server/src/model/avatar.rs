@@ -0,0 +1,261 @@
+use axum::extract::multipart::Field;
+use serde::Serialize;
+use tokio::{fs, io::AsyncWriteExt};
+
+use crate::{media::sniff_file, storage::avatar_key, AppErr, AppState};
+
+use super::{hash_file, User};
+
+const AVATAR_URL_PREFIX: &str = "/avatars/";
+const AVATAR_EXT: &str = ".jpg";
+const IDENTICON_URL_PREFIX: &str = "/identicons/";
+const IDENTICON_EXT: &str = ".png";
+// length of t_user.avatar
+const MAX_AVATAR_LEN: usize = 128;
+// avatars are rendered to a few small sizes, larger uploads are pointless
+const MAX_AVATAR_FILE_SIZE: u64 = 10 * 1024 * 1024;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AvatarOutput {
+    pub avatar: String,
+}
+
+impl User {
+    /// Fall back to the generated identicon of the user if no avatar is uploaded
+    pub fn with_default_avatar(mut self) -> Self {
+        if self.avatar.is_empty() {
+            self.avatar = identicon_url(self.id);
+        }
+        self
+    }
+}
+
+pub fn identicon_url(user_id: i64) -> String {
+    format!("{}{}{}", IDENTICON_URL_PREFIX, user_id, IDENTICON_EXT)
+}
+
+/// Content hash of an avatar reference like `/avatars/{sha256}.jpg`
+pub fn parse_avatar_name(name: &str) -> Option<&str> {
+    name.strip_suffix(AVATAR_EXT)
+        .filter(|hash| hash.len() == 64 && hash.bytes().all(|b| b.is_ascii_hexdigit()))
+}
+
+/// An uploaded avatar, an http(s) url of an image elsewhere, or empty for the identicon
+pub fn avatar_errors(avatar: &str) -> Vec<String> {
+    let valid = avatar.is_empty()
+        || avatar
+            .strip_prefix(AVATAR_URL_PREFIX)
+            .and_then(parse_avatar_name)
+            .is_some()
+        || reqwest::Url::parse(avatar)
+            .is_ok_and(|url| matches!(url.scheme(), "http" | "https") && url.has_host())
+            && !avatar.chars().any(|c| c.is_whitespace() || c.is_control());
+
+    let mut errors = Vec::new();
+    if avatar.chars().count() > MAX_AVATAR_LEN {
+        errors.push(format!("must have at most {} characters", MAX_AVATAR_LEN));
+    }
+    if !valid {
+        errors.push("must be an uploaded avatar or an http(s) url".to_string());
+    }
+    errors
+}
+
+/// User id of an identicon reference like `/identicons/1.png`
+pub fn parse_identicon_name(name: &str) -> Option<i64> {
+    name.strip_suffix(IDENTICON_EXT)?.parse().ok()
+}
+
+impl AppState {
+    /// Crop the uploaded image to a square, render and store it at the configured sizes,
+    /// then make it the avatar of the user
+    pub async fn save_avatar(&self, user_id: i64, field: Field<'_>) -> Result<String, AppErr> {
+        let tmp_dir = self.config.storage.base_dir.join("tmp");
+        fs::create_dir_all(&tmp_dir).await?;
+
+        let tmp_path = tmp_dir.join(uuid::Uuid::now_v7().to_string());
+        let ret = async {
+            write_avatar_file(&tmp_path, field).await?;
+            let sniffed = sniff_file(&tmp_path).await?;
+            if !sniffed.mime.starts_with("image/") {
+                return Err(AppErr::UnsupportedFileTypeErr(format!(
+                    "avatar must be an image, not {}",
+                    sniffed.mime
+                )));
+            }
+
+            let (hash, _) = hash_file(&tmp_path).await?;
+            let sizes = &self.config.storage.avatar.sizes;
+            for (size, data) in self.image_pool.render_avatar(&tmp_path, sizes).await? {
+                let key = avatar_key(&hash, size);
+                if self.storage.exists(&key).await? {
+                    continue;
+                }
+
+                let variant_path = tmp_path.with_extension(format!("{}.jpg", size));
+                fs::write(&variant_path, data).await?;
+                let ret = self.storage.put(&key, &variant_path).await;
+                let _ = fs::remove_file(&variant_path).await;
+                ret?;
+            }
+
+            Ok(format!("{}{}{}", AVATAR_URL_PREFIX, hash, AVATAR_EXT))
+        }
+        .await;
+        let _ = fs::remove_file(&tmp_path).await;
+
+        let avatar = ret?;
+        self.set_avatar(user_id, &avatar).await?;
+        Ok(avatar)
+    }
+
+    /// Remove the uploaded avatar, the user gets the identicon again
+    pub async fn delete_avatar(&self, user_id: i64) -> Result<String, AppErr> {
+        self.set_avatar(user_id, "").await?;
+        Ok(identicon_url(user_id))
+    }
+
+    /// Check the requested avatar size against the rendered ones, the largest one if absent
+    pub fn avatar_size(&self, size: Option<u32>) -> Result<u32, AppErr> {
+        let sizes = &self.config.storage.avatar.sizes;
+        let largest = sizes.iter().copied().max().unwrap_or_default();
+        match size {
+            None => Ok(largest),
+            Some(size) if sizes.contains(&size) => Ok(size),
+            Some(size) => Err(AppErr::NotFoundErr(format!(
+                "avatar size {} is not one of {:?}",
+                size, sizes
+            ))),
+        }
+    }
+
+    pub(super) async fn set_avatar(&self, user_id: i64, avatar: &str) -> Result<(), AppErr> {
+        let old: Option<String> = sqlx::query_scalar(
+            r#"
+            UPDATE t_user u SET avatar = $2, updated_at = NOW()
+            FROM t_user old WHERE u.id = $1 AND old.id = u.id
+            RETURNING old.avatar
+            "#,
+        )
+        .bind(user_id)
+        .bind(avatar)
+        .fetch_optional(&self.pg)
+        .await?;
+
+        let Some(old) = old else {
+            return Err(AppErr::NotFoundErr(format!("user {} not found", user_id)));
+        };
+        let Some(hash) = old
+            .strip_prefix(AVATAR_URL_PREFIX)
+            .and_then(parse_avatar_name)
+            .filter(|_| old != avatar)
+        else {
+            return Ok(());
+        };
+
+        // the same image may be the avatar of other users
+        let referenced: bool =
+            sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM t_user WHERE avatar = $1)")
+                .bind(&old)
+                .fetch_one(&self.pg)
+                .await?;
+        if !referenced {
+            for &size in &self.config.storage.avatar.sizes {
+                self.storage.delete(&avatar_key(hash, size)).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Storage keys of the rendered sizes of all uploaded avatars in use
+    pub(super) async fn avatar_keys(&self) -> Result<Vec<String>, AppErr> {
+        let avatars: Vec<String> = sqlx::query_scalar(
+            "SELECT DISTINCT avatar FROM t_user WHERE avatar LIKE $1 || '%' ORDER BY avatar",
+        )
+        .bind(AVATAR_URL_PREFIX)
+        .fetch_all(&self.pg)
+        .await?;
+
+        let sizes = &self.config.storage.avatar.sizes;
+        Ok(avatars
+            .iter()
+            .filter_map(|avatar| {
+                avatar
+                    .strip_prefix(AVATAR_URL_PREFIX)
+                    .and_then(parse_avatar_name)
+            })
+            .flat_map(|hash| sizes.iter().map(move |&size| avatar_key(hash, size)))
+            .collect())
+    }
+}
+
+async fn write_avatar_file(path: &std::path::Path, mut field: Field<'_>) -> Result<(), AppErr> {
+    let mut file = fs::File::create(path).await?;
+    let mut size = 0;
+
+    while let Some(chunk) = field.chunk().await? {
+        size += chunk.len() as u64;
+        if size > MAX_AVATAR_FILE_SIZE {
+            return Err(AppErr::PayloadTooLargeErr(format!(
+                "avatar is larger than {} bytes",
+                MAX_AVATAR_FILE_SIZE
+            )));
+        }
+        file.write_all(&chunk).await?;
+    }
+    file.flush().await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn test_parse_avatar_refs() {
+        let hash = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08";
+        assert_eq!(parse_avatar_name(&format!("{}.jpg", hash)), Some(hash));
+        assert_eq!(parse_avatar_name("abc.jpg"), None);
+        assert_eq!(parse_avatar_name(&format!("{}.png", hash)), None);
+
+        assert!(avatar_errors(&format!("/avatars/{}.jpg", hash)).is_empty());
+        assert!(avatar_errors("https://cdn.acme.com/a.png").is_empty());
+        assert!(avatar_errors("").is_empty());
+        assert_eq!(avatar_errors("/avatars/abc.jpg").len(), 1);
+        assert_eq!(avatar_errors("javascript:alert(1)").len(), 1);
+        assert_eq!(avatar_errors(" https://cdn.acme.com/a.png").len(), 1);
+        assert_eq!(
+            avatar_errors(&format!("https://acme.com/{}", "a".repeat(128))).len(),
+            1
+        );
+
+        assert_eq!(parse_identicon_name(&identicon_url(42)[12..]), Some(42));
+        assert_eq!(parse_identicon_name("x.png"), None);
+    }
+
+    #[tokio::test]
+    async fn test_avatar_keys() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let hash = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08";
+        let avatar = format!("{}{}{}", AVATAR_URL_PREFIX, hash, AVATAR_EXT);
+        state.set_avatar(1, &avatar).await?;
+        state.set_avatar(2, &avatar).await?;
+        state.set_avatar(3, "https://example.com/a.png").await?;
+
+        let keys = state.avatar_keys().await?;
+        let expected: Vec<String> = state
+            .config
+            .storage
+            .avatar
+            .sizes
+            .iter()
+            .map(|&size| avatar_key(hash, size))
+            .collect();
+        assert_eq!(keys, expected);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,276 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::prelude::FromRow;
+
+use super::excerpt;
+use crate::{notif::AppEvent, AppErr, AppState};
+
+// max chars of the bookmarked message carried by a reminder
+const REMINDER_EXCERPT_LEN: usize = 128;
+const REMINDER_BATCH_SIZE: i64 = 100;
+
+// bookmarks are only visible while the message exists and the user is still a member of its chat
+const SELECT_BOOKMARK: &str = r#"
+SELECT b.id, b.msg_id, m.chat_id, m.sender_id, m.content, b.remind_at, b.created_at
+FROM t_bookmark b
+JOIN t_message m ON m.id = b.msg_id AND m.deleted_at IS NULL
+JOIN t_chat c ON c.id = m.chat_id AND b.user_id = ANY(c.members)
+"#;
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Bookmark {
+    pub id: i64,
+    pub msg_id: i64,
+    pub chat_id: i64,
+    pub sender_id: i64,
+    pub content: String,
+    pub remind_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateBookmark {
+    pub msg_id: i64,
+    pub remind_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateBookmark {
+    pub remind_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, FromRow)]
+struct DueReminder {
+    user_id: i64,
+    #[sqlx(flatten)]
+    bookmark: Bookmark,
+}
+
+impl AppState {
+    /// Bookmark a message of a chat the user belongs to, bookmark it again to reset the reminder
+    pub async fn create_bookmark(
+        &self,
+        input: CreateBookmark,
+        user_id: i64,
+    ) -> Result<Bookmark, AppErr> {
+        // messages of other chats are not found either, so that their ids can not be probed
+        let visible: bool = sqlx::query_scalar(
+            r#"
+            SELECT EXISTS (
+                SELECT 1 FROM t_message m JOIN t_chat c ON c.id = m.chat_id
+                WHERE m.id = $1 AND m.deleted_at IS NULL AND $2 = ANY(c.members)
+            )
+            "#,
+        )
+        .bind(input.msg_id)
+        .bind(user_id)
+        .fetch_one(&self.pg)
+        .await?;
+        if !visible {
+            return Err(AppErr::NotFoundErr(format!(
+                "message {} not found",
+                input.msg_id
+            )));
+        }
+
+        let id: i64 = sqlx::query_scalar(
+            r#"
+            INSERT INTO t_bookmark (user_id, msg_id, remind_at) VALUES ($1, $2, $3)
+            ON CONFLICT (user_id, msg_id) DO UPDATE SET remind_at = EXCLUDED.remind_at, reminded_at = NULL
+            RETURNING id
+            "#,
+        )
+        .bind(user_id)
+        .bind(input.msg_id)
+        .bind(input.remind_at)
+        .fetch_one(&self.pg)
+        .await?;
+
+        self.get_bookmark(id, user_id).await
+    }
+
+    /// Set or cancel the reminder of a bookmark
+    pub async fn update_bookmark(
+        &self,
+        input: UpdateBookmark,
+        id: i64,
+        user_id: i64,
+    ) -> Result<Bookmark, AppErr> {
+        sqlx::query(
+            "UPDATE t_bookmark SET remind_at = $1, reminded_at = NULL WHERE id = $2 AND user_id = $3",
+        )
+        .bind(input.remind_at)
+        .bind(id)
+        .bind(user_id)
+        .execute(&self.pg)
+        .await?;
+
+        self.get_bookmark(id, user_id).await
+    }
+
+    pub async fn delete_bookmark(&self, id: i64, user_id: i64) -> Result<(), AppErr> {
+        let ret = sqlx::query("DELETE FROM t_bookmark WHERE id = $1 AND user_id = $2")
+            .bind(id)
+            .bind(user_id)
+            .execute(&self.pg)
+            .await?;
+
+        if ret.rows_affected() == 0 {
+            return Err(AppErr::NotFoundErr(format!("bookmark {} not found", id)));
+        }
+
+        Ok(())
+    }
+
+    /// List saved items of the user, latest first
+    pub async fn list_bookmarks(&self, user_id: i64) -> Result<Vec<Bookmark>, AppErr> {
+        let bookmarks = sqlx::query_as(&format!(
+            "{} WHERE b.user_id = $1 ORDER BY b.created_at DESC",
+            SELECT_BOOKMARK
+        ))
+        .bind(user_id)
+        .fetch_all(&self.pg)
+        .await?;
+
+        Ok(bookmarks)
+    }
+
+    async fn get_bookmark(&self, id: i64, user_id: i64) -> Result<Bookmark, AppErr> {
+        let bookmark: Option<Bookmark> = sqlx::query_as(&format!(
+            "{} WHERE b.id = $1 AND b.user_id = $2",
+            SELECT_BOOKMARK
+        ))
+        .bind(id)
+        .bind(user_id)
+        .fetch_optional(&self.pg)
+        .await?;
+
+        bookmark.ok_or_else(|| AppErr::NotFoundErr(format!("bookmark {} not found", id)))
+    }
+
+    /// Notify owners of due reminders and mark them as sent.
+    /// Rows are locked with SKIP LOCKED so that several instances can run it at the same time.
+    pub async fn send_due_reminders(&self) -> Result<(), AppErr> {
+        let mut tx = self.pg.begin().await?;
+        let ids: Vec<i64> = sqlx::query_scalar(
+            r#"
+            SELECT id FROM t_bookmark
+            WHERE remind_at <= NOW() AND reminded_at IS NULL
+            ORDER BY remind_at
+            LIMIT $1
+            FOR UPDATE SKIP LOCKED
+            "#,
+        )
+        .bind(REMINDER_BATCH_SIZE)
+        .fetch_all(&mut *tx)
+        .await?;
+        if ids.is_empty() {
+            return Ok(());
+        }
+
+        let due: Vec<DueReminder> = sqlx::query_as(&format!(
+            r#"
+            SELECT b.user_id, t.* FROM ({}) t
+            JOIN t_bookmark b ON b.id = t.id
+            WHERE t.id = ANY($1)
+            "#,
+            SELECT_BOOKMARK
+        ))
+        .bind(&ids)
+        .fetch_all(&mut *tx)
+        .await?;
+
+        // bookmarks no longer visible are marked as well, the others once notified
+        let mut sent: Vec<i64> = ids
+            .into_iter()
+            .filter(|id| due.iter().all(|r| r.bookmark.id != *id))
+            .collect();
+        let mut ret = Ok(());
+        for DueReminder {
+            user_id,
+            mut bookmark,
+        } in due
+        {
+            let id = bookmark.id;
+            bookmark.content = excerpt(&bookmark.content, REMINDER_EXCERPT_LEN);
+            ret = self
+                .notify_users(&[user_id], None, AppEvent::BookmarkReminder(bookmark))
+                .await;
+            if ret.is_err() {
+                break;
+            }
+            sent.push(id);
+        }
+
+        sqlx::query("UPDATE t_bookmark SET reminded_at = NOW() WHERE id = ANY($1)")
+            .bind(&sent)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        ret
+    }
+
+    /// Remove bookmarks whose message is deleted or whose chat the user has left
+    pub async fn purge_bookmarks(&self) -> Result<(), AppErr> {
+        sqlx::query(
+            r#"
+            DELETE FROM t_bookmark b
+            WHERE NOT EXISTS (
+                SELECT 1 FROM t_message m
+                JOIN t_chat c ON c.id = m.chat_id
+                WHERE m.id = b.msg_id AND m.deleted_at IS NULL AND b.user_id = ANY(c.members)
+            )
+            "#,
+        )
+        .execute(&self.pg)
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn test_bookmark() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let create = |msg_id| CreateBookmark {
+            msg_id,
+            remind_at: Some(Utc::now() - chrono::Duration::minutes(1)),
+        };
+
+        // message 3 is in chat 2 which bob is not a member of, like a missing message
+        assert!(matches!(
+            state.create_bookmark(create(3), 2).await,
+            Err(AppErr::NotFoundErr(_))
+        ));
+        assert!(matches!(
+            state.create_bookmark(create(100), 2).await,
+            Err(AppErr::NotFoundErr(_))
+        ));
+
+        let bookmark = state.create_bookmark(create(1), 2).await?;
+        assert_eq!(bookmark.chat_id, 1);
+        assert_eq!(state.list_bookmarks(2).await?.len(), 1);
+
+        state.send_due_reminders().await?;
+        let reminded: bool =
+            sqlx::query_scalar("SELECT reminded_at IS NOT NULL FROM t_bookmark WHERE id = $1")
+                .bind(bookmark.id)
+                .fetch_one(&state.pg)
+                .await?;
+        assert!(reminded);
+
+        state.delete_bookmark(bookmark.id, 2).await?;
+        assert!(state.list_bookmarks(2).await?.is_empty());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,74 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::prelude::FromRow;
+
+use crate::{AppErr, AppState};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "chat_type", rename_all = "snake_case")]
+#[serde(rename_all = "camelCase")]
+pub enum ChatType {
+    Single,
+    Group,
+    PrivateChannel,
+    PublicChannel,
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChatItem {
+    pub id: i64,
+    pub name: String,
+    #[sqlx(rename = "type")]
+    #[serde(rename = "type")]
+    pub r#type: ChatType,
+    pub members: Vec<i64>,
+    pub has_draft: bool,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl AppState {
+    /// List chats the user belongs to
+    pub async fn list_chats(&self, user_id: i64) -> Result<Vec<ChatItem>, AppErr> {
+        let chats = sqlx::query_as(
+            r#"
+            SELECT c.id, c.name, c.type, c.members, c.created_at, c.updated_at,
+                EXISTS(SELECT 1 FROM t_draft d WHERE d.chat_id = c.id AND d.user_id = $1 AND d.content <> '') AS has_draft
+            FROM t_chat c
+            WHERE $1 = ANY(c.members)
+            ORDER BY c.id
+            "#,
+        )
+        .bind(user_id)
+        .fetch_all(&self.pg)
+        .await?;
+
+        Ok(chats)
+    }
+
+    /// Check if the user is a member of the chat
+    pub async fn is_chat_member(&self, chat_id: i64, user_id: i64) -> Result<bool, AppErr> {
+        let is_member = sqlx::query_scalar(
+            "SELECT EXISTS(SELECT 1 FROM t_chat WHERE id = $1 AND $2 = ANY(members))",
+        )
+        .bind(chat_id)
+        .bind(user_id)
+        .fetch_one(&self.pg)
+        .await?;
+
+        Ok(is_member)
+    }
+
+    /// Return an error if the user is not a member of the chat
+    pub async fn ensure_chat_member(&self, chat_id: i64, user_id: i64) -> Result<(), AppErr> {
+        if !self.is_chat_member(chat_id, user_id).await? {
+            return Err(AppErr::PermissionDeniedErr(format!(
+                "user {} is not a member of chat {}",
+                user_id, chat_id
+            )));
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,242 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::prelude::FromRow;
+
+use tracing::warn;
+
+use crate::{notif::AppEvent, AppErr, AppState};
+
+// drafts are pushed to other devices through pg_notify, whose payload is limited to 8000 bytes,
+// the rest is left to the other fields of the notification
+const MAX_DRAFT_BYTES: usize = 6000;
+// the device id is notified along with the draft
+const MAX_DEVICE_ID_LEN: usize = 64;
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Draft {
+    pub chat_id: i64,
+    #[sqlx(try_from = "i64")]
+    pub thread_id: ThreadId,
+    pub content: String,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Thread of a draft, stored as 0 when the draft does not belong to a thread
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ThreadId(Option<i64>);
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SaveDraft {
+    pub thread_id: Option<i64>,
+    #[serde(default)]
+    pub content: String,
+    /// Client side edit time, the latest write wins, times in the future count as now
+    pub updated_at: Option<DateTime<Utc>>,
+    /// Device which made the change, it will not be notified
+    pub device_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GetDraft {
+    pub thread_id: Option<i64>,
+}
+
+impl AppState {
+    /// Save a draft unless a newer one already exists, return the winning draft.
+    /// Clearing a draft is saving an empty one, so that an older save cannot bring it back.
+    pub async fn save_draft(
+        &self,
+        input: SaveDraft,
+        chat_id: i64,
+        user_id: i64,
+    ) -> Result<Draft, AppErr> {
+        // measured as sent, escaping makes quotes and control characters longer
+        if serde_json::to_string(&input.content)?.len() > MAX_DRAFT_BYTES {
+            return Err(AppErr::InvalidInputErr(format!(
+                "draft is longer than {} bytes",
+                MAX_DRAFT_BYTES
+            )));
+        }
+        if input
+            .device_id
+            .as_ref()
+            .is_some_and(|id| id.len() > MAX_DEVICE_ID_LEN)
+        {
+            return Err(AppErr::InvalidInputErr(format!(
+                "device id is longer than {} bytes",
+                MAX_DEVICE_ID_LEN
+            )));
+        }
+
+        self.ensure_chat_member(chat_id, user_id).await?;
+
+        let thread_id = ThreadId(input.thread_id);
+        // a clock ahead would otherwise keep later saves of other devices from winning
+        let now = Utc::now();
+        let updated_at = input
+            .updated_at
+            .map_or(now, |updated_at| updated_at.min(now));
+
+        let saved: Option<Draft> = sqlx::query_as(
+            r#"
+            INSERT INTO t_draft (user_id, chat_id, thread_id, content, updated_at)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (user_id, chat_id, thread_id) DO UPDATE
+                SET content = EXCLUDED.content, updated_at = EXCLUDED.updated_at
+                WHERE t_draft.updated_at < EXCLUDED.updated_at
+            RETURNING chat_id, thread_id, content, updated_at
+            "#,
+        )
+        .bind(user_id)
+        .bind(chat_id)
+        .bind(i64::from(thread_id))
+        .bind(&input.content)
+        .bind(updated_at)
+        .fetch_optional(&self.pg)
+        .await?;
+
+        match saved {
+            Some(draft) => {
+                // the draft is saved, other devices get it on the next fetch otherwise
+                if let Err(e) = self
+                    .notify_users(
+                        &[user_id],
+                        input.device_id.as_deref(),
+                        AppEvent::DraftUpdated(draft.clone()),
+                    )
+                    .await
+                {
+                    warn!("failed to notify draft of chat {}: {}", chat_id, e);
+                }
+                Ok(draft)
+            }
+            // a newer draft exists
+            None => self.fetch_draft(chat_id, thread_id, user_id).await,
+        }
+    }
+
+    /// Get the pending draft of a chat
+    pub async fn get_draft(
+        &self,
+        input: GetDraft,
+        chat_id: i64,
+        user_id: i64,
+    ) -> Result<Draft, AppErr> {
+        let draft = self
+            .fetch_draft(chat_id, ThreadId(input.thread_id), user_id)
+            .await?;
+
+        if draft.content.is_empty() {
+            return Err(AppErr::NotFoundErr(format!(
+                "draft of chat {} not found",
+                chat_id
+            )));
+        }
+
+        Ok(draft)
+    }
+
+    async fn fetch_draft(
+        &self,
+        chat_id: i64,
+        thread_id: ThreadId,
+        user_id: i64,
+    ) -> Result<Draft, AppErr> {
+        let draft: Option<Draft> = sqlx::query_as(
+            "SELECT chat_id, thread_id, content, updated_at FROM t_draft WHERE user_id = $1 AND chat_id = $2 AND thread_id = $3",
+        )
+        .bind(user_id)
+        .bind(chat_id)
+        .bind(i64::from(thread_id))
+        .fetch_optional(&self.pg)
+        .await?;
+
+        draft.ok_or_else(|| AppErr::NotFoundErr(format!("draft of chat {} not found", chat_id)))
+    }
+}
+
+impl From<ThreadId> for i64 {
+    fn from(thread_id: ThreadId) -> Self {
+        thread_id.0.unwrap_or_default()
+    }
+}
+
+impl From<i64> for ThreadId {
+    fn from(id: i64) -> Self {
+        Self(Some(id).filter(|id| *id != 0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn test_save_draft() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let now = Utc::now() - chrono::Duration::seconds(10);
+        let save = |content: &str, updated_at| SaveDraft {
+            thread_id: None,
+            content: content.to_string(),
+            updated_at: Some(updated_at),
+            device_id: None,
+        };
+        let get = || GetDraft { thread_id: None };
+
+        state.save_draft(save("hello", now), 1, 1).await?;
+        assert_eq!(state.get_draft(get(), 1, 1).await?.content, "hello");
+        // an older save loses
+        let draft = state
+            .save_draft(save("stale", now - chrono::Duration::seconds(1)), 1, 1)
+            .await?;
+        assert_eq!(draft.content, "hello");
+
+        // quotes are escaped when notified
+        let quotes = "\"".repeat(MAX_DRAFT_BYTES / 2);
+        assert!(matches!(
+            state.save_draft(save(&quotes, now), 1, 1).await,
+            Err(AppErr::InvalidInputErr(_))
+        ));
+        let long = "文".repeat(MAX_DRAFT_BYTES / 3 - 1);
+        state
+            .save_draft(save(&long, now + chrono::Duration::seconds(1)), 1, 1)
+            .await?;
+
+        // cleared
+        state
+            .save_draft(save("", now + chrono::Duration::seconds(2)), 1, 1)
+            .await?;
+        assert!(matches!(
+            state.get_draft(get(), 1, 1).await,
+            Err(AppErr::NotFoundErr(_))
+        ));
+        // not a member of chat 2
+        assert!(state.save_draft(save("hi", now), 2, 2).await.is_err());
+
+        // a save from the future does not pin the draft
+        let future = DateTime::parse_from_rfc3339("9999-01-01T00:00:00Z")?.to_utc();
+        let draft = state.save_draft(save("pinned", future), 1, 1).await?;
+        assert!(draft.updated_at <= Utc::now());
+        let later = SaveDraft {
+            updated_at: None,
+            ..save("later", now)
+        };
+        assert_eq!(state.save_draft(later, 1, 1).await?.content, "later");
+
+        let device = SaveDraft {
+            device_id: Some("d".repeat(MAX_DEVICE_ID_LEN + 1)),
+            ..save("device", Utc::now())
+        };
+        assert!(matches!(
+            state.save_draft(device, 1, 1).await,
+            Err(AppErr::InvalidInputErr(_))
+        ));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,300 @@
+use chrono::{Duration, Utc};
+use serde::Deserialize;
+use sqlx::PgConnection;
+use tracing::info;
+
+use crate::{
+    mailer::Mail,
+    util::{generate_token, hash_token},
+    AppErr, AppState,
+};
+
+use super::User;
+
+// verification links expire in 24 hours
+const VERIFICATION_EXPIRATION_TIME: i64 = 24 * 60 * 60;
+// at most one mail per minute and five per day for each user
+const RESEND_INTERVAL: i64 = 60;
+const MAX_SENDS_PER_DAY: i64 = 5;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VerifyEmailForm {
+    pub token: String,
+}
+
+impl AppState {
+    /// Mail a verification link to the user
+    pub async fn send_verification_email(&self, user: &User) -> Result<(), AppErr> {
+        let token = insert_verification(&mut *self.pg.acquire().await?, user.id).await?;
+        self.mail_verification(user, &token).await
+    }
+
+    /// Mail a new verification link, limited by `RESEND_INTERVAL` and `MAX_SENDS_PER_DAY`
+    pub async fn resend_verification_email(&self, user_id: i64) -> Result<(), AppErr> {
+        // the user is locked until the link is recorded, so that concurrent resends are counted
+        let mut tx = self.pg.begin().await?;
+        let user: Option<User> = sqlx::query_as(
+            "SELECT id, username, passwd, email, avatar, email_verified_at, created_at, updated_at FROM t_user WHERE id = $1 FOR UPDATE",
+        )
+        .bind(user_id)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some(user) = user else {
+            return Err(AppErr::NotFoundErr(format!("user {} not found", user_id)));
+        };
+        if user.email_verified_at.is_some() {
+            return Err(AppErr::ConflictErr(format!(
+                "email of user {} is already verified",
+                user_id
+            )));
+        }
+
+        let (recent, today): (i64, i64) = sqlx::query_as(
+            r#"
+            SELECT
+                COUNT(*) FILTER (WHERE created_at > NOW() - make_interval(secs => $2)),
+                COUNT(*) FILTER (WHERE created_at > NOW() - INTERVAL '1 day')
+            FROM t_email_verification WHERE user_id = $1
+            "#,
+        )
+        .bind(user_id)
+        .bind(RESEND_INTERVAL as f64)
+        .fetch_one(&mut *tx)
+        .await?;
+        if recent > 0 {
+            return Err(AppErr::TooManyRequestsErr(format!(
+                "verification email can be resent once every {} seconds",
+                RESEND_INTERVAL
+            )));
+        }
+        if today >= MAX_SENDS_PER_DAY {
+            return Err(AppErr::TooManyRequestsErr(format!(
+                "verification email can be sent {} times a day",
+                MAX_SENDS_PER_DAY
+            )));
+        }
+
+        let token = insert_verification(&mut tx, user_id).await?;
+        tx.commit().await?;
+
+        self.mail_verification(&user, &token).await
+    }
+
+    async fn mail_verification(&self, user: &User, token: &str) -> Result<(), AppErr> {
+        let mail = verification_mail(user, &self.config.mail.base_url, token);
+        self.mailer.send(&mail).await?;
+        info!("verification email is sent to user {}", user.id);
+
+        Ok(())
+    }
+
+    /// Mark the email of the token's user as verified, other pending links are invalidated.
+    /// Access tokens issued before carry the unverified state until they are refreshed.
+    pub async fn verify_email(&self, token: &str) -> Result<(), AppErr> {
+        let mut tx = self.pg.begin().await?;
+
+        let user_id: Option<i64> = sqlx::query_scalar(
+            r#"
+            UPDATE t_email_verification SET used_at = NOW()
+            WHERE token_hash = $1 AND used_at IS NULL AND expires_at > NOW()
+            RETURNING user_id
+            "#,
+        )
+        .bind(hash_token(token))
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some(user_id) = user_id else {
+            return Err(AppErr::InvalidInputErr(
+                "invalid or expired verification token".to_string(),
+            ));
+        };
+
+        sqlx::query(
+            "UPDATE t_email_verification SET used_at = NOW() WHERE user_id = $1 AND used_at IS NULL",
+        )
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query(
+            "UPDATE t_user SET email_verified_at = NOW() WHERE id = $1 AND email_verified_at IS NULL",
+        )
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        info!("email of user {} is verified", user_id);
+
+        Ok(())
+    }
+
+    /// Delete verifications older than a day, they are expired and no longer count for the rate limit
+    pub async fn purge_email_verifications(&self) -> Result<(), AppErr> {
+        sqlx::query(
+            "DELETE FROM t_email_verification WHERE created_at <= NOW() - INTERVAL '1 day' AND expires_at <= NOW()",
+        )
+        .execute(&self.pg)
+        .await?;
+
+        Ok(())
+    }
+}
+
+async fn insert_verification(conn: &mut PgConnection, user_id: i64) -> Result<String, AppErr> {
+    let token = generate_token();
+    sqlx::query(
+        "INSERT INTO t_email_verification (user_id, token_hash, expires_at) VALUES ($1, $2, $3)",
+    )
+    .bind(user_id)
+    .bind(hash_token(&token))
+    .bind(Utc::now() + Duration::seconds(VERIFICATION_EXPIRATION_TIME))
+    .execute(conn)
+    .await?;
+
+    Ok(token)
+}
+
+fn verification_mail(user: &User, base_url: &str, token: &str) -> Mail {
+    let link = format!(
+        "{}/verify-email?token={}",
+        base_url.trim_end_matches('/'),
+        token
+    );
+    let body = format!(
+        "Hi {},\n\n\
+        Please confirm your email address by opening the link below, it expires in 24 hours.\n\n\
+        {}\n\n\
+        If you did not sign up for easy-chat, you can ignore this email.\n",
+        user.username, link
+    );
+
+    Mail {
+        to: user.email.clone(),
+        subject: "Verify your email address".to_string(),
+        body,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        init_app,
+        model::{SessionUser, SignUpForm},
+    };
+    use anyhow::Result;
+    use axum::{
+        body::Body,
+        http::{header, Request, StatusCode},
+    };
+    use tower::ServiceExt;
+
+    #[test]
+    fn test_verification_mail() {
+        let user = User::new_for_test(1, "foo".to_string(), "foo@acme.com".to_string());
+        let mail = verification_mail(&user, "https://chat.acme.com/", "abc");
+
+        assert_eq!(mail.to, "foo@acme.com");
+        assert!(mail.body.starts_with("Hi foo,"));
+        assert!(mail
+            .body
+            .contains("https://chat.acme.com/verify-email?token=abc\n"));
+    }
+
+    async fn sends(state: &AppState, user_id: i64) -> Result<i64> {
+        let count =
+            sqlx::query_scalar("SELECT COUNT(*) FROM t_email_verification WHERE user_id = $1")
+                .bind(user_id)
+                .fetch_one(&state.pg)
+                .await?;
+        Ok(count)
+    }
+
+    #[tokio::test]
+    async fn test_verify_email() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let form = SignUpForm {
+            username: "erin".to_string(),
+            email: "erin@acme.com".to_string(),
+            passwd: "erin12345".to_string(),
+            device_name: None,
+        };
+        let user = state.create_user(form).await?;
+        let usage = |verified| {
+            let user =
+                SessionUser::new(user.id, user.username.clone(), user.email.clone(), verified);
+            let token = state.ek.sign(user, "test-session").unwrap();
+            Request::get("/api/usage")
+                .header(header::AUTHORIZATION, format!("Bearer {}", token))
+                .body(Body::empty())
+                .unwrap()
+        };
+        let app = init_app(state.clone()).await?;
+        let res = app.clone().oneshot(usage(false)).await?;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        // concurrent resends send a single mail
+        let resends: Vec<_> = (0..5)
+            .map(|_| {
+                let state = state.clone();
+                tokio::spawn(async move { state.resend_verification_email(user.id).await })
+            })
+            .collect();
+        let mut sent = 0;
+        for resend in resends {
+            match resend.await? {
+                Ok(()) => sent += 1,
+                Err(e) => assert!(matches!(e, AppErr::TooManyRequestsErr(_))),
+            }
+        }
+        assert_eq!(sent, 1);
+        assert_eq!(sends(&state, user.id).await?, 1);
+
+        // a day's worth of links sent earlier than the resend interval
+        sqlx::query(
+            r#"
+            INSERT INTO t_email_verification (user_id, token_hash, expires_at)
+            SELECT $1, md5(i::TEXT) || md5(i::TEXT), NOW() FROM generate_series(1, $2) i
+            "#,
+        )
+        .bind(user.id)
+        .bind(MAX_SENDS_PER_DAY as i32)
+        .execute(&state.pg)
+        .await?;
+        sqlx::query("UPDATE t_email_verification SET created_at = NOW() - INTERVAL '2 minutes' WHERE user_id = $1")
+            .bind(user.id)
+            .execute(&state.pg)
+            .await?;
+        assert!(matches!(
+            state.resend_verification_email(user.id).await,
+            Err(AppErr::TooManyRequestsErr(_))
+        ));
+
+        sqlx::query(
+            "INSERT INTO t_email_verification (user_id, token_hash, expires_at) VALUES ($1, $2, NOW() + INTERVAL '1 hour')",
+        )
+        .bind(user.id)
+        .bind(hash_token("known"))
+        .execute(&state.pg)
+        .await?;
+        assert!(matches!(
+            state.verify_email("unknown").await,
+            Err(AppErr::InvalidInputErr(_))
+        ));
+        state.verify_email("known").await?;
+        // the link is used once
+        assert!(matches!(
+            state.verify_email("known").await,
+            Err(AppErr::InvalidInputErr(_))
+        ));
+        assert!(matches!(
+            state.resend_verification_email(user.id).await,
+            Err(AppErr::ConflictErr(_))
+        ));
+        let res = app.oneshot(usage(true)).await?;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,748 @@
+use std::{path::Path, str::FromStr};
+
+use axum::extract::multipart::Field;
+use chat_core::StorageBackend;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::{
+    fs,
+    io::{AsyncReadExt, AsyncWriteExt},
+};
+use tokio_util::io::StreamReader;
+use tracing::{info, warn};
+
+use crate::{
+    media::{
+        is_allowed, is_strippable, keeps_metadata, sniff_file, strip_metadata, Sniffed,
+        MAX_STRIP_SIZE,
+    },
+    scanner::ScanResult,
+    storage::{blob_key, new_storage, thumbnail_key},
+    util::SIGNED_URL_EXPIRATION_TIME,
+    AppErr, AppState,
+};
+
+use super::quota::lock_blob;
+
+const FILE_URL_PREFIX: &str = "/files/";
+const SIGNED_URL_PREFIX: &str = "/download/";
+const DEFAULT_EXT: &str = "bin";
+const MAX_EXT_LEN: usize = 16;
+// filename and signature columns of t_quarantine
+const MAX_QUARANTINE_FIELD_LEN: usize = 255;
+
+/// Reference to a file posted in a chat, e.g. `/files/1/{sha256}.png`.
+/// The blob is content addressed so that identical uploads are stored once.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChatFile {
+    pub chat_id: i64,
+    pub hash: String,
+    pub ext: String,
+}
+
+impl ChatFile {
+    pub fn new(chat_id: i64, hash: String, filename: &str) -> Self {
+        let ext = declared_ext(filename).unwrap_or_else(|| DEFAULT_EXT.to_string());
+
+        Self { chat_id, hash, ext }
+    }
+
+    pub fn url(&self) -> String {
+        format!(
+            "{}{}/{}.{}",
+            FILE_URL_PREFIX, self.chat_id, self.hash, self.ext
+        )
+    }
+
+    /// Storage key of the blob, shared by all references of the same content
+    pub fn key(&self) -> String {
+        blob_key(&self.hash)
+    }
+
+    /// Reference of the file or one of its thumbnails
+    pub fn variant_url(&self, size: Option<u32>) -> String {
+        match size {
+            Some(size) => format!("{}?size={}", self.url(), size),
+            None => self.url(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SignFileUrls {
+    pub files: Vec<String>,
+    /// Sign the thumbnails of this size instead of the original images
+    pub size: Option<u32>,
+}
+
+/// Download url which is valid without a session token until `expires_at`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SignedFileUrl {
+    pub file: String,
+    pub url: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl FromStr for ChatFile {
+    type Err = AppErr;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || AppErr::InvalidInputErr(format!("invalid file reference: {}", s));
+
+        let (chat_id, name) = s
+            .strip_prefix(FILE_URL_PREFIX)
+            .and_then(|s| s.split_once('/'))
+            .ok_or_else(invalid)?;
+        let (hash, ext) = name.split_once('.').ok_or_else(invalid)?;
+
+        let chat_id = chat_id.parse().map_err(|_| invalid())?;
+        if hash.len() != 64 || !hash.bytes().all(|b| b.is_ascii_hexdigit()) || !is_valid_ext(ext) {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            chat_id,
+            hash: hash.to_lowercase(),
+            ext: ext.to_string(),
+        })
+    }
+}
+
+/// Lowercase extension of the filename given by the client
+fn declared_ext(filename: &str) -> Option<String> {
+    Path::new(filename)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_lowercase())
+        .filter(|ext| is_valid_ext(ext))
+}
+
+/// At most `max` characters of the string
+fn truncate(s: &str, max: usize) -> &str {
+    s.char_indices().nth(max).map_or(s, |(i, _)| &s[..i])
+}
+
+fn is_valid_ext(ext: &str) -> bool {
+    !ext.is_empty() && ext.len() <= MAX_EXT_LEN && ext.bytes().all(|b| b.is_ascii_alphanumeric())
+}
+
+impl AppState {
+    /// Stream a multipart field to a temp file, then store it
+    pub async fn save_file(
+        &self,
+        chat_id: i64,
+        uploader_id: i64,
+        filename: &str,
+        field: Field<'_>,
+    ) -> Result<ChatFile, AppErr> {
+        let quota = self.remaining_quota(uploader_id, chat_id).await?;
+        let tmp_dir = self.config.storage.base_dir.join("tmp");
+        fs::create_dir_all(&tmp_dir).await?;
+
+        let tmp_path = tmp_dir.join(uuid::Uuid::now_v7().to_string());
+        let ret = async {
+            self.write_tmp_file(&tmp_path, field, quota).await?;
+            self.store_file(&tmp_path, filename, chat_id, uploader_id)
+                .await
+        }
+        .await;
+        let _ = fs::remove_file(&tmp_path).await;
+
+        ret
+    }
+
+    /// Check and store a local file under its content hash and record it in the chat,
+    /// the size counts towards the quota of the uploader.
+    /// The file may be rewritten to strip metadata, or moved away if the scanner flags it.
+    pub(crate) async fn store_file(
+        &self,
+        path: &Path,
+        filename: &str,
+        chat_id: i64,
+        uploader_id: i64,
+    ) -> Result<ChatFile, AppErr> {
+        let sniffed = self
+            .check_file(path, filename, chat_id, uploader_id)
+            .await?;
+        let (hash, size) = hash_file(path).await?;
+        let file = ChatFile {
+            chat_id,
+            hash,
+            ext: sniffed.ext_for(declared_ext(filename).as_deref()),
+        };
+
+        // the quota is reserved before storing, so that a rejected file leaves no blob behind,
+        // and the blob is stored under the lock of its hash, so that a release does not delete it
+        let mut tx = self.pg.begin().await?;
+        lock_blob(&mut tx, &file.hash).await?;
+        let inserted: Option<i64> = sqlx::query_scalar(
+            r#"
+            INSERT INTO t_file (chat_id, uploader_id, hash, ext, size) VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (chat_id, hash, ext, uploader_id) DO NOTHING
+            RETURNING id
+            "#,
+        )
+        .bind(chat_id)
+        .bind(uploader_id)
+        .bind(&file.hash)
+        .bind(&file.ext)
+        .bind(size as i64)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        if inserted.is_some() {
+            self.add_usage(&mut tx, uploader_id, chat_id, size as i64)
+                .await?;
+        }
+        let key = file.key();
+        if !self.storage.exists(&key).await? {
+            self.storage.put(&key, path).await?;
+        }
+        tx.commit().await?;
+
+        if let Err(e) = self.process_image(&file, path).await {
+            if let Some(id) = inserted {
+                self.unreserve_file(id, chat_id, uploader_id, size as i64)
+                    .await?;
+                // kept if another record refers to it
+                self.delete_blob(&file.hash).await?;
+            }
+            return Err(e);
+        }
+
+        Ok(file)
+    }
+
+    /// Remove the record of a file which failed to be stored and give back its quota
+    async fn unreserve_file(
+        &self,
+        id: i64,
+        chat_id: i64,
+        uploader_id: i64,
+        size: i64,
+    ) -> Result<(), AppErr> {
+        let mut tx = self.pg.begin().await?;
+        sqlx::query("DELETE FROM t_file WHERE id = $1")
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        self.add_usage(&mut tx, uploader_id, chat_id, -size).await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Reject files whose content is not allowed, strip image metadata and scan the file
+    async fn check_file(
+        &self,
+        path: &Path,
+        filename: &str,
+        chat_id: i64,
+        uploader_id: i64,
+    ) -> Result<Sniffed, AppErr> {
+        let sniffed = sniff_file(path).await?;
+        if !is_allowed(sniffed.mime, &self.config.storage.allowed_types) {
+            return Err(AppErr::UnsupportedFileTypeErr(format!(
+                "{} is not allowed",
+                sniffed.mime
+            )));
+        }
+        // e.g. the location of HEIC photos would be published with them
+        if keeps_metadata(sniffed.mime) {
+            return Err(AppErr::UnsupportedFileTypeErr(format!(
+                "metadata of {} images can not be removed, convert them to JPEG, PNG or WebP",
+                sniffed.mime
+            )));
+        }
+
+        if is_strippable(sniffed.mime) {
+            let size = fs::metadata(path).await?.len();
+            if size > MAX_STRIP_SIZE {
+                return Err(AppErr::PayloadTooLargeErr(format!(
+                    "{} images are limited to {} bytes",
+                    sniffed.mime, MAX_STRIP_SIZE
+                )));
+            }
+            if let Some(stripped) = strip_metadata(&fs::read(path).await?, sniffed.mime) {
+                fs::write(path, stripped?).await?;
+            }
+        }
+
+        let Some(scanner) = &self.scanner else {
+            return Ok(sniffed);
+        };
+        let ScanResult::Infected(signature) = scanner.scan(path).await? else {
+            return Ok(sniffed);
+        };
+
+        let quarantine_dir = self.config.storage.base_dir.join("quarantine");
+        fs::create_dir_all(&quarantine_dir).await?;
+        let quarantine_path = quarantine_dir.join(uuid::Uuid::now_v7().to_string());
+
+        // recorded before the file is moved, so that no quarantined file is left without a record
+        let mut tx = self.pg.begin().await?;
+        sqlx::query(
+            r#"
+            INSERT INTO t_quarantine (chat_id, uploader_id, filename, signature, path)
+            VALUES ($1, $2, $3, $4, $5)
+            "#,
+        )
+        .bind(chat_id)
+        .bind(uploader_id)
+        .bind(truncate(filename, MAX_QUARANTINE_FIELD_LEN))
+        .bind(truncate(&signature, MAX_QUARANTINE_FIELD_LEN))
+        .bind(quarantine_path.to_string_lossy().as_ref())
+        .execute(&mut *tx)
+        .await?;
+        fs::rename(path, &quarantine_path).await?;
+        tx.commit().await?;
+        warn!(
+            "quarantined {} of user {} in chat {}: {}",
+            filename, uploader_id, chat_id, signature
+        );
+
+        Err(AppErr::FileQuarantinedErr(format!(
+            "{} is flagged as {}",
+            filename, signature
+        )))
+    }
+
+    async fn write_tmp_file(
+        &self,
+        tmp_path: &Path,
+        mut field: Field<'_>,
+        quota: Option<u64>,
+    ) -> Result<(), AppErr> {
+        let max_size = self.config.storage.max_file_size;
+
+        let mut file = fs::File::create(tmp_path).await?;
+        let mut size = 0;
+
+        while let Some(chunk) = field.chunk().await? {
+            size += chunk.len() as u64;
+            if size > max_size {
+                return Err(AppErr::PayloadTooLargeErr(format!(
+                    "file is larger than {} bytes",
+                    max_size
+                )));
+            }
+            if let Some(quota) = quota.filter(|quota| size > *quota) {
+                return Err(AppErr::QuotaExceededErr(format!(
+                    "file is larger than the remaining {} bytes",
+                    quota
+                )));
+            }
+
+            file.write_all(&chunk).await?;
+        }
+        file.flush().await?;
+
+        Ok(())
+    }
+
+    /// Return an error if the file was not posted in its chat or the user is not a member of it
+    pub async fn ensure_file_access(&self, file: &ChatFile, user_id: i64) -> Result<(), AppErr> {
+        self.ensure_chat_member(file.chat_id, user_id).await?;
+
+        if !self.file_exists(file).await? {
+            return Err(AppErr::NotFoundErr(format!(
+                "file {} not found",
+                file.url()
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Check the file references of a message, all of them must be uploaded to the chat
+    pub async fn verify_chat_files(&self, chat_id: i64, urls: &[String]) -> Result<(), AppErr> {
+        for url in urls {
+            let file: ChatFile = url.parse()?;
+            if file.chat_id != chat_id {
+                return Err(AppErr::InvalidInputErr(format!(
+                    "file {} is not uploaded to chat {}",
+                    url, chat_id
+                )));
+            }
+
+            if !self.file_exists(&file).await? {
+                return Err(AppErr::NotFoundErr(format!("file {} not found", url)));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Issue signed download urls of files the user has access to
+    pub async fn sign_file_urls(
+        &self,
+        input: SignFileUrls,
+        chat_id: i64,
+        user_id: i64,
+    ) -> Result<Vec<SignedFileUrl>, AppErr> {
+        self.ensure_chat_member(chat_id, user_id).await?;
+
+        let expires = Utc::now().timestamp() + SIGNED_URL_EXPIRATION_TIME;
+        let expires_at = DateTime::from_timestamp(expires, 0).unwrap_or_default();
+
+        let mut signed = Vec::with_capacity(input.files.len());
+        for url in &input.files {
+            let file: ChatFile = url.parse()?;
+            if file.chat_id != chat_id || !self.file_exists(&file).await? {
+                return Err(AppErr::NotFoundErr(format!("file {} not found", url)));
+            }
+            if let Some(size) = input.size {
+                self.ensure_thumbnail(&file, size).await?;
+            }
+
+            let resource = file.variant_url(input.size);
+            let sig = self.url_signer.sign(&resource, expires);
+            let mut query = format!("expires={}&sig={}", expires, sig);
+            if let Some(size) = input.size {
+                query = format!("size={}&{}", size, query);
+            }
+
+            signed.push(SignedFileUrl {
+                file: resource,
+                url: format!(
+                    "{}{}/{}.{}?{}",
+                    SIGNED_URL_PREFIX, file.chat_id, file.hash, file.ext, query
+                ),
+                expires_at,
+            });
+        }
+
+        Ok(signed)
+    }
+
+    /// Return an error unless the signature of the file is valid and has not expired
+    pub async fn verify_signed_file(
+        &self,
+        file: &ChatFile,
+        size: Option<u32>,
+        expires: i64,
+        sig: &str,
+    ) -> Result<(), AppErr> {
+        let resource = file.variant_url(size);
+        if !self
+            .url_signer
+            .verify(&resource, expires, sig, Utc::now().timestamp())
+        {
+            return Err(AppErr::PermissionDeniedErr(format!(
+                "invalid or expired signature of file {}",
+                resource
+            )));
+        }
+
+        if !self.file_exists(file).await? {
+            return Err(AppErr::NotFoundErr(format!("file {} not found", resource)));
+        }
+
+        Ok(())
+    }
+
+    async fn file_exists(&self, file: &ChatFile) -> Result<bool, AppErr> {
+        let exists = sqlx::query_scalar(
+            "SELECT EXISTS(SELECT 1 FROM t_file WHERE chat_id = $1 AND hash = $2 AND ext = $3)",
+        )
+        .bind(file.chat_id)
+        .bind(&file.hash)
+        .bind(&file.ext)
+        .fetch_one(&self.pg)
+        .await?;
+
+        Ok(exists)
+    }
+
+    /// Make the files of a forwarded message available in the target chat, return the new references.
+    /// The copies count towards the quota of the forwarder.
+    pub async fn copy_chat_files(
+        &self,
+        urls: &[String],
+        chat_id: i64,
+        uploader_id: i64,
+    ) -> Result<Vec<String>, AppErr> {
+        let mut copied = Vec::with_capacity(urls.len());
+        let mut tx = self.pg.begin().await?;
+
+        for url in urls {
+            let file: ChatFile = url.parse()?;
+            let target = ChatFile {
+                chat_id,
+                ..file.clone()
+            };
+
+            let size: Option<i64> = sqlx::query_scalar(
+                r#"
+                INSERT INTO t_file (chat_id, uploader_id, hash, ext, size)
+                SELECT $1, $2, hash, ext, size FROM t_file WHERE chat_id = $3 AND hash = $4 AND ext = $5
+                LIMIT 1
+                ON CONFLICT (chat_id, hash, ext, uploader_id) DO NOTHING
+                RETURNING size
+                "#,
+            )
+            .bind(chat_id)
+            .bind(uploader_id)
+            .bind(file.chat_id)
+            .bind(&file.hash)
+            .bind(&file.ext)
+            .fetch_optional(&mut *tx)
+            .await?;
+
+            if let Some(size) = size {
+                self.add_usage(&mut tx, uploader_id, chat_id, size).await?;
+            }
+            copied.push(target.url());
+        }
+        tx.commit().await?;
+
+        Ok(copied)
+    }
+
+    /// Copy all blobs, thumbnails and avatars from the configured backend to the target one,
+    /// return the number of copied objects
+    pub async fn migrate_storage(
+        &self,
+        target: &StorageBackend,
+        delete_source: bool,
+    ) -> Result<u64, AppErr> {
+        let target = new_storage(target)?;
+        let hashes: Vec<String> =
+            sqlx::query_scalar("SELECT DISTINCT hash FROM t_file ORDER BY hash")
+                .fetch_all(&self.pg)
+                .await?;
+        let thumbnails: Vec<(String, Vec<i32>)> =
+            sqlx::query_as("SELECT hash, thumbnails FROM t_image ORDER BY hash")
+                .fetch_all(&self.pg)
+                .await?;
+
+        let mut keys: Vec<String> = hashes.iter().map(|hash| blob_key(hash)).collect();
+        for (hash, sizes) in thumbnails {
+            keys.extend(
+                sizes
+                    .into_iter()
+                    .map(|size| thumbnail_key(&hash, size as u32)),
+            );
+        }
+        keys.extend(self.avatar_keys().await?);
+
+        let tmp_dir = self.config.storage.base_dir.join("tmp");
+        fs::create_dir_all(&tmp_dir).await?;
+
+        let mut copied = 0;
+        for key in keys {
+            if !target.exists(&key).await? {
+                let Some(object) = self.storage.get(&key).await? else {
+                    warn!("blob {} is missing in source storage", key);
+                    continue;
+                };
+
+                let tmp_path = tmp_dir.join(uuid::Uuid::now_v7().to_string());
+                let ret = async {
+                    let mut file = fs::File::create(&tmp_path).await?;
+                    tokio::io::copy(&mut StreamReader::new(object.stream), &mut file).await?;
+                    file.flush().await?;
+                    target.put(&key, &tmp_path).await
+                }
+                .await;
+                let _ = fs::remove_file(&tmp_path).await;
+                ret?;
+
+                copied += 1;
+                info!("copied blob {}", key);
+            }
+
+            if delete_source {
+                self.storage.delete(&key).await?;
+            }
+        }
+
+        Ok(copied)
+    }
+}
+
+/// SHA-256 in hex and size of a local file
+pub(crate) async fn hash_file(path: &Path) -> Result<(String, u64), AppErr> {
+    let mut file = fs::File::open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; 64 * 1024];
+    let mut size = 0;
+
+    loop {
+        let n = file.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        size += n as u64;
+    }
+
+    Ok((hex::encode(hasher.finalize()), size))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use axum::{http::StatusCode, response::IntoResponse};
+
+    const HASH: &str = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08";
+
+    #[test]
+    fn test_chat_file_url_and_key() {
+        let file = ChatFile::new(1, HASH.to_string(), "test.PNG");
+        assert_eq!(file.ext, "png");
+        assert_eq!(file.url(), format!("/files/1/{}.png", HASH));
+        assert_eq!(file.key(), format!("9f/86/{}", HASH));
+
+        let file = ChatFile::new(1, HASH.to_string(), "no_ext");
+        assert_eq!(file.ext, "bin");
+    }
+
+    #[test]
+    fn test_parse_chat_file() {
+        let file: ChatFile = format!("/files/1/{}.png", HASH).parse().unwrap();
+        assert_eq!(file, ChatFile::new(1, HASH.to_string(), "a.png"));
+
+        assert!("/files/1/abc.png".parse::<ChatFile>().is_err());
+        assert!(format!("/files/x/{}.png", HASH)
+            .parse::<ChatFile>()
+            .is_err());
+        assert!(format!("/files/1/{}.p/g", HASH)
+            .parse::<ChatFile>()
+            .is_err());
+    }
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!(truncate("abc", 5), "abc");
+        assert_eq!(truncate("abc", 3), "abc");
+        assert_eq!(truncate("文件名.png", 2), "文件");
+    }
+
+    #[tokio::test]
+    async fn test_store_file_over_quota() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let limit = state.config.storage.quota.user_bytes.unwrap() as i64;
+        let dir = state.config.storage.base_dir.join("tmp");
+        fs::create_dir_all(&dir).await?;
+        let path = dir.join(uuid::Uuid::now_v7().to_string());
+        fs::write(&path, format!("quota test {}", uuid::Uuid::now_v7())).await?;
+        let (hash, size) = hash_file(&path).await?;
+
+        // one byte short of the file
+        sqlx::query("INSERT INTO t_storage_usage (scope, scope_id, bytes) VALUES ('user', 1, $1)")
+            .bind(limit - size as i64 + 1)
+            .execute(&state.pg)
+            .await?;
+        let ret = state.store_file(&path, "a.txt", 1, 1).await;
+        assert!(matches!(ret, Err(AppErr::QuotaExceededErr(_))));
+        // not a server fault which clients would retry
+        let res = ret.unwrap_err().into_response();
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert!(!state.storage.exists(&blob_key(&hash)).await?);
+        let usage = state.storage_usage(1).await?;
+        assert_eq!(usage.user.used, limit - size as i64 + 1);
+
+        sqlx::query("UPDATE t_storage_usage SET bytes = $1 WHERE scope = 'user' AND scope_id = 1")
+            .bind(limit - size as i64)
+            .execute(&state.pg)
+            .await?;
+        let file = state.store_file(&path, "a.txt", 1, 1).await?;
+        assert!(state.storage.exists(&file.key()).await?);
+        assert_eq!(state.storage_usage(1).await?.user.used, limit);
+
+        state.storage.delete(&file.key()).await?;
+        fs::remove_file(&path).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_release_and_reupload_same_blob() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let dir = state.config.storage.base_dir.join("tmp");
+        fs::create_dir_all(&dir).await?;
+        let path = dir.join(uuid::Uuid::now_v7().to_string());
+        fs::write(&path, format!("race test {}", uuid::Uuid::now_v7())).await?;
+        let file = state.store_file(&path, "a.txt", 1, 1).await?;
+
+        // a release has deleted the record and found no other one under the lock of the hash
+        sqlx::query("DELETE FROM t_file WHERE hash = $1")
+            .bind(&file.hash)
+            .execute(&state.pg)
+            .await?;
+        let mut tx = state.pg.begin().await?;
+        lock_blob(&mut tx, &file.hash).await?;
+
+        // the same content is uploaded again meanwhile
+        let upload = tokio::spawn({
+            let (state, path) = (state.clone(), path.clone());
+            async move { state.store_file(&path, "b.txt", 1, 2).await }
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        assert!(!upload.is_finished());
+
+        // the upload stores the blob again once the release has deleted it
+        state.storage.delete(&file.key()).await?;
+        tx.commit().await?;
+        upload.await??;
+        assert!(state.storage.exists(&file.key()).await?);
+
+        // a release waiting for an upload keeps the blob of the new record
+        let mut tx = state.pg.begin().await?;
+        lock_blob(&mut tx, &file.hash).await?;
+        let release = tokio::spawn({
+            let (state, hash) = (state.clone(), file.hash.clone());
+            async move { state.delete_blob(&hash).await }
+        });
+        sqlx::query("INSERT INTO t_file (chat_id, uploader_id, hash, ext, size) VALUES (2, 3, $1, 'txt', 1)")
+            .bind(&file.hash)
+            .execute(&mut *tx)
+            .await?;
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        assert!(!release.is_finished());
+        tx.commit().await?;
+        release.await??;
+        assert!(state.storage.exists(&file.key()).await?);
+
+        sqlx::query("DELETE FROM t_file WHERE hash = $1")
+            .bind(&file.hash)
+            .execute(&state.pg)
+            .await?;
+        state.delete_blob(&file.hash).await?;
+        assert!(!state.storage.exists(&file.key()).await?);
+
+        fs::remove_file(&path).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_reject_image_keeping_metadata() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let dir = state.config.storage.base_dir.join("tmp");
+        fs::create_dir_all(&dir).await?;
+        let path = dir.join(uuid::Uuid::now_v7().to_string());
+
+        // little endian TIFF whose only entry points to a GPS IFD, as written by cameras
+        let mut tiff = b"II*\0\x08\0\0\0".to_vec();
+        tiff.extend_from_slice(&1u16.to_le_bytes());
+        tiff.extend_from_slice(&0x8825u16.to_le_bytes());
+        tiff.extend_from_slice(&4u16.to_le_bytes());
+        tiff.extend_from_slice(&1u32.to_le_bytes());
+        tiff.extend_from_slice(&26u32.to_le_bytes());
+        tiff.extend_from_slice(&0u32.to_le_bytes());
+        tiff.extend_from_slice(&0u16.to_le_bytes());
+        fs::write(&path, &tiff).await?;
+        let (hash, _) = hash_file(&path).await?;
+
+        let ret = state.store_file(&path, "photo.tif", 1, 1).await;
+        assert!(matches!(ret, Err(AppErr::UnsupportedFileTypeErr(_))));
+        assert!(!state.storage.exists(&blob_key(&hash)).await?);
+
+        fs::remove_file(&path).await?;
+        Ok(())
+    }
+}
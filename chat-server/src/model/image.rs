@@ -0,0 +1,193 @@
+use std::{collections::HashMap, path::Path};
+
+use serde::{Deserialize, Serialize};
+use sqlx::prelude::FromRow;
+use tokio::fs;
+
+use crate::{storage::thumbnail_key, AppErr, AppState};
+
+use super::{ChatFile, Message};
+
+/// Size and preview of an image attachment, so that clients can lay it out before downloading
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImageMeta {
+    pub url: String,
+    pub width: i32,
+    pub height: i32,
+    pub blurhash: String,
+    pub thumbnails: Vec<Thumbnail>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Thumbnail {
+    /// Longest edge in pixels
+    pub size: i32,
+    pub url: String,
+}
+
+#[derive(Debug, FromRow)]
+struct ImageRow {
+    hash: String,
+    width: i32,
+    height: i32,
+    blurhash: String,
+    thumbnails: Vec<i32>,
+}
+
+impl AppState {
+    /// Generate thumbnails and metadata of an uploaded image, once per content
+    pub(crate) async fn process_image(&self, file: &ChatFile, path: &Path) -> Result<(), AppErr> {
+        let mime = mime_guess::from_ext(&file.ext).first_or_octet_stream();
+        if mime.type_() != mime_guess::mime::IMAGE {
+            return Ok(());
+        }
+
+        let exists: bool =
+            sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM t_image WHERE hash = $1)")
+                .bind(&file.hash)
+                .fetch_one(&self.pg)
+                .await?;
+        if exists {
+            return Ok(());
+        }
+
+        let Some(image) = self.image_pool.process(path).await? else {
+            return Ok(());
+        };
+
+        let mut sizes = Vec::with_capacity(image.thumbnails.len());
+        for (size, data) in image.thumbnails {
+            let tmp_path = path.with_extension(format!("{}.jpg", size));
+            fs::write(&tmp_path, data).await?;
+            let ret = self
+                .storage
+                .put(&thumbnail_key(&file.hash, size), &tmp_path)
+                .await;
+            let _ = fs::remove_file(&tmp_path).await;
+            ret?;
+
+            sizes.push(size as i32);
+        }
+
+        sqlx::query(
+            r#"
+            INSERT INTO t_image (hash, width, height, blurhash, thumbnails) VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (hash) DO NOTHING
+            "#,
+        )
+        .bind(&file.hash)
+        .bind(image.width as i32)
+        .bind(image.height as i32)
+        .bind(&image.blurhash)
+        .bind(&sizes)
+        .execute(&self.pg)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Return an error if no thumbnail of the size was generated for the image
+    pub async fn ensure_thumbnail(&self, file: &ChatFile, size: u32) -> Result<(), AppErr> {
+        let exists: bool = sqlx::query_scalar(
+            "SELECT EXISTS(SELECT 1 FROM t_image WHERE hash = $1 AND $2 = ANY(thumbnails))",
+        )
+        .bind(&file.hash)
+        .bind(size as i32)
+        .fetch_one(&self.pg)
+        .await?;
+
+        if !exists {
+            return Err(AppErr::NotFoundErr(format!(
+                "thumbnail {} of file {} not found",
+                size,
+                file.url()
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Fill `image_meta` of the messages from their image references
+    pub async fn attach_image_meta(&self, messages: &mut [Message]) -> Result<(), AppErr> {
+        let files: Vec<Vec<ChatFile>> = messages
+            .iter()
+            .map(|msg| {
+                msg.images
+                    .iter()
+                    .filter_map(|url| url.parse().ok())
+                    .collect()
+            })
+            .collect();
+        let hashes: Vec<&str> = files.iter().flatten().map(|f| f.hash.as_str()).collect();
+        if hashes.is_empty() {
+            return Ok(());
+        }
+
+        let rows: Vec<ImageRow> = sqlx::query_as(
+            "SELECT hash, width, height, blurhash, thumbnails FROM t_image WHERE hash = ANY($1)",
+        )
+        .bind(&hashes)
+        .fetch_all(&self.pg)
+        .await?;
+        let rows: HashMap<&str, &ImageRow> =
+            rows.iter().map(|row| (row.hash.as_str(), row)).collect();
+
+        for (msg, files) in messages.iter_mut().zip(files) {
+            msg.image_meta = files
+                .iter()
+                .filter_map(|file| rows.get(file.hash.as_str()).map(|row| row.to_meta(file)))
+                .collect();
+        }
+
+        Ok(())
+    }
+}
+
+impl ImageRow {
+    fn to_meta(&self, file: &ChatFile) -> ImageMeta {
+        let thumbnails = self
+            .thumbnails
+            .iter()
+            .map(|&size| Thumbnail {
+                size,
+                url: file.variant_url(Some(size as u32)),
+            })
+            .collect();
+
+        ImageMeta {
+            url: file.url(),
+            width: self.width,
+            height: self.height,
+            blurhash: self.blurhash.clone(),
+            thumbnails,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_image_meta_urls() {
+        let hash = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08";
+        let row = ImageRow {
+            hash: hash.to_string(),
+            width: 800,
+            height: 600,
+            blurhash: "LEHV6nWB2yk8pyo0adR*.7kCMdnj".to_string(),
+            thumbnails: vec![160, 640],
+        };
+
+        let file = ChatFile::new(3, hash.to_string(), "a.jpg");
+        let meta = row.to_meta(&file);
+        assert_eq!(meta.url, format!("/files/3/{}.jpg", hash));
+        assert_eq!(meta.thumbnails.len(), 2);
+        assert_eq!(
+            meta.thumbnails[1].url,
+            format!("/files/3/{}.jpg?size=640", hash)
+        );
+    }
+}
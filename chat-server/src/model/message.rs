@@ -0,0 +1,430 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::prelude::FromRow;
+
+use crate::{AppErr, AppState};
+
+use super::ImageMeta;
+
+// max chars of the quoted message shown in a quote reply
+const QUOTE_EXCERPT_LEN: usize = 64;
+const DEFAULT_LIST_LIMIT: i64 = 20;
+const MAX_LIST_LIMIT: i64 = 100;
+
+const SELECT_MESSAGE: &str = r#"
+SELECT m.id, m.chat_id, m.sender_id, m.content, m.images, m.created_at,
+    m.forward_msg_id, m.forward_chat_id, m.forward_sender_id,
+    COALESCE($1 = ANY(fc.members), FALSE) AS source_visible,
+    m.quote_msg_id, q.sender_id AS quote_sender_id, q.content AS quote_content,
+    (q.id IS NULL OR q.deleted_at IS NOT NULL) AS quote_deleted
+FROM t_message m
+LEFT JOIN t_chat fc ON fc.id = m.forward_chat_id
+LEFT JOIN t_message q ON q.id = m.quote_msg_id
+"#;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Message {
+    pub id: i64,
+    pub chat_id: i64,
+    pub sender_id: i64,
+    pub content: String,
+    pub images: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub image_meta: Vec<ImageMeta>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub forwarded_from: Option<ForwardSource>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quote: Option<QuoteExcerpt>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Origin of a forwarded message.
+/// `chat_id` and `msg_id` are only present if the viewer is a member of the source chat.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ForwardSource {
+    pub sender_id: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chat_id: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub msg_id: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QuoteExcerpt {
+    pub msg_id: i64,
+    pub sender_id: Option<i64>,
+    pub excerpt: String,
+    pub deleted: bool,
+}
+
+#[derive(Debug, FromRow)]
+struct MessageRow {
+    id: i64,
+    chat_id: i64,
+    sender_id: i64,
+    content: String,
+    images: Vec<String>,
+    created_at: DateTime<Utc>,
+    forward_msg_id: Option<i64>,
+    forward_chat_id: Option<i64>,
+    forward_sender_id: Option<i64>,
+    source_visible: bool,
+    quote_msg_id: Option<i64>,
+    quote_sender_id: Option<i64>,
+    quote_content: Option<String>,
+    quote_deleted: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateMessage {
+    pub content: String,
+    #[serde(default)]
+    pub images: Vec<String>,
+    pub quote_id: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ForwardMessage {
+    pub msg_id: i64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ListMessages {
+    pub last_id: Option<i64>,
+    pub limit: Option<i64>,
+}
+
+impl AppState {
+    /// Create a message, optionally quoting another message of the same chat
+    pub async fn create_message(
+        &self,
+        input: CreateMessage,
+        chat_id: i64,
+        sender_id: i64,
+    ) -> Result<Message, AppErr> {
+        if input.content.is_empty() && input.images.is_empty() {
+            return Err(AppErr::CreateMessageErr(
+                "content and images cannot both be empty".to_string(),
+            ));
+        }
+
+        self.ensure_chat_member(chat_id, sender_id).await?;
+        self.ensure_not_suspended(sender_id).await?;
+        self.verify_chat_files(chat_id, &input.images).await?;
+
+        if let Some(quote_id) = input.quote_id {
+            let quoted: Option<i64> = sqlx::query_scalar(
+                "SELECT id FROM t_message WHERE id = $1 AND chat_id = $2 AND deleted_at IS NULL",
+            )
+            .bind(quote_id)
+            .bind(chat_id)
+            .fetch_optional(&self.pg)
+            .await?;
+
+            if quoted.is_none() {
+                return Err(AppErr::NotFoundErr(format!(
+                    "quoted message {} not found in chat {}",
+                    quote_id, chat_id
+                )));
+            }
+        }
+
+        let decision = self.moderate(chat_id, sender_id, &input.content).await?;
+
+        // a stored message is always logged and, if flagged, queued for review
+        let mut tx = self.pg.begin().await?;
+        let id: i64 = sqlx::query_scalar(
+            "INSERT INTO t_message (chat_id, sender_id, content, images, quote_msg_id) VALUES ($1, $2, $3, $4, $5) RETURNING id",
+        )
+        .bind(chat_id)
+        .bind(sender_id)
+        .bind(&input.content)
+        .bind(&input.images)
+        .bind(input.quote_id)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        if let Some(decision) = decision {
+            self.log_moderation(
+                &mut tx,
+                chat_id,
+                sender_id,
+                Some(id),
+                &decision,
+                &input.content,
+            )
+            .await?;
+        }
+        tx.commit().await?;
+
+        self.get_message(id, sender_id).await
+    }
+
+    /// Forward a message into another chat, both chats must be joined by the sender.
+    /// The origin of an already forwarded message is kept.
+    pub async fn forward_message(
+        &self,
+        input: ForwardMessage,
+        chat_id: i64,
+        sender_id: i64,
+    ) -> Result<Message, AppErr> {
+        self.ensure_chat_member(chat_id, sender_id).await?;
+        self.ensure_not_suspended(sender_id).await?;
+
+        let source: Option<MessageRow> = sqlx::query_as(&format!(
+            "{} WHERE m.id = $2 AND m.deleted_at IS NULL",
+            SELECT_MESSAGE
+        ))
+        .bind(sender_id)
+        .bind(input.msg_id)
+        .fetch_optional(&self.pg)
+        .await?;
+
+        let Some(source) = source else {
+            return Err(AppErr::NotFoundErr(format!(
+                "message {} not found",
+                input.msg_id
+            )));
+        };
+
+        self.ensure_chat_member(source.chat_id, sender_id).await?;
+
+        let (forward_msg_id, forward_chat_id, forward_sender_id) = match source.forward_msg_id {
+            Some(msg_id) => (
+                Some(msg_id),
+                source.forward_chat_id,
+                source.forward_sender_id,
+            ),
+            None => (
+                Some(source.id),
+                Some(source.chat_id),
+                Some(source.sender_id),
+            ),
+        };
+
+        let decision = self.moderate(chat_id, sender_id, &source.content).await?;
+        let images = self
+            .copy_chat_files(&source.images, chat_id, sender_id)
+            .await?;
+
+        let mut tx = self.pg.begin().await?;
+        let id: i64 = sqlx::query_scalar(
+            r#"
+            INSERT INTO t_message (chat_id, sender_id, content, images, forward_msg_id, forward_chat_id, forward_sender_id)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            RETURNING id
+            "#,
+        )
+        .bind(chat_id)
+        .bind(sender_id)
+        .bind(&source.content)
+        .bind(&images)
+        .bind(forward_msg_id)
+        .bind(forward_chat_id)
+        .bind(forward_sender_id)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        if let Some(decision) = decision {
+            self.log_moderation(
+                &mut tx,
+                chat_id,
+                sender_id,
+                Some(id),
+                &decision,
+                &source.content,
+            )
+            .await?;
+        }
+        tx.commit().await?;
+
+        self.get_message(id, sender_id).await
+    }
+
+    /// Get a message as seen by the viewer
+    pub async fn get_message(&self, id: i64, viewer_id: i64) -> Result<Message, AppErr> {
+        let row: Option<MessageRow> = sqlx::query_as(&format!(
+            "{} WHERE m.id = $2 AND m.deleted_at IS NULL",
+            SELECT_MESSAGE
+        ))
+        .bind(viewer_id)
+        .bind(id)
+        .fetch_optional(&self.pg)
+        .await?;
+
+        let Some(row) = row else {
+            return Err(AppErr::NotFoundErr(format!("message {} not found", id)));
+        };
+
+        let mut messages = [row.into()];
+        self.attach_image_meta(&mut messages).await?;
+        let [message] = messages;
+
+        Ok(message)
+    }
+
+    /// List messages of a chat, newest first
+    pub async fn list_messages(
+        &self,
+        input: ListMessages,
+        chat_id: i64,
+        viewer_id: i64,
+    ) -> Result<Vec<Message>, AppErr> {
+        self.ensure_chat_member(chat_id, viewer_id).await?;
+
+        let last_id = input.last_id.unwrap_or(i64::MAX);
+        let limit = input
+            .limit
+            .unwrap_or(DEFAULT_LIST_LIMIT)
+            .clamp(1, MAX_LIST_LIMIT);
+
+        let rows: Vec<MessageRow> = sqlx::query_as(&format!(
+            "{} WHERE m.chat_id = $2 AND m.id < $3 AND m.deleted_at IS NULL ORDER BY m.id DESC LIMIT $4",
+            SELECT_MESSAGE
+        ))
+        .bind(viewer_id)
+        .bind(chat_id)
+        .bind(last_id)
+        .bind(limit)
+        .fetch_all(&self.pg)
+        .await?;
+
+        let mut messages: Vec<Message> = rows.into_iter().map(Into::into).collect();
+        self.attach_image_meta(&mut messages).await?;
+
+        Ok(messages)
+    }
+
+    /// Delete a message sent by the user, quotes of it are shown as deleted afterwards.
+    /// Files no longer posted in the chat are released.
+    pub async fn delete_message(
+        &self,
+        id: i64,
+        chat_id: i64,
+        sender_id: i64,
+    ) -> Result<(), AppErr> {
+        let images: Option<Vec<String>> = sqlx::query_scalar(
+            r#"
+            UPDATE t_message m SET content = '', images = '{}', deleted_at = NOW()
+            FROM t_message old
+            WHERE m.id = $1 AND m.chat_id = $2 AND m.sender_id = $3 AND m.deleted_at IS NULL AND old.id = m.id
+            RETURNING old.images
+            "#,
+        )
+        .bind(id)
+        .bind(chat_id)
+        .bind(sender_id)
+        .fetch_optional(&self.pg)
+        .await?;
+
+        let Some(images) = images else {
+            return Err(AppErr::NotFoundErr(format!("message {} not found", id)));
+        };
+
+        self.release_files(chat_id, &images).await
+    }
+}
+
+impl From<MessageRow> for Message {
+    fn from(row: MessageRow) -> Self {
+        let forwarded_from = row.forward_sender_id.map(|sender_id| ForwardSource {
+            sender_id,
+            chat_id: row.forward_chat_id.filter(|_| row.source_visible),
+            msg_id: row.forward_msg_id.filter(|_| row.source_visible),
+        });
+
+        let quote = row.quote_msg_id.map(|msg_id| QuoteExcerpt {
+            msg_id,
+            sender_id: row.quote_sender_id,
+            excerpt: match (&row.quote_content, row.quote_deleted) {
+                (Some(content), false) => excerpt(content, QUOTE_EXCERPT_LEN),
+                _ => String::new(),
+            },
+            deleted: row.quote_deleted,
+        });
+
+        Self {
+            id: row.id,
+            chat_id: row.chat_id,
+            sender_id: row.sender_id,
+            content: row.content,
+            images: row.images,
+            image_meta: vec![],
+            forwarded_from,
+            quote,
+            created_at: row.created_at,
+        }
+    }
+}
+
+pub(crate) fn excerpt(content: &str, max_chars: usize) -> String {
+    match content.char_indices().nth(max_chars) {
+        Some((idx, _)) => format!("{}…", &content[..idx]),
+        None => content.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_row() -> MessageRow {
+        MessageRow {
+            id: 2,
+            chat_id: 1,
+            sender_id: 1,
+            content: "hello".to_string(),
+            images: vec![],
+            created_at: Utc::now(),
+            forward_msg_id: Some(10),
+            forward_chat_id: Some(5),
+            forward_sender_id: Some(3),
+            source_visible: false,
+            quote_msg_id: Some(1),
+            quote_sender_id: Some(2),
+            quote_content: Some("quoted".to_string()),
+            quote_deleted: false,
+        }
+    }
+
+    #[test]
+    fn test_excerpt() {
+        assert_eq!(excerpt("hello", 10), "hello");
+        assert_eq!(excerpt("hello world", 5), "hello…");
+        assert_eq!(excerpt("你好世界", 2), "你好…");
+    }
+
+    #[test]
+    fn test_forward_source_hidden_for_non_member() {
+        let msg: Message = new_row().into();
+        let source = msg.forwarded_from.expect("should be forwarded");
+        assert_eq!(source.sender_id, 3);
+        assert!(source.chat_id.is_none());
+        assert!(source.msg_id.is_none());
+
+        let mut row = new_row();
+        row.source_visible = true;
+        let msg: Message = row.into();
+        let source = msg.forwarded_from.expect("should be forwarded");
+        assert_eq!(source.chat_id, Some(5));
+        assert_eq!(source.msg_id, Some(10));
+    }
+
+    #[test]
+    fn test_quote_of_deleted_message() {
+        let mut row = new_row();
+        row.quote_content = Some("".to_string());
+        row.quote_deleted = true;
+
+        let msg: Message = row.into();
+        let quote = msg.quote.expect("should quote");
+        assert!(quote.deleted);
+        assert!(quote.excerpt.is_empty());
+    }
+}
@@ -1,3 +1,38 @@
+mod api_token;
+mod avatar;
+mod bookmark;
+mod chat;
+mod draft;
+mod email_verification;
+mod file;
+mod image;
+mod message;
+mod moderation;
+mod oidc;
+mod password_reset;
+mod quota;
+mod refresh_token;
+mod review;
+mod revocation;
+mod session;
+mod two_factor;
+mod upload;
 mod user;
 
+pub(crate) use api_token::*;
+pub(crate) use avatar::*;
+pub(crate) use bookmark::*;
+pub(crate) use draft::*;
+pub(crate) use email_verification::*;
+pub(crate) use file::*;
+pub(crate) use image::*;
+pub(crate) use message::*;
+pub(crate) use moderation::*;
+pub(crate) use oidc::*;
+pub(crate) use password_reset::*;
+pub(crate) use refresh_token::*;
+pub(crate) use review::*;
+pub(crate) use session::*;
+pub(crate) use two_factor::*;
+pub(crate) use upload::*;
 pub(crate) use user::*;
@@ -0,0 +1,448 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{prelude::FromRow, types::Json, PgConnection};
+use tracing::{info, warn};
+
+use super::Role;
+use crate::{
+    moderation::{Decision, Pipeline, RuleConfig},
+    AppErr, AppState,
+};
+
+const SELECT_RULE: &str =
+    "SELECT id, scope, scope_id, rule, enabled, created_at, updated_at FROM t_moderation_rule";
+
+#[derive(Debug, Clone, Copy, sqlx::Type)]
+#[sqlx(type_name = "moderation_decision", rename_all = "snake_case")]
+enum DecisionKind {
+    Allow,
+    Reject,
+    Flag,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "moderation_scope", rename_all = "snake_case")]
+#[serde(rename_all = "camelCase")]
+pub enum RuleScope {
+    Workspace,
+    Chat,
+}
+
+#[derive(Debug, Clone, FromRow, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RuleItem {
+    pub id: i64,
+    pub scope: RuleScope,
+    /// Workspace id or chat id, see `scope`
+    pub scope_id: i64,
+    pub rule: Json<RuleConfig>,
+    pub enabled: bool,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateModerationRule {
+    pub scope: RuleScope,
+    pub scope_id: i64,
+    pub rule: RuleConfig,
+    /// Enabled if absent
+    pub enabled: Option<bool>,
+}
+
+/// Fields of the rule to change, absent ones are kept
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateModerationRule {
+    pub rule: Option<RuleConfig>,
+    pub enabled: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ListModerationRules {
+    pub scope: Option<RuleScope>,
+    pub scope_id: Option<i64>,
+}
+
+impl AppState {
+    /// Run the moderation rules of the chat and its workspace against the content.
+    /// Rejected content is logged here and returned as an error, allowed and flagged content
+    /// is logged by the caller with the id of the stored message.
+    /// Return `None` if no rule is configured, nothing is checked nor logged then.
+    pub async fn moderate(
+        &self,
+        chat_id: i64,
+        sender_id: i64,
+        content: &str,
+    ) -> Result<Option<Decision>, AppErr> {
+        let pipeline = self.load_pipeline(chat_id).await?;
+        if pipeline.is_empty() {
+            return Ok(None);
+        }
+
+        let decision = pipeline.check(content);
+        if let Decision::Reject { rule, reason } = &decision {
+            let mut conn = self.pg.acquire().await?;
+            self.log_moderation(&mut conn, chat_id, sender_id, None, &decision, content)
+                .await?;
+            return Err(AppErr::ModerationErr {
+                rule: rule.clone(),
+                reason: reason.clone(),
+            });
+        }
+
+        Ok(Some(decision))
+    }
+
+    /// Record a moderation decision, `msg_id` is `None` for rejected content.
+    /// Flagged messages are put into the review queue, in the transaction which stores them.
+    pub async fn log_moderation(
+        &self,
+        conn: &mut PgConnection,
+        chat_id: i64,
+        sender_id: i64,
+        msg_id: Option<i64>,
+        decision: &Decision,
+        content: &str,
+    ) -> Result<(), AppErr> {
+        let (kind, rule, reason) = match decision {
+            Decision::Allow => (DecisionKind::Allow, "", ""),
+            Decision::Reject { rule, reason } => {
+                (DecisionKind::Reject, rule.as_str(), reason.as_str())
+            }
+            Decision::Flag { rule, reason } => (DecisionKind::Flag, rule.as_str(), reason.as_str()),
+        };
+        info!(
+            "moderation of message {:?} in chat {} from user {}: {:?} {} {}",
+            msg_id, chat_id, sender_id, kind, rule, reason
+        );
+
+        sqlx::query(
+            "INSERT INTO t_moderation_log (chat_id, sender_id, msg_id, decision, rule, reason, content) VALUES ($1, $2, $3, $4, $5, $6, $7)",
+        )
+        .bind(chat_id)
+        .bind(sender_id)
+        .bind(msg_id)
+        .bind(kind)
+        .bind(rule)
+        .bind(reason)
+        .bind(content)
+        .execute(&mut *conn)
+        .await?;
+
+        if let (Decision::Flag { reason, .. }, Some(msg_id)) = (decision, msg_id) {
+            self.enqueue_flagged(conn, msg_id, chat_id, sender_id, reason)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Rules of all workspaces and chats, or of the given scope, admin only
+    pub async fn list_moderation_rules(
+        &self,
+        input: ListModerationRules,
+        admin_id: i64,
+    ) -> Result<Vec<RuleItem>, AppErr> {
+        self.ensure_role(admin_id, Role::Admin).await?;
+
+        let rules = sqlx::query_as(&format!(
+            "{} WHERE ($1::moderation_scope IS NULL OR scope = $1) AND ($2::BIGINT IS NULL OR scope_id = $2) ORDER BY scope, scope_id, id",
+            SELECT_RULE
+        ))
+        .bind(input.scope)
+        .bind(input.scope_id)
+        .fetch_all(&self.pg)
+        .await?;
+
+        Ok(rules)
+    }
+
+    /// Add a rule to a workspace or chat, admin only
+    pub async fn create_moderation_rule(
+        &self,
+        input: CreateModerationRule,
+        admin_id: i64,
+    ) -> Result<RuleItem, AppErr> {
+        self.ensure_role(admin_id, Role::Admin).await?;
+        check_rule(&input.rule)?;
+        if input.scope == RuleScope::Chat {
+            let exists: bool =
+                sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM t_chat WHERE id = $1)")
+                    .bind(input.scope_id)
+                    .fetch_one(&self.pg)
+                    .await?;
+            if !exists {
+                return Err(AppErr::NotFoundErr(format!(
+                    "chat {} not found",
+                    input.scope_id
+                )));
+            }
+        }
+
+        let rule: RuleItem = sqlx::query_as(
+            r#"
+            INSERT INTO t_moderation_rule (scope, scope_id, rule, enabled) VALUES ($1, $2, $3, $4)
+            RETURNING id, scope, scope_id, rule, enabled, created_at, updated_at
+            "#,
+        )
+        .bind(input.scope)
+        .bind(input.scope_id)
+        .bind(Json(&input.rule))
+        .bind(input.enabled.unwrap_or(true))
+        .fetch_one(&self.pg)
+        .await?;
+        info!(
+            "moderation rule {} of {:?} {} is created by user {}",
+            rule.id, rule.scope, rule.scope_id, admin_id
+        );
+
+        Ok(rule)
+    }
+
+    /// Change the config of a rule or enable and disable it, admin only
+    pub async fn update_moderation_rule(
+        &self,
+        input: UpdateModerationRule,
+        id: i64,
+        admin_id: i64,
+    ) -> Result<RuleItem, AppErr> {
+        self.ensure_role(admin_id, Role::Admin).await?;
+        if let Some(rule) = &input.rule {
+            check_rule(rule)?;
+        }
+
+        let rule: Option<RuleItem> = sqlx::query_as(
+            r#"
+            UPDATE t_moderation_rule
+            SET rule = COALESCE($2, rule), enabled = COALESCE($3, enabled), updated_at = NOW()
+            WHERE id = $1
+            RETURNING id, scope, scope_id, rule, enabled, created_at, updated_at
+            "#,
+        )
+        .bind(id)
+        .bind(input.rule.as_ref().map(Json))
+        .bind(input.enabled)
+        .fetch_optional(&self.pg)
+        .await?;
+        let Some(rule) = rule else {
+            return Err(AppErr::NotFoundErr(format!(
+                "moderation rule {} not found",
+                id
+            )));
+        };
+        info!("moderation rule {} is updated by user {}", id, admin_id);
+
+        Ok(rule)
+    }
+
+    /// Remove a rule, admin only
+    pub async fn delete_moderation_rule(&self, id: i64, admin_id: i64) -> Result<(), AppErr> {
+        self.ensure_role(admin_id, Role::Admin).await?;
+
+        let ret = sqlx::query("DELETE FROM t_moderation_rule WHERE id = $1")
+            .bind(id)
+            .execute(&self.pg)
+            .await?;
+        if ret.rows_affected() == 0 {
+            return Err(AppErr::NotFoundErr(format!(
+                "moderation rule {} not found",
+                id
+            )));
+        }
+        info!("moderation rule {} is deleted by user {}", id, admin_id);
+
+        Ok(())
+    }
+
+    async fn load_pipeline(&self, chat_id: i64) -> Result<Pipeline, AppErr> {
+        let rules: Vec<(i64, Json<RuleConfig>)> = sqlx::query_as(
+            r#"
+            SELECT r.id, r.rule FROM t_moderation_rule r
+            JOIN t_chat c ON c.id = $1
+            WHERE r.enabled AND (
+                (r.scope = 'workspace' AND r.scope_id = c.ws_id) OR (r.scope = 'chat' AND r.scope_id = c.id)
+            )
+            ORDER BY r.scope, r.id
+            "#,
+        )
+        .bind(chat_id)
+        .fetch_all(&self.pg)
+        .await?;
+
+        let mut pipeline = Pipeline::default();
+        for (id, Json(rule)) in rules {
+            if let Err(e) = pipeline.add_config(rule) {
+                warn!("skip invalid moderation rule {}: {}", id, e);
+            }
+        }
+
+        Ok(pipeline)
+    }
+}
+
+/// Reject configs which the pipeline would skip, e.g. an invalid regex
+fn check_rule(rule: &RuleConfig) -> Result<(), AppErr> {
+    Pipeline::default()
+        .add_config(rule.clone())
+        .map_err(|e| AppErr::InvalidInputErr(format!("invalid moderation rule: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::CreateMessage;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn test_moderation_log() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let message = |content: &str| CreateMessage {
+            content: content.to_string(),
+            images: vec![],
+            quote_id: None,
+        };
+        let decisions = || async {
+            sqlx::query_scalar::<_, String>(
+                "SELECT decision::TEXT FROM t_moderation_log WHERE chat_id = 1 ORDER BY id",
+            )
+            .fetch_all(&state.pg)
+            .await
+        };
+
+        // no rule, nothing to log
+        state.create_message(message("hello"), 1, 1).await?;
+        assert!(decisions().await?.is_empty());
+
+        sqlx::query(
+            r#"INSERT INTO t_moderation_rule (scope, scope_id, rule) VALUES ('chat', 1, '{"type": "blockedWords", "words": ["spam"]}')"#,
+        )
+        .execute(&state.pg)
+        .await?;
+        state.create_message(message("hello"), 1, 1).await?;
+        assert!(matches!(
+            state.create_message(message("spam"), 1, 1).await,
+            Err(AppErr::ModerationErr { .. })
+        ));
+        assert_eq!(decisions().await?, vec!["allow", "reject"]);
+
+        // a flagged message is stored only together with its log and review item
+        sqlx::query(
+            r#"INSERT INTO t_moderation_rule (scope, scope_id, rule) VALUES ('chat', 1, '{"type": "blockedWords", "words": ["promo"], "action": "flag"}')"#,
+        )
+        .execute(&state.pg)
+        .await?;
+        sqlx::raw_sql(
+            r#"
+            CREATE FUNCTION fail_review_item() RETURNS TRIGGER AS $$ BEGIN RAISE EXCEPTION 'queue is down'; END $$ LANGUAGE plpgsql;
+            CREATE TRIGGER fail_review_item BEFORE INSERT ON t_review_item FOR EACH ROW EXECUTE FUNCTION fail_review_item();
+            "#,
+        )
+        .execute(&state.pg)
+        .await?;
+        let messages = || async {
+            sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM t_message WHERE content = 'promo'")
+                .fetch_one(&state.pg)
+                .await
+        };
+        assert!(state.create_message(message("promo"), 1, 1).await.is_err());
+        assert_eq!(messages().await?, 0);
+        assert_eq!(decisions().await?, vec!["allow", "reject"]);
+
+        sqlx::query("DROP TRIGGER fail_review_item ON t_review_item")
+            .execute(&state.pg)
+            .await?;
+        let msg = state.create_message(message("promo"), 1, 1).await?;
+        assert_eq!(messages().await?, 1);
+        assert_eq!(decisions().await?, vec!["allow", "reject", "flag"]);
+        let queued: bool =
+            sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM t_review_item WHERE msg_id = $1)")
+                .bind(msg.id)
+                .fetch_one(&state.pg)
+                .await?;
+        assert!(queued);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_moderation_rules() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let create = |scope_id, rule: serde_json::Value| CreateModerationRule {
+            scope: RuleScope::Chat,
+            scope_id,
+            rule: serde_json::from_value(rule).unwrap(),
+            enabled: None,
+        };
+        let message = |content: &str| CreateMessage {
+            content: content.to_string(),
+            images: vec![],
+            quote_id: None,
+        };
+        let spam = serde_json::json!({ "type": "blockedWords", "words": ["spam"] });
+        let invalid = serde_json::json!({ "type": "regex", "pattern": "(" });
+
+        assert!(matches!(
+            state
+                .create_moderation_rule(create(1, spam.clone()), 1)
+                .await,
+            Err(AppErr::PermissionDeniedErr(_))
+        ));
+        assert!(matches!(
+            state
+                .create_moderation_rule(create(1, invalid.clone()), 4)
+                .await,
+            Err(AppErr::InvalidInputErr(_))
+        ));
+        assert!(matches!(
+            state
+                .create_moderation_rule(create(99, spam.clone()), 4)
+                .await,
+            Err(AppErr::NotFoundErr(_))
+        ));
+
+        let rule = state.create_moderation_rule(create(1, spam), 4).await?;
+        assert!(rule.enabled);
+        let list = ListModerationRules {
+            scope: Some(RuleScope::Chat),
+            scope_id: Some(1),
+        };
+        let rules = state.list_moderation_rules(list, 4).await?;
+        assert_eq!(
+            rules.iter().map(|r| r.id).collect::<Vec<_>>(),
+            vec![rule.id]
+        );
+        assert!(state.create_message(message("spam"), 1, 1).await.is_err());
+
+        let disable = UpdateModerationRule {
+            rule: None,
+            enabled: Some(false),
+        };
+        assert!(
+            !state
+                .update_moderation_rule(disable, rule.id, 4)
+                .await?
+                .enabled
+        );
+        state.create_message(message("spam"), 1, 1).await?;
+
+        let update = UpdateModerationRule {
+            rule: Some(serde_json::from_value(invalid)?),
+            enabled: None,
+        };
+        assert!(matches!(
+            state.update_moderation_rule(update, rule.id, 4).await,
+            Err(AppErr::InvalidInputErr(_))
+        ));
+
+        state.delete_moderation_rule(rule.id, 4).await?;
+        assert!(matches!(
+            state.delete_moderation_rule(rule.id, 4).await,
+            Err(AppErr::NotFoundErr(_))
+        ));
+
+        Ok(())
+    }
+}
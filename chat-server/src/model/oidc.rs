@@ -0,0 +1,298 @@
+use chrono::{Duration, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::{
+    oidc::{IdTokenClaims, OidcProvider},
+    util::{email_errors, hash_token, is_username_char, MAX_USERNAME_LEN},
+    AppErr, AppState,
+};
+
+use super::{taken, User};
+
+// the user has 10 minutes to sign in at the provider
+const OIDC_STATE_EXPIRATION_TIME: i64 = 10 * 60;
+// room for the suffix of a taken username, e.g. `Foo Bar-2`
+const USERNAME_SUFFIX_LEN: usize = 8;
+// used if the provider gives no name fitting the username rules
+const FALLBACK_USERNAME: &str = "user";
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OidcAuthorization {
+    /// Login page of the provider to redirect to
+    pub url: String,
+    pub state: String,
+}
+
+/// Query parameters of the redirect from the provider, posted by the web client
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OidcCallbackForm {
+    pub code: String,
+    pub state: String,
+    pub device_name: Option<String>,
+}
+
+impl AppState {
+    /// Names of the configured providers
+    pub fn list_oidc_providers(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.oidc.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// Start a sign in through the provider, the state is kept until the callback
+    pub async fn start_oidc_login(&self, provider: &str) -> Result<OidcAuthorization, AppErr> {
+        let request = self
+            .oidc_provider(provider)?
+            .authorization_request()
+            .await?;
+        sqlx::query(
+            "INSERT INTO t_oidc_state (state_hash, provider, nonce, code_verifier, expires_at) VALUES ($1, $2, $3, $4, $5)",
+        )
+        .bind(hash_token(&request.state))
+        .bind(provider)
+        .bind(&request.nonce)
+        .bind(&request.code_verifier)
+        .bind(Utc::now() + Duration::seconds(OIDC_STATE_EXPIRATION_TIME))
+        .execute(&self.pg)
+        .await?;
+
+        Ok(OidcAuthorization {
+            url: request.url,
+            state: request.state,
+        })
+    }
+
+    /// Redeem the code of the callback and return the linked user, who is created on first sign in
+    pub async fn finish_oidc_login(
+        &self,
+        provider: &str,
+        form: &OidcCallbackForm,
+    ) -> Result<User, AppErr> {
+        let oidc = self.oidc_provider(provider)?;
+        // a state is used once, whether the sign in succeeds or not
+        let pending: Option<(String, String)> = sqlx::query_as(
+            r#"
+            DELETE FROM t_oidc_state WHERE state_hash = $1 AND provider = $2
+            RETURNING nonce, code_verifier, expires_at > NOW()
+            "#,
+        )
+        .bind(hash_token(&form.state))
+        .bind(provider)
+        .fetch_optional(&self.pg)
+        .await?
+        .and_then(|(nonce, code_verifier, valid): (String, String, bool)| {
+            valid.then_some((nonce, code_verifier))
+        });
+        let Some((nonce, code_verifier)) = pending else {
+            return Err(AppErr::AuthErr("invalid or expired state".to_string()));
+        };
+
+        let claims = oidc
+            .exchange_code(&form.code, &code_verifier, &nonce)
+            .await?;
+        self.link_oidc_user(provider, claims).await
+    }
+
+    /// Delete the states of abandoned sign ins
+    pub async fn purge_oidc_states(&self) -> Result<(), AppErr> {
+        sqlx::query("DELETE FROM t_oidc_state WHERE expires_at <= NOW()")
+            .execute(&self.pg)
+            .await?;
+
+        Ok(())
+    }
+
+    fn oidc_provider(&self, name: &str) -> Result<&OidcProvider, AppErr> {
+        self.oidc
+            .get(name)
+            .ok_or_else(|| AppErr::NotFoundErr(format!("oidc provider {} not found", name)))
+    }
+
+    /// Find the user of the identity, or link it by the verified email
+    async fn link_oidc_user(&self, provider: &str, claims: IdTokenClaims) -> Result<User, AppErr> {
+        let mut tx = self.pg.begin().await?;
+
+        let linked: Option<User> = sqlx::query_as(
+            r#"
+            SELECT u.id, u.username, u.passwd, u.email, u.avatar, u.email_verified_at, u.created_at, u.updated_at
+            FROM t_user_identity i JOIN t_user u ON u.id = i.user_id
+            WHERE i.provider = $1 AND i.subject = $2
+            "#,
+        )
+        .bind(provider)
+        .bind(&claims.sub)
+        .fetch_optional(&mut *tx)
+        .await?;
+        if let Some(user) = linked {
+            return Ok(user.with_default_avatar());
+        }
+
+        // an unverified email could belong to anyone, it must not take over an account
+        let Some(email) = claims.email.as_deref().filter(|_| claims.email_verified) else {
+            return Err(AppErr::PermissionDeniedErr(format!(
+                "{} did not return a verified email",
+                provider
+            )));
+        };
+        let errors = email_errors(email);
+        if !errors.is_empty() {
+            return Err(AppErr::OidcErr(format!(
+                "{} returned an email which can not be used: {}",
+                provider,
+                errors.join(", ")
+            )));
+        }
+
+        let existing: Option<User> = sqlx::query_as(
+            r#"
+            SELECT id, username, passwd, email, avatar, email_verified_at, created_at, updated_at
+            FROM t_user WHERE LOWER(email) = LOWER($1) ORDER BY id LIMIT 1 FOR UPDATE
+            "#,
+        )
+        .bind(email)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let user = match existing {
+            // whoever signed up with it may not own the email, e.g. to hijack the account later
+            Some(user) if user.email_verified_at.is_none() => {
+                return Err(AppErr::ConflictErr(format!(
+                    "an unverified account uses {}, verify it before signing in with {}",
+                    email, provider
+                )));
+            }
+            Some(user) => user,
+            None => {
+                // no password, it can be set with a password reset
+                sqlx::query_as(
+                    r#"
+                    INSERT INTO t_user (username, passwd, email, email_verified_at) VALUES ($1, '', $2, NOW())
+                    RETURNING id, username, passwd, email, avatar, email_verified_at, created_at, updated_at
+                    "#,
+                )
+                .bind(free_username(&mut tx, &oidc_username(&claims, email)).await?)
+                .bind(email)
+                .fetch_one(&mut *tx)
+                .await
+                .map_err(taken)?
+            }
+        };
+
+        sqlx::query(
+            "INSERT INTO t_user_identity (user_id, provider, subject, email) VALUES ($1, $2, $3, $4)",
+        )
+        .bind(user.id)
+        .bind(provider)
+        .bind(&claims.sub)
+        .bind(email)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        info!(
+            "{} account {} is linked to user {}",
+            provider, claims.sub, user.id
+        );
+
+        Ok(user.with_default_avatar())
+    }
+}
+
+/// Name of the user at the provider, stripped of characters usernames can not have
+fn oidc_username(claims: &IdTokenClaims, email: &str) -> String {
+    let name: String = claims
+        .name
+        .as_deref()
+        .or(claims.preferred_username.as_deref())
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| email.split('@').next().unwrap_or(email))
+        .chars()
+        .filter(|&c| is_username_char(c))
+        .take(MAX_USERNAME_LEN - USERNAME_SUFFIX_LEN)
+        .collect();
+    match name.trim() {
+        name if name.chars().count() < 2 => FALLBACK_USERNAME.to_string(),
+        name => name.to_string(),
+    }
+}
+
+/// The username, or the first free one of `{username}-2`, `{username}-3`...
+async fn free_username(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    username: &str,
+) -> Result<String, AppErr> {
+    let taken: Vec<String> = sqlx::query_scalar(
+        "SELECT LOWER(username) FROM t_user WHERE starts_with(LOWER(username), LOWER($1))",
+    )
+    .bind(username)
+    .fetch_all(&mut **tx)
+    .await?;
+
+    let free = (1..)
+        .map(|n| match n {
+            1 => username.to_string(),
+            n => format!("{}-{}", username, n),
+        })
+        .find(|name| !taken.contains(&name.to_lowercase()))
+        .unwrap_or_default();
+    Ok(free)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn test_oidc_username() {
+        let mut claims = IdTokenClaims {
+            sub: "1".to_string(),
+            email: Some("foo@acme.com".to_string()),
+            email_verified: true,
+            name: Some(" Foo Bar ".to_string()),
+            preferred_username: Some("foo".to_string()),
+            nonce: None,
+        };
+        assert_eq!(oidc_username(&claims, "foo@acme.com"), "Foo Bar");
+
+        claims.name = None;
+        assert_eq!(oidc_username(&claims, "foo@acme.com"), "foo");
+
+        claims.preferred_username = Some("".to_string());
+        assert_eq!(oidc_username(&claims, "bar@acme.com"), "bar");
+
+        claims.name = Some("<O'Brien>".to_string());
+        assert_eq!(oidc_username(&claims, "bar@acme.com"), "OBrien");
+        claims.name = Some("!".to_string());
+        assert_eq!(oidc_username(&claims, "bar@acme.com"), FALLBACK_USERNAME);
+    }
+
+    #[tokio::test]
+    async fn test_link_oidc_user() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let claims = |sub: &str, email: &str| IdTokenClaims {
+            sub: sub.to_string(),
+            email: Some(email.to_string()),
+            email_verified: true,
+            name: None,
+            preferred_username: None,
+            nonce: None,
+        };
+
+        // linked to the user of the verified email
+        let user = state
+            .link_oidc_user("acme", claims("1", "Alice@acme.com"))
+            .await?;
+        assert_eq!(user.id, 1);
+
+        let long = format!("{}@acme.com", "a".repeat(60));
+        assert!(matches!(
+            state.link_oidc_user("acme", claims("2", &long)).await,
+            Err(AppErr::OidcErr(_))
+        ));
+
+        Ok(())
+    }
+}
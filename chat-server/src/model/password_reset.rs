@@ -0,0 +1,226 @@
+use chrono::{Duration, Utc};
+use serde::Deserialize;
+use tracing::info;
+
+use crate::{
+    mailer::Mail,
+    util::{generate_token, hash_token, passwd_errors, FieldErrors},
+    AppErr, AppState,
+};
+
+use super::{revoke_user_api_tokens, User};
+
+// reset links expire in 1 hour
+const RESET_EXPIRATION_TIME: i64 = 60 * 60;
+// at most one mail per minute for each user, further requests are dropped silently
+const RESET_INTERVAL: i64 = 60;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ForgotPasswdForm {
+    pub email: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResetPasswdForm {
+    pub token: String,
+    pub passwd: String,
+}
+
+impl AppState {
+    /// Mail a reset link if the email belongs to a user.
+    /// Nothing tells the caller whether it does, unknown emails and throttled requests are ignored.
+    pub async fn request_passwd_reset(&self, email: &str) -> Result<(), AppErr> {
+        let user: Option<User> = sqlx::query_as(
+            "SELECT id, username, passwd, email, avatar, email_verified_at, created_at, updated_at FROM t_user WHERE LOWER(email) = LOWER($1)",
+        )
+        .bind(email)
+        .fetch_optional(&self.pg)
+        .await?;
+        let Some(user) = user else {
+            info!("password reset of unknown email is ignored");
+            return Ok(());
+        };
+
+        let throttled: bool = sqlx::query_scalar(
+            "SELECT EXISTS (SELECT 1 FROM t_password_reset WHERE user_id = $1 AND created_at > NOW() - make_interval(secs => $2))",
+        )
+        .bind(user.id)
+        .bind(RESET_INTERVAL as f64)
+        .fetch_one(&self.pg)
+        .await?;
+        if throttled {
+            info!("password reset of user {} is throttled", user.id);
+            return Ok(());
+        }
+
+        let token = generate_token();
+        sqlx::query(
+            "INSERT INTO t_password_reset (user_id, token_hash, expires_at) VALUES ($1, $2, $3)",
+        )
+        .bind(user.id)
+        .bind(hash_token(&token))
+        .bind(Utc::now() + Duration::seconds(RESET_EXPIRATION_TIME))
+        .execute(&self.pg)
+        .await?;
+
+        let mail = reset_mail(&user, &self.config.mail.base_url, &token);
+        self.mailer.send(&mail).await?;
+        info!("password reset email is sent to user {}", user.id);
+
+        Ok(())
+    }
+
+    /// Set a new password with a reset token, sign out all sessions of the user and revoke its api tokens.
+    /// Opening the mailed link proves the ownership of the email, so it is verified as well.
+    pub async fn reset_passwd(&self, form: ResetPasswdForm) -> Result<(), AppErr> {
+        let mut tx = self.pg.begin().await?;
+
+        let row: Option<(i64, String, String)> = sqlx::query_as(
+            r#"
+            UPDATE t_password_reset r SET used_at = NOW()
+            FROM t_user u
+            WHERE u.id = r.user_id AND r.token_hash = $1 AND r.used_at IS NULL AND r.expires_at > NOW()
+            RETURNING r.user_id, u.username, u.email
+            "#,
+        )
+        .bind(hash_token(&form.token))
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some((user_id, username, email)) = row else {
+            return Err(AppErr::InvalidInputErr(
+                "invalid or expired reset token".to_string(),
+            ));
+        };
+        // the token stays usable with a stronger password, the update is rolled back
+        let mut errors = FieldErrors::default();
+        errors.add("passwd", passwd_errors(&form.passwd, &[&username, &email]));
+        errors.into_result()?;
+        let passwd_hash = self.passwd_hasher.hash_async(&form.passwd).await?;
+
+        sqlx::query(
+            "UPDATE t_password_reset SET used_at = NOW() WHERE user_id = $1 AND used_at IS NULL",
+        )
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query(
+            r#"
+            UPDATE t_user SET passwd = $2, email_verified_at = COALESCE(email_verified_at, NOW()), updated_at = NOW()
+            WHERE id = $1
+            "#,
+        )
+        .bind(user_id)
+        .bind(&passwd_hash)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query(
+            "UPDATE t_refresh_token SET revoked_at = NOW() WHERE user_id = $1 AND revoked_at IS NULL",
+        )
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+        let tokens = revoke_user_api_tokens(&mut tx, user_id).await?;
+        tx.commit().await?;
+
+        let revoked = self.revoke_other_sessions(user_id, None).await?;
+        info!(
+            "password of user {} is reset, {} sessions and {} api tokens are revoked",
+            user_id, revoked, tokens
+        );
+
+        Ok(())
+    }
+
+    /// Delete expired and used reset tokens older than the throttle interval
+    pub async fn purge_passwd_resets(&self) -> Result<(), AppErr> {
+        sqlx::query(
+            "DELETE FROM t_password_reset WHERE (expires_at <= NOW() OR used_at IS NOT NULL) AND created_at <= NOW() - make_interval(secs => $1)",
+        )
+        .bind(RESET_INTERVAL as f64)
+        .execute(&self.pg)
+        .await?;
+
+        Ok(())
+    }
+}
+
+fn reset_mail(user: &User, base_url: &str, token: &str) -> Mail {
+    let link = format!(
+        "{}/reset-password?token={}",
+        base_url.trim_end_matches('/'),
+        token
+    );
+    let body = format!(
+        "Hi {},\n\n\
+        A password reset was requested for your account. Open the link below to choose a new password, \
+        it expires in 1 hour and signs out all your devices.\n\n\
+        {}\n\n\
+        If you did not request it, you can ignore this email and your password stays unchanged.\n",
+        user.username, link
+    );
+
+    Mail {
+        to: user.email.clone(),
+        subject: "Reset your password".to_string(),
+        body,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::SignInForm;
+    use anyhow::Result;
+
+    #[test]
+    fn test_reset_mail() {
+        let user = User::new_for_test(1, "foo".to_string(), "foo@acme.com".to_string());
+        let mail = reset_mail(&user, "https://chat.acme.com", "abc");
+
+        assert_eq!(mail.to, "foo@acme.com");
+        assert!(mail
+            .body
+            .contains("https://chat.acme.com/reset-password?token=abc\n"));
+    }
+
+    #[tokio::test]
+    async fn test_reset_passwd() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+
+        // emails are matched regardless of case
+        state.request_passwd_reset("ALICE@Acme.com").await?;
+        let requested: i64 =
+            sqlx::query_scalar("SELECT COUNT(*) FROM t_password_reset WHERE user_id = 1")
+                .fetch_one(&state.pg)
+                .await?;
+        assert_eq!(requested, 1);
+
+        sqlx::query(
+            "INSERT INTO t_password_reset (user_id, token_hash, expires_at) VALUES (1, $1, NOW() + INTERVAL '1 hour')",
+        )
+        .bind(hash_token("known"))
+        .execute(&state.pg)
+        .await?;
+        let reset = |passwd: &str| ResetPasswdForm {
+            token: "known".to_string(),
+            passwd: passwd.to_string(),
+        };
+        assert!(matches!(
+            state.reset_passwd(reset("short1")).await,
+            Err(AppErr::ValidationErr(_))
+        ));
+        // the token is still usable after a rejected password
+        state.reset_passwd(reset("renewed456")).await?;
+
+        let form = SignInForm {
+            email: "Alice@ACME.com".to_string(),
+            passwd: "renewed456".to_string(),
+            device_name: None,
+        };
+        assert!(state.verify_user(form).await?.is_some());
+
+        Ok(())
+    }
+}
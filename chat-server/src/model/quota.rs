@@ -0,0 +1,387 @@
+use std::{collections::BTreeSet, fmt};
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{prelude::FromRow, PgConnection};
+
+use crate::{
+    storage::{blob_key, thumbnail_key},
+    AppErr, AppState,
+};
+
+use super::Role;
+
+// uploads which are not posted in any message within this time are purged
+const ORPHAN_FILE_HOURS: i64 = 24;
+
+// reference of a file record, see ChatFile::url
+const FILE_URL_SQL: &str = "('/files/' || f.chat_id || '/' || f.hash || '.' || f.ext)";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "quota_scope", rename_all = "snake_case")]
+#[serde(rename_all = "camelCase")]
+pub enum QuotaScope {
+    User,
+    Workspace,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Usage {
+    pub used: i64,
+    /// Max bytes allowed, unlimited if absent
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkspaceUsage {
+    pub ws_id: i64,
+    #[serde(flatten)]
+    pub usage: Usage,
+}
+
+/// Storage used by the user and by the workspaces of the chats the user belongs to
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StorageUsage {
+    pub user: Usage,
+    pub workspaces: Vec<WorkspaceUsage>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecomputedUsage {
+    /// File records removed because their blob is missing in storage
+    pub missing_files: u64,
+    pub users: u64,
+    pub workspaces: u64,
+}
+
+#[derive(Debug, FromRow)]
+struct ReleasedFile {
+    uploader_id: i64,
+    ws_id: i64,
+    hash: String,
+    size: i64,
+}
+
+impl AppState {
+    /// Bytes the uploader can still store in the chat, `None` if unlimited
+    pub(crate) async fn remaining_quota(
+        &self,
+        uploader_id: i64,
+        chat_id: i64,
+    ) -> Result<Option<u64>, AppErr> {
+        let used: Vec<(QuotaScope, i64)> = sqlx::query_as(
+            r#"
+            SELECT u.scope, u.bytes FROM t_storage_usage u
+            WHERE (u.scope = 'user' AND u.scope_id = $1)
+                OR (u.scope = 'workspace' AND u.scope_id = (SELECT ws_id FROM t_chat WHERE id = $2))
+            "#,
+        )
+        .bind(uploader_id)
+        .bind(chat_id)
+        .fetch_all(&self.pg)
+        .await?;
+
+        let remaining = [QuotaScope::User, QuotaScope::Workspace]
+            .into_iter()
+            .filter_map(|scope| {
+                let used = used
+                    .iter()
+                    .find(|(s, _)| *s == scope)
+                    .map_or(0, |(_, bytes)| *bytes);
+                remaining_bytes(self.quota_limit(scope), used)
+            })
+            .min();
+
+        Ok(remaining)
+    }
+
+    /// Add stored bytes to the usage of the uploader and the workspace of the chat.
+    /// Fail if a quota is exceeded, the caller must not commit the transaction then.
+    pub(crate) async fn add_usage(
+        &self,
+        conn: &mut PgConnection,
+        uploader_id: i64,
+        chat_id: i64,
+        bytes: i64,
+    ) -> Result<(), AppErr> {
+        let ws_id: i64 = sqlx::query_scalar("SELECT ws_id FROM t_chat WHERE id = $1")
+            .bind(chat_id)
+            .fetch_one(&mut *conn)
+            .await?;
+
+        for (scope, scope_id) in [
+            (QuotaScope::User, uploader_id),
+            (QuotaScope::Workspace, ws_id),
+        ] {
+            let used: i64 = sqlx::query_scalar(
+                r#"
+                INSERT INTO t_storage_usage (scope, scope_id, bytes) VALUES ($1, $2, $3)
+                ON CONFLICT (scope, scope_id) DO UPDATE
+                    SET bytes = t_storage_usage.bytes + EXCLUDED.bytes, updated_at = NOW()
+                RETURNING bytes
+                "#,
+            )
+            .bind(scope)
+            .bind(scope_id)
+            .bind(bytes)
+            .fetch_one(&mut *conn)
+            .await?;
+
+            if let Some(limit) = self.quota_limit(scope) {
+                if bytes > 0 && used as u64 > limit {
+                    return Err(AppErr::QuotaExceededErr(format!(
+                        "{} {} would use {} of {} bytes",
+                        scope, scope_id, used, limit
+                    )));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Usage of the user and of the workspaces the user belongs to
+    pub async fn storage_usage(&self, user_id: i64) -> Result<StorageUsage, AppErr> {
+        let used: Option<i64> = sqlx::query_scalar(
+            "SELECT bytes FROM t_storage_usage WHERE scope = 'user' AND scope_id = $1",
+        )
+        .bind(user_id)
+        .fetch_optional(&self.pg)
+        .await?;
+
+        let workspaces: Vec<(i64, i64)> = sqlx::query_as(
+            r#"
+            SELECT DISTINCT c.ws_id, COALESCE(u.bytes, 0) AS bytes
+            FROM t_chat c
+            LEFT JOIN t_storage_usage u ON u.scope = 'workspace' AND u.scope_id = c.ws_id
+            WHERE $1 = ANY(c.members)
+            ORDER BY c.ws_id
+            "#,
+        )
+        .bind(user_id)
+        .fetch_all(&self.pg)
+        .await?;
+
+        let workspace_limit = self.quota_limit(QuotaScope::Workspace);
+        Ok(StorageUsage {
+            user: Usage {
+                used: used.unwrap_or_default(),
+                limit: self.quota_limit(QuotaScope::User),
+            },
+            workspaces: workspaces
+                .into_iter()
+                .map(|(ws_id, used)| WorkspaceUsage {
+                    ws_id,
+                    usage: Usage {
+                        used,
+                        limit: workspace_limit,
+                    },
+                })
+                .collect(),
+        })
+    }
+
+    /// Free the files of a deleted message unless other messages of the chat still post them
+    pub(crate) async fn release_files(&self, chat_id: i64, urls: &[String]) -> Result<(), AppErr> {
+        if urls.is_empty() {
+            return Ok(());
+        }
+
+        self.delete_unreferenced_files(Some(chat_id), Some(urls), Utc::now())
+            .await
+    }
+
+    /// Free uploads which have not been posted in any message for a while
+    pub async fn purge_files(&self) -> Result<(), AppErr> {
+        let created_before = Utc::now() - Duration::hours(ORPHAN_FILE_HOURS);
+        self.delete_unreferenced_files(None, None, created_before)
+            .await
+    }
+
+    /// Rebuild usage from the file records whose blob still exists in storage, admin only
+    pub async fn recompute_usage(&self, admin_id: i64) -> Result<RecomputedUsage, AppErr> {
+        self.ensure_role(admin_id, Role::Admin).await?;
+
+        let hashes: Vec<String> = sqlx::query_scalar("SELECT DISTINCT hash FROM t_file")
+            .fetch_all(&self.pg)
+            .await?;
+        let mut missing = vec![];
+        for hash in hashes {
+            if !self.storage.exists(&blob_key(&hash)).await? {
+                missing.push(hash);
+            }
+        }
+
+        let mut tx = self.pg.begin().await?;
+        let missing_files = sqlx::query("DELETE FROM t_file WHERE hash = ANY($1)")
+            .bind(&missing)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+
+        sqlx::query("DELETE FROM t_storage_usage")
+            .execute(&mut *tx)
+            .await?;
+        let users = sqlx::query(
+            r#"
+            INSERT INTO t_storage_usage (scope, scope_id, bytes)
+            SELECT 'user', uploader_id, SUM(size) FROM t_file GROUP BY uploader_id
+            "#,
+        )
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+        let workspaces = sqlx::query(
+            r#"
+            INSERT INTO t_storage_usage (scope, scope_id, bytes)
+            SELECT 'workspace', c.ws_id, SUM(f.size) FROM t_file f JOIN t_chat c ON c.id = f.chat_id
+            GROUP BY c.ws_id
+            "#,
+        )
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+        tx.commit().await?;
+
+        Ok(RecomputedUsage {
+            missing_files,
+            users,
+            workspaces,
+        })
+    }
+
+    fn quota_limit(&self, scope: QuotaScope) -> Option<u64> {
+        let quota = &self.config.storage.quota;
+        match scope {
+            QuotaScope::User => quota.user_bytes,
+            QuotaScope::Workspace => quota.workspace_bytes,
+        }
+    }
+
+    async fn delete_unreferenced_files(
+        &self,
+        chat_id: Option<i64>,
+        urls: Option<&[String]>,
+        created_before: DateTime<Utc>,
+    ) -> Result<(), AppErr> {
+        let mut tx = self.pg.begin().await?;
+
+        let released: Vec<ReleasedFile> = sqlx::query_as(&format!(
+            r#"
+            DELETE FROM t_file f USING t_chat c
+            WHERE c.id = f.chat_id
+                AND ($1::BIGINT IS NULL OR f.chat_id = $1)
+                AND ($2::TEXT[] IS NULL OR {url} = ANY($2))
+                AND f.created_at < $3
+                AND NOT EXISTS (
+                    SELECT 1 FROM t_message m
+                    WHERE m.chat_id = f.chat_id AND m.deleted_at IS NULL AND {url} = ANY(m.images)
+                )
+            RETURNING f.uploader_id, c.ws_id, f.hash, f.size
+            "#,
+            url = FILE_URL_SQL
+        ))
+        .bind(chat_id)
+        .bind(urls)
+        .bind(created_before)
+        .fetch_all(&mut *tx)
+        .await?;
+
+        for file in &released {
+            for (scope, scope_id) in [
+                (QuotaScope::User, file.uploader_id),
+                (QuotaScope::Workspace, file.ws_id),
+            ] {
+                sqlx::query(
+                    "UPDATE t_storage_usage SET bytes = GREATEST(bytes - $3, 0), updated_at = NOW() WHERE scope = $1 AND scope_id = $2",
+                )
+                .bind(scope)
+                .bind(scope_id)
+                .bind(file.size)
+                .execute(&mut *tx)
+                .await?;
+            }
+        }
+        tx.commit().await?;
+
+        let hashes: BTreeSet<&str> = released.iter().map(|f| f.hash.as_str()).collect();
+        for hash in hashes {
+            self.delete_blob(hash).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Delete a blob and its thumbnails from storage once no file record refers to it
+    pub(super) async fn delete_blob(&self, hash: &str) -> Result<(), AppErr> {
+        let mut tx = self.pg.begin().await?;
+        lock_blob(&mut tx, hash).await?;
+
+        let referenced: bool =
+            sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM t_file WHERE hash = $1)")
+                .bind(hash)
+                .fetch_one(&mut *tx)
+                .await?;
+        if referenced {
+            return Ok(());
+        }
+
+        let thumbnails: Option<Vec<i32>> =
+            sqlx::query_scalar("DELETE FROM t_image WHERE hash = $1 RETURNING thumbnails")
+                .bind(hash)
+                .fetch_optional(&mut *tx)
+                .await?;
+        for size in thumbnails.unwrap_or_default() {
+            self.storage
+                .delete(&thumbnail_key(hash, size as u32))
+                .await?;
+        }
+        self.storage.delete(&blob_key(hash)).await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+}
+
+/// Serialize storing and deleting the blob of the hash until the transaction ends,
+/// so that a blob is never deleted while a new record of it is committed
+pub(super) async fn lock_blob(conn: &mut PgConnection, hash: &str) -> Result<(), AppErr> {
+    sqlx::query("SELECT pg_advisory_xact_lock(hashtext($1))")
+        .bind(hash)
+        .execute(conn)
+        .await?;
+
+    Ok(())
+}
+
+/// Bytes left under the limit, usage may exceed it after the limit is lowered
+fn remaining_bytes(limit: Option<u64>, used: i64) -> Option<u64> {
+    limit.map(|limit| limit.saturating_sub(used.max(0) as u64))
+}
+
+impl fmt::Display for QuotaScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::User => write!(f, "user"),
+            Self::Workspace => write!(f, "workspace"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_remaining_bytes() {
+        assert_eq!(remaining_bytes(None, 10), None);
+        assert_eq!(remaining_bytes(Some(10), 3), Some(7));
+        assert_eq!(remaining_bytes(Some(10), 12), Some(0));
+        assert_eq!(remaining_bytes(Some(10), -1), Some(10));
+    }
+}
@@ -0,0 +1,204 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::Deserialize;
+use sqlx::{prelude::FromRow, PgConnection};
+use tracing::warn;
+
+use crate::{
+    util::{generate_token, hash_token, REFRESH_TOKEN_EXPIRATION_TIME},
+    AppErr, AppState,
+};
+
+use super::User;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RefreshForm {
+    pub refresh_token: String,
+}
+
+#[derive(Debug)]
+pub struct RefreshedSession {
+    pub user: User,
+    pub session_id: String,
+    pub refresh_token: String,
+}
+
+#[derive(Debug, FromRow)]
+struct RefreshToken {
+    id: i64,
+    user_id: i64,
+    family_id: String,
+    expires_at: DateTime<Utc>,
+    used_at: Option<DateTime<Utc>>,
+    revoked_at: Option<DateTime<Utc>>,
+}
+
+impl AppState {
+    /// Exchange a refresh token for a new one of the same family.
+    /// A token can only be used once, reusing it revokes the whole family and its session.
+    pub async fn rotate_refresh_token(&self, token: &str) -> Result<RefreshedSession, AppErr> {
+        let invalid = || AppErr::AuthErr("invalid refresh token".to_string());
+        let mut tx = self.pg.begin().await?;
+
+        let stored: Option<RefreshToken> = sqlx::query_as(
+            r#"
+            SELECT id, user_id, family_id, expires_at, used_at, revoked_at FROM t_refresh_token
+            WHERE token_hash = $1 FOR UPDATE
+            "#,
+        )
+        .bind(hash_token(token))
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let Some(stored) = stored else {
+            return Err(invalid());
+        };
+        if stored.revoked_at.is_some() || stored.expires_at <= Utc::now() {
+            return Err(invalid());
+        }
+        if stored.used_at.is_some() {
+            sqlx::query(
+                "UPDATE t_refresh_token SET revoked_at = NOW() WHERE family_id = $1 AND revoked_at IS NULL",
+            )
+            .bind(&stored.family_id)
+            .execute(&mut *tx)
+            .await?;
+            sqlx::query(
+                "UPDATE t_session SET revoked_at = NOW() WHERE id = $1 AND revoked_at IS NULL",
+            )
+            .bind(&stored.family_id)
+            .execute(&mut *tx)
+            .await?;
+            tx.commit().await?;
+            self.deny_sessions(stored.user_id, std::slice::from_ref(&stored.family_id))
+                .await;
+
+            warn!(
+                "refresh token {} of user {} is reused, revoked family {}",
+                stored.id, stored.user_id, stored.family_id
+            );
+            return Err(AppErr::AuthErr("refresh token is reused".to_string()));
+        }
+
+        sqlx::query("UPDATE t_refresh_token SET used_at = NOW() WHERE id = $1")
+            .bind(stored.id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("UPDATE t_session SET last_seen_at = NOW() WHERE id = $1")
+            .bind(&stored.family_id)
+            .execute(&mut *tx)
+            .await?;
+        let refresh_token =
+            insert_refresh_token(&mut tx, stored.user_id, &stored.family_id).await?;
+
+        let user: Option<User> = sqlx::query_as(
+            "SELECT id, username, passwd, email, avatar, email_verified_at, created_at, updated_at FROM t_user WHERE id = $1",
+        )
+        .bind(stored.user_id)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let user = user.ok_or_else(invalid)?;
+        tx.commit().await?;
+
+        Ok(RefreshedSession {
+            user: user.with_default_avatar(),
+            session_id: stored.family_id,
+            refresh_token,
+        })
+    }
+
+    /// Delete expired refresh tokens, used and revoked ones are kept until then to detect reuse
+    pub async fn purge_refresh_tokens(&self) -> Result<(), AppErr> {
+        sqlx::query("DELETE FROM t_refresh_token WHERE expires_at <= NOW()")
+            .execute(&self.pg)
+            .await?;
+
+        Ok(())
+    }
+}
+
+pub(super) async fn insert_refresh_token(
+    conn: &mut PgConnection,
+    user_id: i64,
+    family_id: &str,
+) -> Result<String, AppErr> {
+    let token = generate_token();
+
+    sqlx::query(
+        "INSERT INTO t_refresh_token (user_id, family_id, token_hash, expires_at) VALUES ($1, $2, $3, $4)",
+    )
+    .bind(user_id)
+    .bind(family_id)
+    .bind(hash_token(&token))
+    .bind(Utc::now() + Duration::seconds(REFRESH_TOKEN_EXPIRATION_TIME))
+    .execute(conn)
+    .await?;
+
+    Ok(token)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::ClientInfo;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn test_rotate_refresh_token() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let (sid, first) = state.create_session(1, ClientInfo::default()).await?;
+
+        let refreshed = state.rotate_refresh_token(&first).await?;
+        assert_eq!(refreshed.user.id, 1);
+        assert_eq!(refreshed.session_id, sid);
+        assert_ne!(refreshed.refresh_token, first);
+        let used_at: Option<DateTime<Utc>> =
+            sqlx::query_scalar("SELECT used_at FROM t_refresh_token WHERE token_hash = $1")
+                .bind(hash_token(&first))
+                .fetch_one(&state.pg)
+                .await?;
+        assert!(used_at.is_some());
+
+        // reusing the first token revokes the whole family and the session
+        assert!(matches!(
+            state.rotate_refresh_token(&first).await,
+            Err(AppErr::AuthErr(_))
+        ));
+        let active: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM t_refresh_token WHERE family_id = $1 AND revoked_at IS NULL",
+        )
+        .bind(&sid)
+        .fetch_one(&state.pg)
+        .await?;
+        assert_eq!(active, 0);
+        let revoked_at: Option<DateTime<Utc>> =
+            sqlx::query_scalar("SELECT revoked_at FROM t_session WHERE id = $1")
+                .bind(&sid)
+                .fetch_one(&state.pg)
+                .await?;
+        assert!(revoked_at.is_some());
+        assert!(state.denylist.contains(&sid, Utc::now().timestamp()));
+        assert!(matches!(
+            state.rotate_refresh_token(&refreshed.refresh_token).await,
+            Err(AppErr::AuthErr(_))
+        ));
+
+        let (_, expired) = state.create_session(2, ClientInfo::default()).await?;
+        sqlx::query(
+            "UPDATE t_refresh_token SET expires_at = NOW() - INTERVAL '1 second' WHERE token_hash = $1",
+        )
+        .bind(hash_token(&expired))
+        .execute(&state.pg)
+        .await?;
+        assert!(matches!(
+            state.rotate_refresh_token(&expired).await,
+            Err(AppErr::AuthErr(_))
+        ));
+        assert!(matches!(
+            state.rotate_refresh_token("unknown").await,
+            Err(AppErr::AuthErr(_))
+        ));
+
+        Ok(())
+    }
+}
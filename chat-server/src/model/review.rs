@@ -0,0 +1,419 @@
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{prelude::FromRow, PgConnection};
+use tracing::warn;
+
+use super::Role;
+use crate::{notif::AppEvent, AppErr, AppState};
+
+// suspend sender for one day if the moderator does not specify
+const DEFAULT_SUSPEND_HOURS: i64 = 24;
+// longer suspensions are rather bans, which are out of the review queue's scope
+const MAX_SUSPEND_HOURS: i64 = 365 * 24;
+
+const SELECT_REVIEW_ITEM: &str = r#"
+SELECT i.id, i.msg_id, i.chat_id, i.sender_id, i.source, i.reporter_id, i.reason, i.status,
+    COALESCE(m.content, '') AS content, i.created_at
+FROM t_review_item i
+LEFT JOIN t_message m ON m.id = i.msg_id
+"#;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "review_source", rename_all = "snake_case")]
+#[serde(rename_all = "camelCase")]
+pub enum ReviewSource {
+    Report,
+    Moderation,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "review_status", rename_all = "snake_case")]
+#[serde(rename_all = "camelCase")]
+pub enum ReviewStatus {
+    #[default]
+    Pending,
+    Resolved,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "review_action", rename_all = "snake_case")]
+#[serde(rename_all = "camelCase")]
+pub enum ReviewAction {
+    Dismiss,
+    DeleteMessage,
+    WarnSender,
+    SuspendSender,
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReviewItem {
+    pub id: i64,
+    pub msg_id: i64,
+    pub chat_id: i64,
+    pub sender_id: i64,
+    pub source: ReviewSource,
+    pub reporter_id: Option<i64>,
+    pub reason: String,
+    pub status: ReviewStatus,
+    pub content: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReviewAudit {
+    pub id: i64,
+    pub item_id: i64,
+    pub moderator_id: i64,
+    pub action: ReviewAction,
+    pub note: String,
+    pub suspended_until: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Warning sent to the sender of a reviewed message
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ModerationWarning {
+    pub msg_id: i64,
+    pub chat_id: i64,
+    pub note: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReportMessage {
+    pub reason: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ListReviewItems {
+    #[serde(default)]
+    pub status: ReviewStatus,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResolveReviewItem {
+    pub action: ReviewAction,
+    #[serde(default)]
+    pub note: String,
+    pub suspend_hours: Option<i64>,
+}
+
+impl AppState {
+    /// Report a message of a chat the reporter belongs to, reporting it again updates the reason
+    /// of a pending report, a resolved one is not reopened by the same reporter
+    pub async fn report_message(
+        &self,
+        input: ReportMessage,
+        chat_id: i64,
+        msg_id: i64,
+        reporter_id: i64,
+    ) -> Result<ReviewItem, AppErr> {
+        self.ensure_chat_member(chat_id, reporter_id).await?;
+
+        let msg = self.get_message(msg_id, reporter_id).await?;
+        if msg.chat_id != chat_id {
+            return Err(AppErr::NotFoundErr(format!(
+                "message {} not found in chat {}",
+                msg_id, chat_id
+            )));
+        }
+
+        let id: Option<i64> = sqlx::query_scalar(
+            r#"
+            INSERT INTO t_review_item (msg_id, chat_id, sender_id, source, reporter_id, reason)
+            VALUES ($1, $2, $3, 'report', $4, $5)
+            ON CONFLICT (msg_id, reporter_id) WHERE source = 'report'
+                DO UPDATE SET reason = EXCLUDED.reason WHERE t_review_item.status = 'pending'
+            RETURNING id
+            "#,
+        )
+        .bind(msg_id)
+        .bind(chat_id)
+        .bind(msg.sender_id)
+        .bind(reporter_id)
+        .bind(&input.reason)
+        .fetch_optional(&self.pg)
+        .await?;
+        let Some(id) = id else {
+            return Err(AppErr::ConflictErr(format!(
+                "report of message {} is already resolved",
+                msg_id
+            )));
+        };
+
+        self.get_review_item(id).await
+    }
+
+    /// Put a message flagged by the moderation pipeline into the review queue
+    pub async fn enqueue_flagged(
+        &self,
+        conn: &mut PgConnection,
+        msg_id: i64,
+        chat_id: i64,
+        sender_id: i64,
+        reason: &str,
+    ) -> Result<(), AppErr> {
+        sqlx::query(
+            "INSERT INTO t_review_item (msg_id, chat_id, sender_id, source, reason) VALUES ($1, $2, $3, 'moderation', $4)",
+        )
+        .bind(msg_id)
+        .bind(chat_id)
+        .bind(sender_id)
+        .bind(reason)
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// List the review queue, oldest first
+    pub async fn list_review_items(
+        &self,
+        input: ListReviewItems,
+        moderator_id: i64,
+    ) -> Result<Vec<ReviewItem>, AppErr> {
+        self.ensure_role(moderator_id, Role::Moderator).await?;
+
+        let items = sqlx::query_as(&format!(
+            "{} WHERE i.status = $1 ORDER BY i.created_at, i.id",
+            SELECT_REVIEW_ITEM
+        ))
+        .bind(input.status)
+        .fetch_all(&self.pg)
+        .await?;
+
+        Ok(items)
+    }
+
+    /// Resolve a pending review item and record it in the audit trail
+    pub async fn resolve_review_item(
+        &self,
+        input: ResolveReviewItem,
+        id: i64,
+        moderator_id: i64,
+    ) -> Result<ReviewAudit, AppErr> {
+        self.ensure_role(moderator_id, Role::Moderator).await?;
+
+        let mut tx = self.pg.begin().await?;
+
+        let item: Option<(i64, i64, i64)> = sqlx::query_as(
+            "UPDATE t_review_item SET status = 'resolved' WHERE id = $1 AND status = 'pending' RETURNING msg_id, chat_id, sender_id",
+        )
+        .bind(id)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let Some((msg_id, chat_id, sender_id)) = item else {
+            return Err(AppErr::NotFoundErr(format!(
+                "pending review item {} not found",
+                id
+            )));
+        };
+
+        let mut suspended_until = None;
+        let mut deleted_images: Option<Vec<String>> = None;
+        match input.action {
+            ReviewAction::Dismiss | ReviewAction::WarnSender => {}
+            ReviewAction::DeleteMessage => {
+                deleted_images = sqlx::query_scalar(
+                    r#"
+                    UPDATE t_message m SET content = '', images = '{}', deleted_at = NOW()
+                    FROM t_message old
+                    WHERE m.id = $1 AND m.deleted_at IS NULL AND old.id = m.id
+                    RETURNING old.images
+                    "#,
+                )
+                .bind(msg_id)
+                .fetch_optional(&mut *tx)
+                .await?;
+            }
+            ReviewAction::SuspendSender => {
+                let until = suspend_until(input.suspend_hours, Utc::now())?;
+                sqlx::query("UPDATE t_user SET suspended_until = $1 WHERE id = $2")
+                    .bind(until)
+                    .bind(sender_id)
+                    .execute(&mut *tx)
+                    .await?;
+                suspended_until = Some(until);
+            }
+        }
+
+        let audit: ReviewAudit = sqlx::query_as(
+            r#"
+            INSERT INTO t_review_audit (item_id, moderator_id, action, note, suspended_until)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING id, item_id, moderator_id, action, note, suspended_until, created_at
+            "#,
+        )
+        .bind(id)
+        .bind(moderator_id)
+        .bind(input.action)
+        .bind(&input.note)
+        .bind(suspended_until)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        // the resolution is committed, failing here would leave the moderator nothing to retry
+        if let Some(images) = deleted_images {
+            if let Err(e) = self.release_files(chat_id, &images).await {
+                warn!("failed to release files of message {}: {}", msg_id, e);
+            }
+        }
+
+        if input.action == ReviewAction::WarnSender {
+            let warning = ModerationWarning {
+                msg_id,
+                chat_id,
+                note: input.note,
+            };
+            if let Err(e) = self
+                .notify_users(&[sender_id], None, AppEvent::ModerationWarning(warning))
+                .await
+            {
+                warn!(
+                    "failed to warn user {} of message {}: {}",
+                    sender_id, msg_id, e
+                );
+            }
+        }
+
+        Ok(audit)
+    }
+
+    /// List the audit trail of a review item
+    pub async fn list_review_audits(
+        &self,
+        id: i64,
+        moderator_id: i64,
+    ) -> Result<Vec<ReviewAudit>, AppErr> {
+        self.ensure_role(moderator_id, Role::Moderator).await?;
+
+        let audits = sqlx::query_as(
+            "SELECT id, item_id, moderator_id, action, note, suspended_until, created_at FROM t_review_audit WHERE item_id = $1 ORDER BY id",
+        )
+        .bind(id)
+        .fetch_all(&self.pg)
+        .await?;
+
+        Ok(audits)
+    }
+
+    async fn get_review_item(&self, id: i64) -> Result<ReviewItem, AppErr> {
+        let item: Option<ReviewItem> =
+            sqlx::query_as(&format!("{} WHERE i.id = $1", SELECT_REVIEW_ITEM))
+                .bind(id)
+                .fetch_optional(&self.pg)
+                .await?;
+
+        item.ok_or_else(|| AppErr::NotFoundErr(format!("review item {} not found", id)))
+    }
+}
+
+/// End of a suspension of the given hours, the default if absent
+fn suspend_until(hours: Option<i64>, now: DateTime<Utc>) -> Result<DateTime<Utc>, AppErr> {
+    let hours = hours.unwrap_or(DEFAULT_SUSPEND_HOURS);
+    if !(1..=MAX_SUSPEND_HOURS).contains(&hours) {
+        return Err(AppErr::InvalidInputErr(format!(
+            "suspend hours must be 1 to {}",
+            MAX_SUSPEND_HOURS
+        )));
+    }
+
+    TimeDelta::try_hours(hours)
+        .and_then(|delta| now.checked_add_signed(delta))
+        .ok_or_else(|| AppErr::InvalidInputErr(format!("invalid suspend hours {}", hours)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn test_suspend_until() {
+        let now = Utc::now();
+        assert_eq!(
+            suspend_until(None, now).unwrap(),
+            now + TimeDelta::hours(DEFAULT_SUSPEND_HOURS)
+        );
+        assert_eq!(
+            suspend_until(Some(2), now).unwrap(),
+            now + TimeDelta::hours(2)
+        );
+        for hours in [0, -1, MAX_SUSPEND_HOURS + 1, i64::MAX, i64::MIN] {
+            assert!(matches!(
+                suspend_until(Some(hours), now),
+                Err(AppErr::InvalidInputErr(_))
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_review_queue() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let report = |reason: &str| ReportMessage {
+            reason: reason.to_string(),
+        };
+        let resolve = |action, suspend_hours| ResolveReviewItem {
+            action,
+            note: String::new(),
+            suspend_hours,
+        };
+
+        // alice reports message 2 of bob, carol is a moderator
+        let item = state.report_message(report("spam"), 1, 2, 1).await?;
+        assert_eq!(item.status, ReviewStatus::Pending);
+        assert_eq!(item.sender_id, 2);
+        let item = state.report_message(report("abuse"), 1, 2, 1).await?;
+        assert_eq!(item.reason, "abuse");
+
+        let pending = |status| ListReviewItems { status };
+        assert!(matches!(
+            state
+                .list_review_items(pending(ReviewStatus::Pending), 1)
+                .await,
+            Err(AppErr::PermissionDeniedErr(_))
+        ));
+        let items = state
+            .list_review_items(pending(ReviewStatus::Pending), 3)
+            .await?;
+        assert_eq!(items.len(), 1);
+
+        // an invalid suspension leaves the item pending
+        let ret = state
+            .resolve_review_item(
+                resolve(ReviewAction::SuspendSender, Some(i64::MAX)),
+                item.id,
+                3,
+            )
+            .await;
+        assert!(matches!(ret, Err(AppErr::InvalidInputErr(_))));
+        let audit = state
+            .resolve_review_item(resolve(ReviewAction::SuspendSender, Some(48)), item.id, 3)
+            .await?;
+        assert!(audit.suspended_until.is_some());
+        assert!(matches!(
+            state.ensure_not_suspended(2).await,
+            Err(AppErr::PermissionDeniedErr(_))
+        ));
+
+        // reporting it again does not reopen it
+        assert!(matches!(
+            state.report_message(report("again"), 1, 2, 1).await,
+            Err(AppErr::ConflictErr(_))
+        ));
+        let items = state
+            .list_review_items(pending(ReviewStatus::Pending), 3)
+            .await?;
+        assert!(items.is_empty());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,148 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgListener;
+use tracing::warn;
+
+use crate::{util::AccessToken, AppErr, AppState};
+
+// every chat-server instance listens on this channel to update its denylist
+const TOKEN_REVOKED_CHANNEL: &str = "token_revoked";
+
+#[derive(Debug, Serialize, Deserialize)]
+struct RevokedToken {
+    jti: String,
+    exp: i64,
+}
+
+impl AppState {
+    /// Reject the access token from now on, on all instances
+    pub async fn revoke_access_token(
+        &self,
+        token: &AccessToken,
+        user_id: i64,
+    ) -> Result<(), AppErr> {
+        self.deny_token_id(&token.jti, user_id, token.exp).await
+    }
+
+    /// Deny the jti of a token or the sid of all tokens of a session until `exp`
+    pub(crate) async fn deny_token_id(
+        &self,
+        id: &str,
+        user_id: i64,
+        exp: i64,
+    ) -> Result<(), AppErr> {
+        let expires_at = DateTime::from_timestamp(exp, 0).unwrap_or_default();
+        sqlx::query(
+            "INSERT INTO t_revoked_token (jti, user_id, expires_at) VALUES ($1, $2, $3) ON CONFLICT DO NOTHING",
+        )
+        .bind(id)
+        .bind(user_id)
+        .bind(expires_at)
+        .execute(&self.pg)
+        .await?;
+        self.denylist.insert(id.to_string(), exp);
+
+        let payload = serde_json::to_string(&RevokedToken {
+            jti: id.to_string(),
+            exp,
+        })?;
+        sqlx::query("SELECT pg_notify($1, $2)")
+            .bind(TOKEN_REVOKED_CHANNEL)
+            .bind(payload)
+            .execute(&self.pg)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Keep the denylist in sync with revocations of other instances until reconnecting fails.
+    /// All unexpired revocations are loaded once listening and again after each reconnect,
+    /// as notifications sent while the connection is lost are dropped.
+    pub async fn listen_revoked_tokens(&self) -> Result<(), AppErr> {
+        let mut listener = PgListener::connect_with(&self.pg).await?;
+        listener.listen(TOKEN_REVOKED_CHANNEL).await?;
+        self.load_revoked_tokens().await?;
+
+        loop {
+            // the listener is connected again when the lost connection is reported
+            let Some(notif) = listener.try_recv().await? else {
+                warn!("token revocation listener is reconnected, reloading revoked tokens");
+                self.load_revoked_tokens().await?;
+                continue;
+            };
+            match serde_json::from_str::<RevokedToken>(notif.payload()) {
+                Ok(token) => self.denylist.insert(token.jti, token.exp),
+                Err(e) => warn!("failed to parse revoked token: {}", e),
+            }
+        }
+    }
+
+    /// Delete expired revocations, the tokens are rejected as expired anyway
+    pub async fn purge_revoked_tokens(&self) -> Result<(), AppErr> {
+        self.denylist.prune(Utc::now().timestamp());
+        sqlx::query("DELETE FROM t_revoked_token WHERE expires_at <= NOW()")
+            .execute(&self.pg)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn load_revoked_tokens(&self) -> Result<(), AppErr> {
+        let tokens: Vec<(String, DateTime<Utc>)> =
+            sqlx::query_as("SELECT jti, expires_at FROM t_revoked_token WHERE expires_at > NOW()")
+                .fetch_all(&self.pg)
+                .await?;
+
+        for (jti, expires_at) in tokens {
+            self.denylist.insert(jti, expires_at.timestamp());
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_revoked_tokens_reloaded_on_reconnect() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let listening = state.clone();
+        let task = tokio::spawn(async move { listening.listen_revoked_tokens().await });
+
+        tokio::time::sleep(Duration::from_millis(500)).await;
+
+        // a revocation while the connection is lost is not notified, but reloaded
+        sqlx::query(
+            "INSERT INTO t_revoked_token (jti, user_id, expires_at) VALUES ('missed', 1, NOW() + INTERVAL '1 hour')",
+        )
+        .execute(&state.pg)
+        .await?;
+        sqlx::query(
+            r#"
+            SELECT pg_terminate_backend(pid) FROM pg_stat_activity
+            WHERE datname = current_database() AND query LIKE 'LISTEN%'
+            "#,
+        )
+        .execute(&state.pg)
+        .await?;
+
+        let now = Utc::now().timestamp();
+        let mut reloaded = false;
+        for _ in 0..50 {
+            if state.denylist.contains("missed", now) {
+                reloaded = true;
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        assert!(reloaded);
+        assert!(!task.is_finished());
+        task.abort();
+
+        Ok(())
+    }
+}
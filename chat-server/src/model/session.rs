@@ -0,0 +1,249 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::prelude::FromRow;
+use tracing::warn;
+
+use crate::{notif::AppEvent, util::JWT_EXPIRATION_TIME, AppErr, AppState};
+
+use super::insert_refresh_token;
+
+const MAX_DEVICE_NAME_LEN: usize = 64;
+const MAX_USER_AGENT_LEN: usize = 255;
+
+/// Signed in device of a user, backed by a family of refresh tokens
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Session {
+    pub id: String,
+    pub device_name: String,
+    pub user_agent: String,
+    pub ip: String,
+    pub created_at: DateTime<Utc>,
+    /// Time of the last sign in or token refresh
+    pub last_seen_at: DateTime<Utc>,
+    /// Whether it is the session of the request
+    pub current: bool,
+}
+
+/// Device which signs in
+#[derive(Debug, Clone, Default)]
+pub struct ClientInfo {
+    pub device_name: String,
+    pub user_agent: String,
+    pub ip: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RevokedSession {
+    pub session_id: String,
+}
+
+impl ClientInfo {
+    pub fn new(device_name: Option<String>, user_agent: Option<&str>, ip: Option<String>) -> Self {
+        Self {
+            device_name: device_name
+                .unwrap_or_default()
+                .chars()
+                .take(MAX_DEVICE_NAME_LEN)
+                .collect(),
+            user_agent: user_agent
+                .unwrap_or_default()
+                .chars()
+                .take(MAX_USER_AGENT_LEN)
+                .collect(),
+            ip: ip.unwrap_or_default(),
+        }
+    }
+}
+
+impl AppState {
+    /// Start a session of a signed in user, return its id and the first refresh token
+    pub async fn create_session(
+        &self,
+        user_id: i64,
+        client: ClientInfo,
+    ) -> Result<(String, String), AppErr> {
+        let session_id = uuid::Uuid::now_v7().to_string();
+        let mut tx = self.pg.begin().await?;
+
+        sqlx::query(
+            "INSERT INTO t_session (id, user_id, device_name, user_agent, ip) VALUES ($1, $2, $3, $4, $5)",
+        )
+        .bind(&session_id)
+        .bind(user_id)
+        .bind(&client.device_name)
+        .bind(&client.user_agent)
+        .bind(&client.ip)
+        .execute(&mut *tx)
+        .await?;
+        let refresh_token = insert_refresh_token(&mut tx, user_id, &session_id).await?;
+        tx.commit().await?;
+
+        Ok((session_id, refresh_token))
+    }
+
+    /// Sessions of the user which can still be refreshed
+    pub async fn list_sessions(
+        &self,
+        user_id: i64,
+        current: Option<&str>,
+    ) -> Result<Vec<Session>, AppErr> {
+        let sessions = sqlx::query_as(
+            r#"
+            SELECT s.id, s.device_name, s.user_agent, s.ip, s.created_at, s.last_seen_at,
+                s.id = $2 IS TRUE AS current
+            FROM t_session s
+            WHERE s.user_id = $1 AND s.revoked_at IS NULL AND EXISTS (
+                SELECT 1 FROM t_refresh_token t
+                WHERE t.family_id = s.id AND t.used_at IS NULL AND t.revoked_at IS NULL AND t.expires_at > NOW()
+            )
+            ORDER BY s.last_seen_at DESC
+            "#,
+        )
+        .bind(user_id)
+        .bind(current)
+        .fetch_all(&self.pg)
+        .await?;
+
+        Ok(sessions)
+    }
+
+    /// Sign out a session of the user
+    pub async fn revoke_session(&self, user_id: i64, session_id: &str) -> Result<(), AppErr> {
+        let revoked = self
+            .revoke_sessions(user_id, Some(session_id), None)
+            .await?;
+        if revoked == 0 {
+            return Err(AppErr::NotFoundErr(format!(
+                "session {} not found",
+                session_id
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Sign out all sessions of the user except the current one, return the number of them
+    pub async fn revoke_other_sessions(
+        &self,
+        user_id: i64,
+        current: Option<&str>,
+    ) -> Result<u64, AppErr> {
+        self.revoke_sessions(user_id, None, current).await
+    }
+
+    /// Reject the access tokens of the sessions until they expire,
+    /// and close the notify-server connections of the devices.
+    /// The sessions are revoked already, failures are only logged.
+    pub(crate) async fn deny_sessions(&self, user_id: i64, session_ids: &[String]) {
+        let exp = (Utc::now() + Duration::seconds(JWT_EXPIRATION_TIME)).timestamp();
+        for session_id in session_ids {
+            if let Err(e) = self.deny_token_id(session_id, user_id, exp).await {
+                warn!(
+                    "failed to deny session {} of user {}: {}",
+                    session_id, user_id, e
+                );
+            }
+            if let Err(e) = self
+                .notify_users(
+                    &[user_id],
+                    None,
+                    AppEvent::SessionRevoked(RevokedSession {
+                        session_id: session_id.clone(),
+                    }),
+                )
+                .await
+            {
+                warn!("failed to notify revoked session {}: {}", session_id, e);
+            }
+        }
+    }
+
+    async fn revoke_sessions(
+        &self,
+        user_id: i64,
+        only: Option<&str>,
+        except: Option<&str>,
+    ) -> Result<u64, AppErr> {
+        let mut tx = self.pg.begin().await?;
+        let session_ids: Vec<String> = sqlx::query_scalar(
+            r#"
+            UPDATE t_session SET revoked_at = NOW()
+            WHERE user_id = $1 AND revoked_at IS NULL
+                AND ($2::VARCHAR IS NULL OR id = $2)
+                AND ($3::VARCHAR IS NULL OR id <> $3)
+            RETURNING id
+            "#,
+        )
+        .bind(user_id)
+        .bind(only)
+        .bind(except)
+        .fetch_all(&mut *tx)
+        .await?;
+
+        sqlx::query(
+            "UPDATE t_refresh_token SET revoked_at = NOW() WHERE family_id = ANY($1) AND revoked_at IS NULL",
+        )
+        .bind(&session_ids)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        self.deny_sessions(user_id, &session_ids).await;
+        Ok(session_ids.len() as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn test_client_info() {
+        let client = ClientInfo::new(Some("手机".repeat(40)), Some("curl/8.0"), None);
+        assert_eq!(client.device_name.chars().count(), MAX_DEVICE_NAME_LEN);
+        assert_eq!(client.user_agent, "curl/8.0");
+        assert_eq!(client.ip, "");
+    }
+
+    #[tokio::test]
+    async fn test_sessions() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let (a, _) = state.create_session(1, ClientInfo::default()).await?;
+        let (b, _) = state.create_session(1, ClientInfo::default()).await?;
+        let (c, _) = state.create_session(2, ClientInfo::default()).await?;
+
+        let mut sessions = state.list_sessions(1, Some(&a)).await?;
+        sessions.sort_by(|x, y| x.id.cmp(&y.id));
+        let flags: Vec<_> = sessions
+            .iter()
+            .map(|s| (s.id.as_str(), s.current))
+            .collect();
+        assert_eq!(flags, vec![(a.as_str(), true), (b.as_str(), false)]);
+
+        // unknown sessions and those of other users are not found
+        for id in ["unknown", c.as_str()] {
+            assert!(matches!(
+                state.revoke_session(1, id).await,
+                Err(AppErr::NotFoundErr(_))
+            ));
+        }
+        assert_eq!(state.list_sessions(2, None).await?.len(), 1);
+
+        assert_eq!(state.revoke_other_sessions(1, Some(&a)).await?, 1);
+        let sessions = state.list_sessions(1, Some(&a)).await?;
+        assert_eq!(sessions.len(), 1);
+        assert!(sessions[0].current);
+        let now = Utc::now().timestamp();
+        assert!(state.denylist.contains(&b, now));
+        assert!(!state.denylist.contains(&a, now));
+
+        state.revoke_session(1, &a).await?;
+        assert!(state.list_sessions(1, None).await?.is_empty());
+        assert!(state.denylist.contains(&a, now));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,513 @@
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use chrono::{Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgConnection;
+use tracing::info;
+
+use crate::{
+    util::{
+        base32_encode, generate_token, generate_totp_secret, hash_token, totp_qr_svg, totp_uri,
+        verify_totp, LOGIN_CHALLENGE_EXPIRATION_TIME,
+    },
+    AppErr, AppState,
+};
+
+use super::{SessionUser, User};
+
+// issuer shown in authenticator apps
+const TOTP_ISSUER: &str = "easy-chat";
+const RECOVERY_CODE_COUNT: usize = 10;
+const RECOVERY_CODE_LEN: usize = 10;
+// wrong codes allowed for a challenge, and for all challenges of a user within the window
+const MAX_CHALLENGE_ATTEMPTS: i32 = 5;
+const MAX_FAILED_ATTEMPTS: i64 = 10;
+const FAILED_ATTEMPTS_WINDOW: i64 = 15 * 60;
+
+/// Secret of a pending enrollment, to be added to an authenticator app
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TotpEnrollment {
+    /// Base32 secret for manual entry
+    pub secret: String,
+    /// `otpauth://` uri, the payload of the QR code
+    pub uri: String,
+    pub qr_svg: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecoveryCodes {
+    /// Shown only once, each one can replace a code a single time
+    pub recovery_codes: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TotpCodeForm {
+    /// TOTP code or recovery code
+    pub code: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TwoFactorForm {
+    pub challenge_token: String,
+    /// TOTP code or recovery code
+    pub code: String,
+}
+
+/// User and device of a sign in completed with the second factor
+#[derive(Debug)]
+pub struct CompletedChallenge {
+    pub user: User,
+    pub device_name: String,
+}
+
+impl AppState {
+    /// Start enrolling TOTP, a pending enrollment is replaced with a new secret
+    pub async fn enroll_totp(&self, user: &SessionUser) -> Result<TotpEnrollment, AppErr> {
+        let secret = generate_totp_secret();
+        let enrolled = sqlx::query(
+            r#"
+            INSERT INTO t_totp (user_id, secret) VALUES ($1, $2)
+            ON CONFLICT (user_id) DO UPDATE SET secret = EXCLUDED.secret, last_step = NULL, created_at = NOW()
+            WHERE t_totp.enabled_at IS NULL
+            "#,
+        )
+        .bind(user.id)
+        .bind(&secret)
+        .execute(&self.pg)
+        .await?;
+        if enrolled.rows_affected() == 0 {
+            return Err(AppErr::ConflictErr(format!(
+                "2FA of user {} is already enabled",
+                user.id
+            )));
+        }
+
+        let uri = totp_uri(&secret, TOTP_ISSUER, &user.email);
+        Ok(TotpEnrollment {
+            secret: base32_encode(&secret),
+            qr_svg: totp_qr_svg(&uri)?,
+            uri,
+        })
+    }
+
+    /// Enable 2FA with a code of the enrolled secret, return new recovery codes
+    pub async fn confirm_totp(&self, user_id: i64, code: &str) -> Result<RecoveryCodes, AppErr> {
+        let mut tx = self.pg.begin().await?;
+        let totp: Option<(Vec<u8>, bool)> = sqlx::query_as(
+            "SELECT secret, enabled_at IS NOT NULL FROM t_totp WHERE user_id = $1 FOR UPDATE",
+        )
+        .bind(user_id)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let secret = match totp {
+            None => {
+                return Err(AppErr::NotFoundErr(format!(
+                    "TOTP of user {} is not enrolled",
+                    user_id
+                )))
+            }
+            Some((_, true)) => {
+                return Err(AppErr::ConflictErr(format!(
+                    "2FA of user {} is already enabled",
+                    user_id
+                )))
+            }
+            Some((secret, false)) => secret,
+        };
+
+        let Some(step) = verify_totp(&secret, code.trim(), Utc::now().timestamp()) else {
+            return Err(AppErr::InvalidInputErr("invalid TOTP code".to_string()));
+        };
+        sqlx::query("UPDATE t_totp SET enabled_at = NOW(), last_step = $2 WHERE user_id = $1")
+            .bind(user_id)
+            .bind(step)
+            .execute(&mut *tx)
+            .await?;
+        let recovery_codes = replace_recovery_codes(&mut tx, user_id).await?;
+        tx.commit().await?;
+        info!("2FA of user {} is enabled", user_id);
+
+        Ok(RecoveryCodes { recovery_codes })
+    }
+
+    /// Turn off 2FA, a current code is required so that a stolen session can not do it.
+    /// Wrong codes count towards the lockout of sign ins, see `complete_login_challenge`.
+    pub async fn disable_totp(&self, user_id: i64, code: &str) -> Result<(), AppErr> {
+        let mut tx = self.pg.begin().await?;
+        // one attempt of the user at a time, so that concurrent ones can not pass the lockout
+        sqlx::query("SELECT user_id FROM t_totp WHERE user_id = $1 FOR UPDATE")
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+        ensure_attempts_left(&mut tx, user_id).await?;
+
+        if !check_second_factor(&mut tx, user_id, code).await? {
+            sqlx::query("INSERT INTO t_totp_failure (user_id) VALUES ($1)")
+                .bind(user_id)
+                .execute(&mut *tx)
+                .await?;
+            tx.commit().await?;
+            return Err(AppErr::InvalidInputErr("invalid code".to_string()));
+        }
+
+        sqlx::query("DELETE FROM t_totp WHERE user_id = $1")
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM t_recovery_code WHERE user_id = $1")
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        info!("2FA of user {} is disabled", user_id);
+
+        Ok(())
+    }
+
+    pub async fn totp_enabled(&self, user_id: i64) -> Result<bool, AppErr> {
+        let enabled = sqlx::query_scalar(
+            "SELECT EXISTS (SELECT 1 FROM t_totp WHERE user_id = $1 AND enabled_at IS NOT NULL)",
+        )
+        .bind(user_id)
+        .fetch_one(&self.pg)
+        .await?;
+
+        Ok(enabled)
+    }
+
+    /// Hold a sign in whose password is verified until the second factor, return the challenge token
+    pub async fn create_login_challenge(
+        &self,
+        user_id: i64,
+        device_name: &str,
+    ) -> Result<String, AppErr> {
+        let token = generate_token();
+        sqlx::query(
+            "INSERT INTO t_login_challenge (user_id, token_hash, device_name, expires_at) VALUES ($1, $2, $3, $4)",
+        )
+        .bind(user_id)
+        .bind(hash_token(&token))
+        .bind(device_name)
+        .bind(Utc::now() + Duration::seconds(LOGIN_CHALLENGE_EXPIRATION_TIME))
+        .execute(&self.pg)
+        .await?;
+
+        Ok(token)
+    }
+
+    /// Finish a sign in with a TOTP or recovery code.
+    /// A challenge is dropped after `MAX_CHALLENGE_ATTEMPTS` wrong codes,
+    /// and the user is locked out for a while after `MAX_FAILED_ATTEMPTS`, see `ensure_attempts_left`.
+    pub async fn complete_login_challenge(
+        &self,
+        form: TwoFactorForm,
+    ) -> Result<CompletedChallenge, AppErr> {
+        let mut tx = self.pg.begin().await?;
+        let challenge: Option<(i64, i64, String)> = sqlx::query_as(
+            r#"
+            SELECT id, user_id, device_name FROM t_login_challenge
+            WHERE token_hash = $1 AND used_at IS NULL AND expires_at > NOW()
+            FOR UPDATE
+            "#,
+        )
+        .bind(hash_token(&form.challenge_token))
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some((id, user_id, device_name)) = challenge else {
+            return Err(AppErr::AuthErr(
+                "invalid or expired challenge token".to_string(),
+            ));
+        };
+
+        ensure_attempts_left(&mut tx, user_id).await?;
+
+        if !check_second_factor(&mut tx, user_id, &form.code).await? {
+            sqlx::query(
+                r#"
+                UPDATE t_login_challenge SET attempts = attempts + 1,
+                    used_at = CASE WHEN attempts + 1 >= $2 THEN NOW() END
+                WHERE id = $1
+                "#,
+            )
+            .bind(id)
+            .bind(MAX_CHALLENGE_ATTEMPTS)
+            .execute(&mut *tx)
+            .await?;
+            tx.commit().await?;
+            return Err(AppErr::AuthErr("invalid code".to_string()));
+        }
+
+        sqlx::query("UPDATE t_login_challenge SET used_at = NOW() WHERE id = $1")
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        let user: User = sqlx::query_as(
+            "SELECT id, username, passwd, email, avatar, email_verified_at, created_at, updated_at FROM t_user WHERE id = $1",
+        )
+        .bind(user_id)
+        .fetch_one(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(CompletedChallenge {
+            user: user.with_default_avatar(),
+            device_name,
+        })
+    }
+
+    /// Delete challenges and failures which can no longer count for the lockout
+    pub async fn purge_login_challenges(&self) -> Result<(), AppErr> {
+        sqlx::query(
+            "DELETE FROM t_login_challenge WHERE expires_at <= NOW() AND created_at <= NOW() - make_interval(secs => $1)",
+        )
+        .bind(FAILED_ATTEMPTS_WINDOW as f64)
+        .execute(&self.pg)
+        .await?;
+        sqlx::query(
+            "DELETE FROM t_totp_failure WHERE created_at <= NOW() - make_interval(secs => $1)",
+        )
+        .bind(FAILED_ATTEMPTS_WINDOW as f64)
+        .execute(&self.pg)
+        .await?;
+
+        Ok(())
+    }
+}
+
+/// Lock the user out after `MAX_FAILED_ATTEMPTS` wrong codes within the window,
+/// of sign in challenges and of other checks together
+async fn ensure_attempts_left(conn: &mut PgConnection, user_id: i64) -> Result<(), AppErr> {
+    let failed: i64 = sqlx::query_scalar(
+        r#"
+        SELECT (
+            SELECT COALESCE(SUM(attempts), 0) FROM t_login_challenge
+            WHERE user_id = $1 AND created_at > NOW() - make_interval(secs => $2)
+        ) + (
+            SELECT COUNT(*) FROM t_totp_failure
+            WHERE user_id = $1 AND created_at > NOW() - make_interval(secs => $2)
+        )
+        "#,
+    )
+    .bind(user_id)
+    .bind(FAILED_ATTEMPTS_WINDOW as f64)
+    .fetch_one(conn)
+    .await?;
+    if failed >= MAX_FAILED_ATTEMPTS {
+        return Err(AppErr::TooManyRequestsErr(
+            "too many wrong codes, try again later".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Accept a TOTP code which was not used before, or consume an unused recovery code
+async fn check_second_factor(
+    conn: &mut PgConnection,
+    user_id: i64,
+    code: &str,
+) -> Result<bool, AppErr> {
+    let code = code.trim();
+    if code.len() == 6 && code.bytes().all(|b| b.is_ascii_digit()) {
+        let totp: Option<(Vec<u8>, Option<i64>)> = sqlx::query_as(
+            "SELECT secret, last_step FROM t_totp WHERE user_id = $1 AND enabled_at IS NOT NULL FOR UPDATE",
+        )
+        .bind(user_id)
+        .fetch_optional(&mut *conn)
+        .await?;
+        let Some((secret, last_step)) = totp else {
+            return Ok(false);
+        };
+
+        // a code is accepted once, even within its step
+        let step = verify_totp(&secret, code, Utc::now().timestamp())
+            .filter(|step| last_step.is_none_or(|last| *step > last));
+        let Some(step) = step else {
+            return Ok(false);
+        };
+        sqlx::query("UPDATE t_totp SET last_step = $2 WHERE user_id = $1")
+            .bind(user_id)
+            .bind(step)
+            .execute(&mut *conn)
+            .await?;
+        return Ok(true);
+    }
+
+    let used = sqlx::query(
+        "UPDATE t_recovery_code SET used_at = NOW() WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL",
+    )
+    .bind(user_id)
+    .bind(hash_token(&normalize_recovery_code(code)))
+    .execute(&mut *conn)
+    .await?;
+    if used.rows_affected() > 0 {
+        info!("recovery code of user {} is used", user_id);
+    }
+
+    Ok(used.rows_affected() > 0)
+}
+
+async fn replace_recovery_codes(
+    conn: &mut PgConnection,
+    user_id: i64,
+) -> Result<Vec<String>, AppErr> {
+    sqlx::query("DELETE FROM t_recovery_code WHERE user_id = $1")
+        .bind(user_id)
+        .execute(&mut *conn)
+        .await?;
+
+    let codes: Vec<String> = (0..RECOVERY_CODE_COUNT)
+        .map(|_| generate_recovery_code())
+        .collect();
+    let hashes: Vec<String> = codes
+        .iter()
+        .map(|code| hash_token(&normalize_recovery_code(code)))
+        .collect();
+    sqlx::query(
+        "INSERT INTO t_recovery_code (user_id, code_hash) SELECT $1, UNNEST($2::CHAR(64)[])",
+    )
+    .bind(user_id)
+    .bind(&hashes)
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(codes)
+}
+
+/// Lowercase base32 in groups of 4, e.g. `k3v7-qmz2-xa5d-r6ty`
+fn generate_recovery_code() -> String {
+    let mut buf = [0u8; RECOVERY_CODE_LEN];
+    OsRng.fill_bytes(&mut buf);
+    let code = base32_encode(&buf).to_lowercase();
+
+    code.as_bytes()
+        .chunks(4)
+        .map(|group| std::str::from_utf8(group).expect("base32 is ascii"))
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Codes are typed by hand, ignore case, spaces and dashes
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::totp_code_at;
+    use anyhow::Result;
+
+    #[test]
+    fn test_recovery_code() {
+        let code = generate_recovery_code();
+        // 10 bytes are 16 base32 characters in 4 groups
+        assert_eq!(code.len(), 16 + 3);
+        assert_eq!(code.split('-').count(), 4);
+        assert_ne!(code, generate_recovery_code());
+
+        assert_eq!(
+            normalize_recovery_code(&code.to_uppercase().replace('-', " ")),
+            normalize_recovery_code(&code)
+        );
+    }
+
+    async fn totp_secret(state: &AppState, user_id: i64) -> Result<Vec<u8>> {
+        let secret = sqlx::query_scalar("SELECT secret FROM t_totp WHERE user_id = $1")
+            .bind(user_id)
+            .fetch_one(&state.pg)
+            .await?;
+        Ok(secret)
+    }
+
+    #[tokio::test]
+    async fn test_two_factor_flow() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let alice = SessionUser::new(1, "alice".to_string(), "alice@acme.com".to_string(), true);
+        let now = Utc::now().timestamp();
+        let complete = |code: String| async {
+            let challenge_token = state.create_login_challenge(1, "phone").await?;
+            state
+                .complete_login_challenge(TwoFactorForm {
+                    challenge_token,
+                    code,
+                })
+                .await
+        };
+
+        // a pending enrollment is replaced, a code of another time is rejected
+        state.enroll_totp(&alice).await?;
+        let first = totp_secret(&state, 1).await?;
+        state.enroll_totp(&alice).await?;
+        let secret_1 = totp_secret(&state, 1).await?;
+        assert_ne!(first, secret_1);
+        assert!(matches!(
+            state
+                .confirm_totp(1, &totp_code_at(&secret_1, now - 3600))
+                .await,
+            Err(AppErr::InvalidInputErr(_))
+        ));
+
+        let codes = state
+            .confirm_totp(1, &totp_code_at(&secret_1, now))
+            .await?
+            .recovery_codes;
+        assert_eq!(codes.len(), RECOVERY_CODE_COUNT);
+        assert!(state.totp_enabled(1).await?);
+        assert!(matches!(
+            state.enroll_totp(&alice).await,
+            Err(AppErr::ConflictErr(_))
+        ));
+
+        // the code which confirmed the enrollment can not be replayed in the same step
+        assert!(matches!(
+            complete(totp_code_at(&secret_1, now)).await,
+            Err(AppErr::AuthErr(_))
+        ));
+        let completed = complete(totp_code_at(&secret_1, now + 30)).await?;
+        assert_eq!(completed.user.id, 1);
+        assert_eq!(completed.device_name, "phone");
+
+        // a recovery code is used once, in any case and grouping
+        complete(codes[0].to_uppercase()).await?;
+        assert!(matches!(
+            complete(codes[0].clone()).await,
+            Err(AppErr::AuthErr(_))
+        ));
+
+        // wrong codes to disable 2FA count towards the lockout of sign ins, 2 are used above
+        for _ in 2..MAX_FAILED_ATTEMPTS {
+            assert!(matches!(
+                state.disable_totp(1, "000000").await,
+                Err(AppErr::InvalidInputErr(_))
+            ));
+        }
+        assert!(matches!(
+            state.disable_totp(1, &codes[1]).await,
+            Err(AppErr::TooManyRequestsErr(_))
+        ));
+        assert!(matches!(
+            complete(codes[1].clone()).await,
+            Err(AppErr::TooManyRequestsErr(_))
+        ));
+        assert!(state.totp_enabled(1).await?);
+
+        // another user disables 2FA with a recovery code
+        let bob = SessionUser::new(2, "bob".to_string(), "bob@acme.com".to_string(), true);
+        state.enroll_totp(&bob).await?;
+        let codes = state
+            .confirm_totp(2, &totp_code_at(&totp_secret(&state, 2).await?, now))
+            .await?
+            .recovery_codes;
+        state.disable_totp(2, &codes[0]).await?;
+        assert!(!state.totp_enabled(2).await?);
+
+        Ok(())
+    }
+}
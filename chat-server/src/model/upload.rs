@@ -0,0 +1,454 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use axum::body::BodyDataStream;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{DateTime, Duration, Utc};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use sqlx::prelude::FromRow;
+use tokio::{
+    fs::{self, OpenOptions},
+    io::{AsyncSeekExt, AsyncWriteExt, SeekFrom},
+    time::Instant,
+};
+use tracing::warn;
+
+use crate::{AppErr, AppState};
+
+// abandoned uploads are purged after this time since the last chunk
+const UPLOAD_EXPIRATION_HOURS: i64 = 24;
+const DEFAULT_FILENAME: &str = "file";
+const MAX_FILENAME_LEN: usize = 255;
+// a chunk being written holds a lease on the upload, renewed until the request ends
+const UPLOAD_LEASE_SECS: i64 = 60;
+const LEASE_RENEW_INTERVAL: std::time::Duration = std::time::Duration::from_secs(20);
+
+const SELECT_UPLOAD: &str = "SELECT id, chat_id, uploader_id, filename, length, upload_offset, file_url, expires_at FROM t_upload";
+
+/// Resumable upload of a file, see https://tus.io/protocols/resumable-upload
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Upload {
+    pub id: String,
+    pub chat_id: i64,
+    pub uploader_id: i64,
+    pub filename: String,
+    pub length: i64,
+    pub upload_offset: i64,
+    /// Reference of the stored file once the upload is complete
+    pub file_url: Option<String>,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug)]
+pub struct CreateUpload {
+    pub length: u64,
+    /// Decoded `Upload-Metadata` header
+    pub metadata: HashMap<String, String>,
+}
+
+impl AppState {
+    /// Create an upload, the content is appended with `append_upload`
+    pub async fn create_upload(
+        &self,
+        input: CreateUpload,
+        chat_id: i64,
+        uploader_id: i64,
+    ) -> Result<Upload, AppErr> {
+        self.ensure_chat_member(chat_id, uploader_id).await?;
+
+        let max_size = self.config.storage.max_file_size;
+        if input.length > max_size {
+            return Err(AppErr::PayloadTooLargeErr(format!(
+                "file is larger than {} bytes",
+                max_size
+            )));
+        }
+        if let Some(quota) = self
+            .remaining_quota(uploader_id, chat_id)
+            .await?
+            .filter(|quota| input.length > *quota)
+        {
+            return Err(AppErr::QuotaExceededErr(format!(
+                "file is larger than the remaining {} bytes",
+                quota
+            )));
+        }
+
+        let id = uuid::Uuid::now_v7().to_string();
+        let path = self.upload_path(&id);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        fs::File::create(&path).await?;
+
+        let filename = input
+            .metadata
+            .get("filename")
+            .map(|name| name.chars().take(MAX_FILENAME_LEN).collect())
+            .filter(|name: &String| !name.is_empty())
+            .unwrap_or_else(|| DEFAULT_FILENAME.to_string());
+
+        let upload = sqlx::query_as(
+            r#"
+            INSERT INTO t_upload (id, chat_id, uploader_id, filename, length, expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING id, chat_id, uploader_id, filename, length, upload_offset, file_url, expires_at
+            "#,
+        )
+        .bind(&id)
+        .bind(chat_id)
+        .bind(uploader_id)
+        .bind(filename)
+        .bind(input.length as i64)
+        .bind(Utc::now() + Duration::hours(UPLOAD_EXPIRATION_HOURS))
+        .fetch_one(&self.pg)
+        .await?;
+
+        Ok(upload)
+    }
+
+    /// Get an unexpired upload of the user
+    pub async fn get_upload(
+        &self,
+        id: &str,
+        chat_id: i64,
+        uploader_id: i64,
+    ) -> Result<Upload, AppErr> {
+        let upload: Option<Upload> = sqlx::query_as(&format!(
+            "{} WHERE id = $1 AND chat_id = $2 AND uploader_id = $3 AND expires_at > NOW()",
+            SELECT_UPLOAD
+        ))
+        .bind(id)
+        .bind(chat_id)
+        .bind(uploader_id)
+        .fetch_optional(&self.pg)
+        .await?;
+
+        upload.ok_or_else(|| AppErr::NotFoundErr(format!("upload {} not found", id)))
+    }
+
+    /// Append a chunk at the offset, the upload is stored as a chat file once complete.
+    /// If the client disconnects, the bytes received so far are kept so that it can resume.
+    pub async fn append_upload(
+        &self,
+        id: &str,
+        chat_id: i64,
+        uploader_id: i64,
+        offset: u64,
+        chunk: BodyDataStream,
+    ) -> Result<Upload, AppErr> {
+        let lease_id = uuid::Uuid::now_v7().to_string();
+        let upload = self
+            .claim_upload(id, chat_id, uploader_id, offset, &lease_id)
+            .await?;
+
+        let ret = self.write_upload(&upload, &lease_id, chunk).await;
+        if ret.is_err() {
+            if let Err(e) = self.release_upload(id, &lease_id).await {
+                warn!("failed to release upload {}: {}", id, e);
+            }
+        }
+
+        ret
+    }
+
+    /// Lease the upload to write a chunk at the offset, so that a chunk is only appended once
+    async fn claim_upload(
+        &self,
+        id: &str,
+        chat_id: i64,
+        uploader_id: i64,
+        offset: u64,
+        lease_id: &str,
+    ) -> Result<Upload, AppErr> {
+        let upload: Option<Upload> = sqlx::query_as(
+            r#"
+            UPDATE t_upload SET lease_id = $5, leased_until = $6
+            WHERE id = $1 AND chat_id = $2 AND uploader_id = $3 AND expires_at > NOW()
+                AND upload_offset = $4 AND file_url IS NULL
+                AND (leased_until IS NULL OR leased_until <= NOW())
+            RETURNING id, chat_id, uploader_id, filename, length, upload_offset, file_url, expires_at
+            "#,
+        )
+        .bind(id)
+        .bind(chat_id)
+        .bind(uploader_id)
+        .bind(offset as i64)
+        .bind(lease_id)
+        .bind(Utc::now() + Duration::seconds(UPLOAD_LEASE_SECS))
+        .fetch_optional(&self.pg)
+        .await?;
+        if let Some(upload) = upload {
+            return Ok(upload);
+        }
+
+        let upload = self.get_upload(id, chat_id, uploader_id).await?;
+        if upload.file_url.is_some() || offset != upload.upload_offset as u64 {
+            return Err(AppErr::ConflictErr(format!(
+                "offset of upload {} is {}",
+                id, upload.upload_offset
+            )));
+        }
+
+        Err(AppErr::ConflictErr(format!(
+            "upload {} is being written",
+            id
+        )))
+    }
+
+    async fn write_upload(
+        &self,
+        upload: &Upload,
+        lease_id: &str,
+        mut chunk: BodyDataStream,
+    ) -> Result<Upload, AppErr> {
+        let id = upload.id.as_str();
+        let offset = upload.upload_offset as u64;
+        let path = self.upload_path(id);
+        let mut file = OpenOptions::new().write(true).open(&path).await?;
+        // drop bytes of a previous request which were written but not committed
+        file.set_len(offset).await?;
+        file.seek(SeekFrom::End(0)).await?;
+
+        let length = upload.length as u64;
+        let mut written = offset;
+        let mut renew_at = Instant::now() + LEASE_RENEW_INTERVAL;
+        while let Some(data) = chunk.next().await {
+            let data = match data {
+                Ok(data) => data,
+                Err(e) => {
+                    warn!("upload {} interrupted at {}: {}", id, written, e);
+                    break;
+                }
+            };
+
+            if written + data.len() as u64 > length {
+                return Err(AppErr::PayloadTooLargeErr(format!(
+                    "upload {} is larger than {} bytes",
+                    id, length
+                )));
+            }
+            // renewed before writing, a stalled request must not write once the lease is taken over
+            if Instant::now() >= renew_at {
+                self.renew_upload(id, lease_id).await?;
+                renew_at = Instant::now() + LEASE_RENEW_INTERVAL;
+            }
+            file.write_all(&data).await?;
+            written += data.len() as u64;
+        }
+        file.flush().await?;
+        drop(file);
+
+        let mut file_url = None;
+        if written == length {
+            self.renew_upload(id, lease_id).await?;
+            let file = match self
+                .store_file(&path, &upload.filename, upload.chat_id, upload.uploader_id)
+                .await
+            {
+                Ok(file) => file,
+                // the content is rejected, there is nothing to resume
+                Err(
+                    e @ (AppErr::UnsupportedFileTypeErr(_)
+                    | AppErr::FileQuarantinedErr(_)
+                    | AppErr::PayloadTooLargeErr(_)),
+                ) => {
+                    sqlx::query("DELETE FROM t_upload WHERE id = $1")
+                        .bind(id)
+                        .execute(&self.pg)
+                        .await?;
+                    self.remove_upload_file(id).await?;
+                    return Err(e);
+                }
+                Err(e) => return Err(e),
+            };
+            fs::remove_file(&path).await?;
+            file_url = Some(file.url());
+        }
+
+        let upload: Option<Upload> = sqlx::query_as(
+            r#"
+            UPDATE t_upload SET upload_offset = $3, file_url = $4, expires_at = $5, lease_id = NULL, leased_until = NULL
+            WHERE id = $1 AND lease_id = $2
+            RETURNING id, chat_id, uploader_id, filename, length, upload_offset, file_url, expires_at
+            "#,
+        )
+        .bind(id)
+        .bind(lease_id)
+        .bind(written as i64)
+        .bind(file_url)
+        .bind(Utc::now() + Duration::hours(UPLOAD_EXPIRATION_HOURS))
+        .fetch_optional(&self.pg)
+        .await?;
+
+        upload.ok_or_else(|| AppErr::ConflictErr(format!("lease of upload {} is lost", id)))
+    }
+
+    async fn renew_upload(&self, id: &str, lease_id: &str) -> Result<(), AppErr> {
+        let ret =
+            sqlx::query("UPDATE t_upload SET leased_until = $3 WHERE id = $1 AND lease_id = $2")
+                .bind(id)
+                .bind(lease_id)
+                .bind(Utc::now() + Duration::seconds(UPLOAD_LEASE_SECS))
+                .execute(&self.pg)
+                .await?;
+
+        if ret.rows_affected() == 0 {
+            return Err(AppErr::ConflictErr(format!(
+                "lease of upload {} is lost",
+                id
+            )));
+        }
+
+        Ok(())
+    }
+
+    async fn release_upload(&self, id: &str, lease_id: &str) -> Result<(), AppErr> {
+        sqlx::query(
+            "UPDATE t_upload SET lease_id = NULL, leased_until = NULL WHERE id = $1 AND lease_id = $2",
+        )
+        .bind(id)
+        .bind(lease_id)
+        .execute(&self.pg)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Cancel an upload and delete its content
+    pub async fn delete_upload(
+        &self,
+        id: &str,
+        chat_id: i64,
+        uploader_id: i64,
+    ) -> Result<(), AppErr> {
+        let ret =
+            sqlx::query("DELETE FROM t_upload WHERE id = $1 AND chat_id = $2 AND uploader_id = $3")
+                .bind(id)
+                .bind(chat_id)
+                .bind(uploader_id)
+                .execute(&self.pg)
+                .await?;
+
+        if ret.rows_affected() == 0 {
+            return Err(AppErr::NotFoundErr(format!("upload {} not found", id)));
+        }
+
+        self.remove_upload_file(id).await
+    }
+
+    /// Delete expired uploads, whether abandoned or complete
+    pub async fn purge_uploads(&self) -> Result<(), AppErr> {
+        let ids: Vec<String> =
+            sqlx::query_scalar("DELETE FROM t_upload WHERE expires_at <= NOW() RETURNING id")
+                .fetch_all(&self.pg)
+                .await?;
+
+        for id in ids {
+            self.remove_upload_file(&id).await?;
+        }
+
+        Ok(())
+    }
+
+    fn upload_path(&self, id: &str) -> PathBuf {
+        self.config.storage.base_dir.join("uploads").join(id)
+    }
+
+    async fn remove_upload_file(&self, id: &str) -> Result<(), AppErr> {
+        match fs::remove_file(self.upload_path(id)).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Parse the `Upload-Metadata` header, e.g. `filename d29ybGQucG5n,is_confidential`
+pub fn parse_upload_metadata(header: &str) -> Result<HashMap<String, String>, AppErr> {
+    let mut metadata = HashMap::new();
+
+    for pair in header.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        let (key, value) = match pair.split_once(' ') {
+            Some((key, value)) => {
+                let value = STANDARD
+                    .decode(value.trim())
+                    .ok()
+                    .and_then(|value| String::from_utf8(value).ok())
+                    .ok_or_else(|| {
+                        AppErr::InvalidInputErr(format!("invalid upload metadata of {}", key))
+                    })?;
+                (key, value)
+            }
+            None => (pair, String::new()),
+        };
+        metadata.insert(key.to_string(), value);
+    }
+
+    Ok(metadata)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn test_parse_upload_metadata() {
+        let metadata =
+            parse_upload_metadata("filename d29ybGQucG5n, is_confidential,filetype aW1hZ2UvcG5n")
+                .unwrap();
+        assert_eq!(metadata["filename"], "world.png");
+        assert_eq!(metadata["filetype"], "image/png");
+        assert_eq!(metadata["is_confidential"], "");
+
+        assert!(parse_upload_metadata("").unwrap().is_empty());
+        assert!(parse_upload_metadata("filename !!!").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_append_upload() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreateUpload {
+            length: 10,
+            metadata: HashMap::from([("filename".to_string(), "a.txt".to_string())]),
+        };
+        let upload = state.create_upload(input, 1, 1).await?;
+        let body = |data: &'static str| axum::body::Body::from(data).into_data_stream();
+
+        let upload = state
+            .append_upload(&upload.id, 1, 1, 0, body("hello"))
+            .await?;
+        assert_eq!(upload.upload_offset, 5);
+        assert!(matches!(
+            state
+                .append_upload(&upload.id, 1, 1, 0, body("hello"))
+                .await,
+            Err(AppErr::ConflictErr(_))
+        ));
+
+        // a chunk being written by another request
+        state.claim_upload(&upload.id, 1, 1, 5, "other").await?;
+        assert!(matches!(
+            state
+                .append_upload(&upload.id, 1, 1, 5, body("world"))
+                .await,
+            Err(AppErr::ConflictErr(_))
+        ));
+        state.release_upload(&upload.id, "other").await?;
+
+        // a chunk larger than the upload releases the lease
+        assert!(matches!(
+            state
+                .append_upload(&upload.id, 1, 1, 5, body("world!"))
+                .await,
+            Err(AppErr::PayloadTooLargeErr(_))
+        ));
+        let upload = state.get_upload(&upload.id, 1, 1).await?;
+        assert_eq!(upload.upload_offset, 5);
+        state.claim_upload(&upload.id, 1, 1, 5, "other").await?;
+        state.delete_upload(&upload.id, 1, 1).await?;
+
+        Ok(())
+    }
+}
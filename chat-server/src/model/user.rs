@@ -1,12 +1,18 @@
-use argon2::{
-    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
-    Argon2,
-};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::prelude::FromRow;
+use tracing::{info, warn};
+
+use crate::{
+    util::{email_errors, passwd_errors, username_errors, FieldErrors},
+    AppErr, AppState,
+};
 
-use crate::{AppErr, AppState};
+use super::{avatar_errors, revoke_user_api_tokens};
+
+// unique indexes of t_user, their violations are answered with 409
+const USERNAME_INDEX: &str = "idx_user_username";
+const EMAIL_INDEX: &str = "idx_user_email";
 
 #[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -17,58 +23,157 @@ pub struct User {
     pub passwd: String,
     pub email: String,
     pub avatar: String,
+    /// Absent until the user opens the link mailed on signup
+    pub email_verified_at: Option<DateTime<Utc>>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
 
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, sqlx::Type,
+)]
+#[sqlx(type_name = "user_role", rename_all = "snake_case")]
+#[serde(rename_all = "camelCase")]
+pub enum Role {
+    Member,
+    Moderator,
+    Admin,
+}
+
 #[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct SignUpForm {
     pub username: String,
     pub email: String,
     pub passwd: String,
-    pub avatar: String,
+    /// Name of the session shown in the device list
+    pub device_name: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct SignInForm {
     pub email: String,
     pub passwd: String,
+    pub device_name: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChangePasswdForm {
+    pub current_passwd: String,
+    pub new_passwd: String,
+}
+
+/// Fields of the profile to change, absent ones are kept
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateProfileForm {
+    pub username: Option<String>,
+    /// An uploaded avatar like `/avatars/{sha256}.jpg`, an http(s) url, or empty for the identicon
+    pub avatar: Option<String>,
+}
+
+impl SignUpForm {
+    fn validate(&self) -> Result<(), AppErr> {
+        let mut errors = FieldErrors::default();
+        errors.add("username", username_errors(&self.username));
+        errors.add("email", email_errors(&self.email));
+        errors.add(
+            "passwd",
+            passwd_errors(&self.passwd, &[&self.username, &self.email]),
+        );
+        errors.into_result()
+    }
+}
+
+impl UpdateProfileForm {
+    fn validate(&self) -> Result<(), AppErr> {
+        let mut errors = FieldErrors::default();
+        if let Some(username) = &self.username {
+            errors.add("username", username_errors(username));
+        }
+        if let Some(avatar) = &self.avatar {
+            errors.add("avatar", avatar_errors(avatar));
+        }
+        errors.into_result()
+    }
 }
 
 impl AppState {
     /// Create a user
     pub async fn create_user(&self, form: SignUpForm) -> Result<User, AppErr> {
-        let passwd_hash = hash_passwd(&form.passwd)?;
+        form.validate()?;
+        let passwd_hash = self.passwd_hasher.hash_async(&form.passwd).await?;
 
-        let user = sqlx::query_as(
-            "INSERT INTO t_user (username, passwd, email, avatar) VALUES ($1, $2, $3, $4) RETURNING id, username, passwd, email, avatar, created_at, updated_at",
+        let user: User = sqlx::query_as(
+            "INSERT INTO t_user (username, passwd, email) VALUES ($1, $2, $3) RETURNING id, username, passwd, email, avatar, email_verified_at, created_at, updated_at",
         )
         .bind(&form.username)
         .bind(&passwd_hash)
         .bind(&form.email)
-        .bind(&form.avatar)
         .fetch_one(&self.pg)
+        .await
+        .map_err(taken)?;
+
+        Ok(user.with_default_avatar())
+    }
+
+    /// Change the username or avatar of the user
+    pub async fn update_profile(
+        &self,
+        user_id: i64,
+        form: UpdateProfileForm,
+    ) -> Result<User, AppErr> {
+        form.validate()?;
+
+        if let Some(username) = &form.username {
+            sqlx::query("UPDATE t_user SET username = $2, updated_at = NOW() WHERE id = $1")
+                .bind(user_id)
+                .bind(username)
+                .execute(&self.pg)
+                .await
+                .map_err(taken)?;
+        }
+        if let Some(avatar) = &form.avatar {
+            self.set_avatar(user_id, avatar).await?;
+        }
+
+        let user: Option<User> = sqlx::query_as(
+            "SELECT id, username, passwd, email, avatar, email_verified_at, created_at, updated_at FROM t_user WHERE id = $1",
+        )
+        .bind(user_id)
+        .fetch_optional(&self.pg)
         .await?;
 
-        Ok(user)
+        user.map(User::with_default_avatar)
+            .ok_or_else(|| AppErr::NotFoundErr(format!("user {} not found", user_id)))
     }
 
     /// Verify user email and password
     pub async fn verify_user(&self, form: SignInForm) -> Result<Option<User>, AppErr> {
         let user: Option<User> = sqlx::query_as(
-            "SELECT id, username, passwd, email, avatar, created_at, updated_at FROM t_user where emial = $1"
+            "SELECT id, username, passwd, email, avatar, email_verified_at, created_at, updated_at FROM t_user WHERE LOWER(email) = LOWER($1)"
         )
         .bind(&form.email)
         .fetch_optional(&self.pg)
         .await?;
 
         match user {
+            // users of an oidc provider have no password until they reset it
+            Some(user) if user.passwd.is_empty() => Ok(None),
             Some(user) => {
                 // verify user password
-                let is_valid = verify_passwd(&form.passwd, &user.passwd)?;
+                let is_valid = self
+                    .passwd_hasher
+                    .verify_async(&form.passwd, &user.passwd)
+                    .await?;
                 if is_valid {
-                    // check ad load workspace info
-                    Ok(Some(user))
+                    // the password is only known here, upgrade a hash of an outdated cost
+                    if let Err(e) = self.rehash_passwd(&user, &form.passwd).await {
+                        warn!("failed to rehash password of user {}: {}", user.id, e);
+                    }
+                    Ok(Some(user.with_default_avatar()))
                 } else {
                     Ok(None)
                 }
@@ -76,26 +181,128 @@ impl AppState {
             None => Ok(None),
         }
     }
-}
 
-fn hash_passwd(passwd: &str) -> Result<String, AppErr> {
-    let salt = SaltString::generate(&mut OsRng);
+    /// Change the password of a signed in user, revoke its api tokens and sign out the other sessions,
+    /// return the number of signed out sessions
+    pub async fn change_passwd(
+        &self,
+        user_id: i64,
+        form: ChangePasswdForm,
+        current_session: Option<&str>,
+    ) -> Result<u64, AppErr> {
+        let row: Option<(String, String, String)> =
+            sqlx::query_as("SELECT passwd, username, email FROM t_user WHERE id = $1")
+                .bind(user_id)
+                .fetch_optional(&self.pg)
+                .await?;
+        let Some((passwd_hash, username, email)) = row else {
+            return Err(AppErr::NotFoundErr(format!("user {} not found", user_id)));
+        };
+        if passwd_hash.is_empty() {
+            return Err(AppErr::PermissionDeniedErr(
+                "no password is set, use a password reset to set one".to_string(),
+            ));
+        }
+        if !self
+            .passwd_hasher
+            .verify_async(&form.current_passwd, &passwd_hash)
+            .await?
+        {
+            return Err(AppErr::PermissionDeniedErr(
+                "current password is incorrect".to_string(),
+            ));
+        }
+        let mut errors = FieldErrors::default();
+        errors.add(
+            "newPasswd",
+            passwd_errors(&form.new_passwd, &[&username, &email]),
+        );
+        errors.into_result()?;
 
-    let argon2 = Argon2::default();
-    let passwd_hash = argon2.hash_password(passwd.as_bytes(), &salt)?.to_string();
+        let mut tx = self.pg.begin().await?;
+        sqlx::query("UPDATE t_user SET passwd = $2, updated_at = NOW() WHERE id = $1")
+            .bind(user_id)
+            .bind(self.passwd_hasher.hash_async(&form.new_passwd).await?)
+            .execute(&mut *tx)
+            .await?;
+        let tokens = revoke_user_api_tokens(&mut tx, user_id).await?;
+        tx.commit().await?;
 
-    Ok(passwd_hash)
-}
+        let revoked = self.revoke_other_sessions(user_id, current_session).await?;
+        info!(
+            "password of user {} is changed, {} other sessions and {} api tokens are revoked",
+            user_id, revoked, tokens
+        );
+
+        Ok(revoked)
+    }
+
+    async fn rehash_passwd(&self, user: &User, passwd: &str) -> Result<(), AppErr> {
+        if !self.passwd_hasher.needs_rehash(&user.passwd)? {
+            return Ok(());
+        }
+
+        // skip it if the password is changed meanwhile
+        sqlx::query("UPDATE t_user SET passwd = $3 WHERE id = $1 AND passwd = $2")
+            .bind(user.id)
+            .bind(&user.passwd)
+            .bind(self.passwd_hasher.hash_async(passwd).await?)
+            .execute(&self.pg)
+            .await?;
+        info!("password hash of user {} is upgraded", user.id);
 
-fn verify_passwd(passwd: &str, passwd_hash: &str) -> Result<bool, AppErr> {
-    let argon2 = Argon2::default();
-    let passwd_hash = PasswordHash::new(passwd_hash)?;
+        Ok(())
+    }
+
+    /// Return an error if the user's role is lower than the required one
+    pub async fn ensure_role(&self, user_id: i64, required: Role) -> Result<(), AppErr> {
+        let role: Option<Role> = sqlx::query_scalar("SELECT role FROM t_user WHERE id = $1")
+            .bind(user_id)
+            .fetch_optional(&self.pg)
+            .await?;
+
+        match role {
+            Some(role) if role >= required => Ok(()),
+            _ => Err(AppErr::PermissionDeniedErr(format!(
+                "user {} is not {:?}",
+                user_id, required
+            ))),
+        }
+    }
+
+    /// Return an error if the user is suspended from sending messages
+    pub async fn ensure_not_suspended(&self, user_id: i64) -> Result<(), AppErr> {
+        let suspended_until: Option<DateTime<Utc>> = sqlx::query_scalar(
+            "SELECT suspended_until FROM t_user WHERE id = $1 AND suspended_until > NOW()",
+        )
+        .bind(user_id)
+        .fetch_optional(&self.pg)
+        .await?;
 
-    let is_valid = argon2
-        .verify_password(passwd.as_bytes(), &passwd_hash)
-        .is_ok();
+        match suspended_until {
+            Some(until) => Err(AppErr::PermissionDeniedErr(format!(
+                "user {} is suspended until {}",
+                user_id, until
+            ))),
+            None => Ok(()),
+        }
+    }
+}
 
-    Ok(is_valid)
+/// 409 if the username or email is used by another user
+pub(crate) fn taken(e: sqlx::Error) -> AppErr {
+    if let sqlx::Error::Database(db) = &e {
+        match db.constraint() {
+            Some(USERNAME_INDEX) => {
+                return AppErr::ConflictErr("username is already taken".to_string())
+            }
+            Some(EMAIL_INDEX) => {
+                return AppErr::ConflictErr("email is already registered".to_string())
+            }
+            _ => {}
+        }
+    }
+    e.into()
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -103,6 +310,8 @@ pub struct SessionUser {
     pub id: i64,
     pub username: String,
     pub email: String,
+    /// Unverified users only have access to a few routes, see `verify_token`
+    pub verified: bool,
 }
 
 impl From<User> for SessionUser {
@@ -111,41 +320,18 @@ impl From<User> for SessionUser {
             id: user.id,
             username: user.username,
             email: user.email,
+            verified: user.email_verified_at.is_some(),
         }
     }
 }
 
 impl SessionUser {
-    pub fn new(id: i64, username: String, email: String) -> Self {
+    pub fn new(id: i64, username: String, email: String, verified: bool) -> Self {
         Self {
             id,
             username,
             email,
+            verified,
         }
     }
 }
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use anyhow::{Ok, Result};
-
-    #[test]
-    fn test_hash_and_verify_passwd() -> Result<()> {
-        let passwd = "test_password";
-        let passwd_hash = hash_passwd(passwd).expect("Failed to hash password");
-
-        assert!(verify_passwd(passwd, &passwd_hash).expect("Failed to verify password"));
-        Ok(())
-    }
-
-    #[test]
-    fn test_verify_invalid_passwd() -> Result<()> {
-        let passwd = "test_password";
-        let invalid_passwd = "invalid_password";
-        let passwd_hash = hash_passwd(passwd).expect("Failed to hash password");
-
-        assert!(!verify_passwd(invalid_passwd, &passwd_hash).expect("Failed to verify password"));
-        Ok(())
-    }
-}
@@ -0,0 +1,150 @@
+mod rule;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+pub(crate) use rule::*;
+
+/// Decision of a moderation rule on an outgoing message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Decision {
+    Allow,
+    Reject { rule: String, reason: String },
+    Flag { rule: String, reason: String },
+}
+
+/// What to do with a message once a rule matches it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum RuleAction {
+    #[default]
+    Reject,
+    Flag,
+}
+
+pub trait ModerationRule: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Check the content, return the reason if it matches the rule
+    fn check(&self, content: &str) -> Option<String>;
+
+    fn action(&self) -> RuleAction;
+}
+
+/// Rule config stored in `t_moderation_rule.rule`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum RuleConfig {
+    #[serde(rename_all = "camelCase")]
+    BlockedWords {
+        words: Vec<String>,
+        #[serde(default)]
+        action: RuleAction,
+    },
+    #[serde(rename_all = "camelCase")]
+    Regex {
+        pattern: String,
+        #[serde(default)]
+        action: RuleAction,
+    },
+    #[serde(rename_all = "camelCase")]
+    LinkDomains {
+        #[serde(default)]
+        allow: Vec<String>,
+        #[serde(default)]
+        deny: Vec<String>,
+        #[serde(default)]
+        action: RuleAction,
+    },
+    #[serde(rename_all = "camelCase")]
+    MaxLength { max: usize },
+}
+
+/// Chain of moderation rules, a rejection stops the chain
+#[derive(Default)]
+pub struct Pipeline {
+    rules: Vec<Box<dyn ModerationRule>>,
+}
+
+impl Pipeline {
+    pub fn add(&mut self, rule: impl ModerationRule + 'static) {
+        self.rules.push(Box::new(rule));
+    }
+
+    pub fn add_config(&mut self, config: RuleConfig) -> Result<()> {
+        match config {
+            RuleConfig::BlockedWords { words, action } => {
+                self.add(BlockedWords::new(words, action))
+            }
+            RuleConfig::Regex { pattern, action } => {
+                self.add(RegexRule::try_new(&pattern, action)?)
+            }
+            RuleConfig::LinkDomains {
+                allow,
+                deny,
+                action,
+            } => self.add(LinkDomains::new(allow, deny, action)),
+            RuleConfig::MaxLength { max } => self.add(MaxLength::new(max)),
+        };
+
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    pub fn check(&self, content: &str) -> Decision {
+        let mut decision = Decision::Allow;
+
+        for rule in &self.rules {
+            let Some(reason) = rule.check(content) else {
+                continue;
+            };
+
+            let rule_name = rule.name().to_string();
+            match rule.action() {
+                RuleAction::Reject => {
+                    return Decision::Reject {
+                        rule: rule_name,
+                        reason,
+                    }
+                }
+                RuleAction::Flag => {
+                    // keep the first flag, later rules may still reject
+                    if decision == Decision::Allow {
+                        decision = Decision::Flag {
+                            rule: rule_name,
+                            reason,
+                        };
+                    }
+                }
+            }
+        }
+
+        decision
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pipeline_reject_wins_over_flag() -> Result<()> {
+        let mut pipeline = Pipeline::default();
+        pipeline.add(BlockedWords::new(
+            vec!["spam".to_string()],
+            RuleAction::Flag,
+        ));
+        pipeline.add_config(serde_json::from_str(r#"{"type": "maxLength", "max": 8}"#)?)?;
+
+        assert_eq!(pipeline.check("hello"), Decision::Allow);
+        assert!(matches!(pipeline.check("spam"), Decision::Flag { .. }));
+        assert!(matches!(
+            pipeline.check("spam spam spam"),
+            Decision::Reject { rule, .. } if rule == "maxLength"
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,184 @@
+use anyhow::Result;
+use regex::Regex;
+
+use super::{ModerationRule, RuleAction};
+
+pub struct BlockedWords {
+    words: Vec<String>,
+    action: RuleAction,
+}
+
+impl BlockedWords {
+    pub fn new(words: Vec<String>, action: RuleAction) -> Self {
+        let words = words
+            .into_iter()
+            .map(|w| w.to_lowercase())
+            .filter(|w| !w.is_empty())
+            .collect();
+        Self { words, action }
+    }
+}
+
+impl ModerationRule for BlockedWords {
+    fn name(&self) -> &'static str {
+        "blockedWords"
+    }
+
+    fn check(&self, content: &str) -> Option<String> {
+        let content = content.to_lowercase();
+        self.words
+            .iter()
+            .find(|w| content.contains(w.as_str()))
+            .map(|w| format!("contains blocked word \"{}\"", w))
+    }
+
+    fn action(&self) -> RuleAction {
+        self.action
+    }
+}
+
+pub struct RegexRule {
+    regex: Regex,
+    action: RuleAction,
+}
+
+impl RegexRule {
+    pub fn try_new(pattern: &str, action: RuleAction) -> Result<Self> {
+        Ok(Self {
+            regex: Regex::new(pattern)?,
+            action,
+        })
+    }
+}
+
+impl ModerationRule for RegexRule {
+    fn name(&self) -> &'static str {
+        "regex"
+    }
+
+    fn check(&self, content: &str) -> Option<String> {
+        self.regex
+            .is_match(content)
+            .then(|| format!("matches pattern \"{}\"", self.regex.as_str()))
+    }
+
+    fn action(&self) -> RuleAction {
+        self.action
+    }
+}
+
+/// Check domains of the links in content, an empty allow list allows any domain not denied.
+/// A domain also matches its sub domains.
+pub struct LinkDomains {
+    allow: Vec<String>,
+    deny: Vec<String>,
+    action: RuleAction,
+    link: Regex,
+}
+
+impl LinkDomains {
+    pub fn new(allow: Vec<String>, deny: Vec<String>, action: RuleAction) -> Self {
+        let lowercase = |v: Vec<String>| v.into_iter().map(|d| d.to_lowercase()).collect();
+        Self {
+            allow: lowercase(allow),
+            deny: lowercase(deny),
+            action,
+            link: Regex::new(r"(?i)\bhttps?://([^/\s:?#@]+)").expect("invalid link regex"),
+        }
+    }
+
+    fn matches(domain: &str, list: &[String]) -> bool {
+        list.iter().any(|d| {
+            domain == d
+                || domain
+                    .strip_suffix(d.as_str())
+                    .is_some_and(|sub| sub.ends_with('.'))
+        })
+    }
+}
+
+impl ModerationRule for LinkDomains {
+    fn name(&self) -> &'static str {
+        "linkDomains"
+    }
+
+    fn check(&self, content: &str) -> Option<String> {
+        self.link.captures_iter(content).find_map(|cap| {
+            let domain = cap[1].to_lowercase();
+            if Self::matches(&domain, &self.deny) {
+                Some(format!("link domain \"{}\" is denied", domain))
+            } else if !self.allow.is_empty() && !Self::matches(&domain, &self.allow) {
+                Some(format!("link domain \"{}\" is not allowed", domain))
+            } else {
+                None
+            }
+        })
+    }
+
+    fn action(&self) -> RuleAction {
+        self.action
+    }
+}
+
+pub struct MaxLength {
+    max: usize,
+}
+
+impl MaxLength {
+    pub fn new(max: usize) -> Self {
+        Self { max }
+    }
+}
+
+impl ModerationRule for MaxLength {
+    fn name(&self) -> &'static str {
+        "maxLength"
+    }
+
+    fn check(&self, content: &str) -> Option<String> {
+        let len = content.chars().count();
+        (len > self.max).then(|| format!("length {} exceeds {}", len, self.max))
+    }
+
+    fn action(&self) -> RuleAction {
+        RuleAction::Reject
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blocked_words() {
+        let rule = BlockedWords::new(vec!["Foo".to_string()], RuleAction::Reject);
+        assert!(rule.check("say FOO bar").is_some());
+        assert!(rule.check("say bar").is_none());
+    }
+
+    #[test]
+    fn test_link_domains() {
+        let rule = LinkDomains::new(
+            vec!["acme.com".to_string()],
+            vec!["evil.acme.com".to_string()],
+            RuleAction::Reject,
+        );
+        assert!(rule.check("see https://docs.acme.com/a").is_none());
+        assert!(rule.check("see http://ACME.com").is_none());
+        assert!(rule.check("see https://evil.acme.com/x").is_some());
+        assert!(rule.check("see https://notacme.com").is_some());
+        assert!(rule.check("no links here").is_none());
+    }
+
+    #[test]
+    fn test_regex_and_max_length() -> Result<()> {
+        let rule = RegexRule::try_new(r"\d{11}", RuleAction::Flag)?;
+        assert!(rule.check("call 13800000000").is_some());
+        assert!(rule.check("call me").is_none());
+
+        let rule = MaxLength::new(3);
+        assert!(rule.check("你好呀").is_none());
+        assert!(rule.check("hello").is_some());
+        Ok(())
+    }
+}
@@ -0,0 +1,52 @@
+use serde::Serialize;
+
+use crate::{
+    model::{Bookmark, Draft, ModerationWarning, RevokedSession},
+    AppErr, AppState,
+};
+
+// notify-server listens on this channel and pushes events to the connected users
+const USER_EVENT_CHANNEL: &str = "user_event";
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", content = "data", rename_all = "camelCase")]
+pub enum AppEvent {
+    DraftUpdated(Draft),
+    BookmarkReminder(Bookmark),
+    ModerationWarning(ModerationWarning),
+    SessionRevoked(RevokedSession),
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Notification<'a> {
+    user_ids: &'a [i64],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    origin_device: Option<&'a str>,
+    #[serde(flatten)]
+    event: AppEvent,
+}
+
+impl AppState {
+    /// Push an event to all connected devices of the users except the origin device
+    pub async fn notify_users(
+        &self,
+        user_ids: &[i64],
+        origin_device: Option<&str>,
+        event: AppEvent,
+    ) -> Result<(), AppErr> {
+        let payload = serde_json::to_string(&Notification {
+            user_ids,
+            origin_device,
+            event,
+        })?;
+
+        sqlx::query("SELECT pg_notify($1, $2)")
+            .bind(USER_EVENT_CHANNEL)
+            .bind(payload)
+            .execute(&self.pg)
+            .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,54 @@
+mod provider;
+
+use std::collections::HashMap;
+
+use chat_core::OidcProviderConfig;
+use serde::Deserialize;
+
+pub(crate) use provider::OidcProvider;
+
+/// Authorization request of a sign in, the secrets are kept until the callback
+#[derive(Debug, Clone)]
+pub struct AuthorizationRequest {
+    pub url: String,
+    pub state: String,
+    pub nonce: String,
+    pub code_verifier: String,
+}
+
+/// Claims of a validated ID token used to find or create the user
+#[derive(Debug, Clone, Deserialize)]
+pub struct IdTokenClaims {
+    pub sub: String,
+    pub email: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_bool_or_string")]
+    pub email_verified: bool,
+    pub name: Option<String>,
+    pub preferred_username: Option<String>,
+    pub nonce: Option<String>,
+}
+
+pub fn new_oidc_providers(configs: &[OidcProviderConfig]) -> HashMap<String, OidcProvider> {
+    configs
+        .iter()
+        .map(|config| (config.name.clone(), OidcProvider::new(config.clone())))
+        .collect()
+}
+
+// some providers send `"email_verified": "true"`
+fn deserialize_bool_or_string<'de, D>(deserializer: D) -> Result<bool, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum BoolOrString {
+        Bool(bool),
+        String(String),
+    }
+
+    Ok(match BoolOrString::deserialize(deserializer)? {
+        BoolOrString::Bool(b) => b,
+        BoolOrString::String(s) => s == "true",
+    })
+}
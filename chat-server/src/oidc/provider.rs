@@ -0,0 +1,475 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chat_core::OidcProviderConfig;
+use jsonwebtoken::{decode, decode_header, jwk::JwkSet, Algorithm, DecodingKey, Validation};
+use reqwest::{Client, Url};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+
+use crate::{util::generate_token, AppErr};
+
+use super::{AuthorizationRequest, IdTokenClaims};
+
+// discovered metadata and keys are refetched after a while, or at once for an unknown key id
+const METADATA_TTL: Duration = Duration::from_secs(60 * 60);
+const MIN_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+const HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+// ID tokens are signed with asymmetric keys, HS256 with the client secret is not accepted
+const ID_TOKEN_ALGORITHMS: &[Algorithm] = &[
+    Algorithm::RS256,
+    Algorithm::RS384,
+    Algorithm::RS512,
+    Algorithm::PS256,
+    Algorithm::PS384,
+    Algorithm::PS512,
+    Algorithm::ES256,
+    Algorithm::ES384,
+    Algorithm::EdDSA,
+];
+
+#[derive(Debug, Clone, Deserialize)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+    #[serde(default)]
+    token_endpoint_auth_methods_supported: Vec<String>,
+}
+
+#[derive(Debug)]
+struct Discovered {
+    metadata: ProviderMetadata,
+    jwks: JwkSet,
+    fetched_at: Instant,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+/// OpenID Connect relying party of a provider, with the authorization code flow and PKCE
+pub struct OidcProvider {
+    config: OidcProviderConfig,
+    client: Client,
+    discovered: RwLock<Option<Arc<Discovered>>>,
+}
+
+impl OidcProvider {
+    pub fn new(config: OidcProviderConfig) -> Self {
+        Self {
+            config,
+            client: Client::builder()
+                .timeout(HTTP_TIMEOUT)
+                .build()
+                .expect("http client of default tls"),
+            discovered: RwLock::new(None),
+        }
+    }
+
+    /// Url of the provider's login page, with new state, nonce and PKCE verifier
+    pub async fn authorization_request(&self) -> Result<AuthorizationRequest, AppErr> {
+        let discovered = self.discover(false).await?;
+        let state = generate_token();
+        let nonce = generate_token();
+        let code_verifier = generate_token();
+
+        let mut url = Url::parse(&discovered.metadata.authorization_endpoint)
+            .map_err(|e| self.err(format!("invalid authorization endpoint: {}", e)))?;
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &self.config.client_id)
+            .append_pair("redirect_uri", &self.config.redirect_uri)
+            .append_pair("scope", &self.config.scopes.join(" "))
+            .append_pair("state", &state)
+            .append_pair("nonce", &nonce)
+            .append_pair("code_challenge", &code_challenge(&code_verifier))
+            .append_pair("code_challenge_method", "S256");
+
+        Ok(AuthorizationRequest {
+            url: url.to_string(),
+            state,
+            nonce,
+            code_verifier,
+        })
+    }
+
+    /// Redeem the authorization code and validate the returned ID token
+    pub async fn exchange_code(
+        &self,
+        code: &str,
+        code_verifier: &str,
+        nonce: &str,
+    ) -> Result<IdTokenClaims, AppErr> {
+        let discovered = self.discover(false).await?;
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", &self.config.redirect_uri),
+            ("code_verifier", code_verifier),
+        ];
+        let mut req = self.client.post(&discovered.metadata.token_endpoint);
+        match &self.config.client_secret {
+            // client_secret_basic is the default of the spec
+            Some(secret) if discovered.uses_basic_auth() => {
+                req = req.basic_auth(&self.config.client_id, Some(secret));
+            }
+            Some(secret) => {
+                form.push(("client_id", &self.config.client_id));
+                form.push(("client_secret", secret));
+            }
+            None => form.push(("client_id", &self.config.client_id)),
+        }
+
+        let res = req
+            .form(&form)
+            .send()
+            .await
+            .map_err(|e| self.err(format!("token request failed: {}", e)))?;
+        if !res.status().is_success() {
+            let status = res.status();
+            // the code is invalid, expired or already used, the details are for us only
+            let body = res.text().await.unwrap_or_default();
+            warn!(
+                "{} rejected the authorization code with {}: {}",
+                self.config.name, status, body
+            );
+            return Err(AppErr::AuthErr(format!(
+                "{} rejected the authorization code",
+                self.config.name
+            )));
+        }
+        let token: TokenResponse = res
+            .json()
+            .await
+            .map_err(|e| self.err(format!("invalid token response: {}", e)))?;
+
+        self.validate_id_token(&token.id_token, nonce).await
+    }
+
+    /// Check the signature with the provider's keys, the issuer, audience, expiry and nonce
+    async fn validate_id_token(
+        &self,
+        id_token: &str,
+        nonce: &str,
+    ) -> Result<IdTokenClaims, AppErr> {
+        let invalid = |msg: String| AppErr::AuthErr(format!("invalid ID token: {}", msg));
+        let header = decode_header(id_token).map_err(|e| invalid(e.to_string()))?;
+        if !ID_TOKEN_ALGORITHMS.contains(&header.alg) {
+            return Err(invalid(format!(
+                "algorithm {:?} is not allowed",
+                header.alg
+            )));
+        }
+
+        let mut discovered = self.discover(false).await?;
+        let jwk = match discovered.find_key(header.kid.as_deref()) {
+            Some(jwk) => jwk,
+            None => {
+                // the provider may have rotated its keys
+                discovered = self.discover(true).await?;
+                discovered
+                    .find_key(header.kid.as_deref())
+                    .ok_or_else(|| invalid(format!("unknown key {:?}", header.kid)))?
+            }
+        };
+        let key = DecodingKey::from_jwk(jwk).map_err(|e| invalid(e.to_string()))?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&discovered.metadata.issuer]);
+        validation.set_audience(&[&self.config.client_id]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+        let claims = decode::<IdTokenClaims>(id_token, &key, &validation)
+            .map_err(|e| invalid(e.to_string()))?
+            .claims;
+
+        if claims.nonce.as_deref() != Some(nonce) {
+            return Err(invalid("nonce mismatch".to_string()));
+        }
+
+        Ok(claims)
+    }
+
+    /// Metadata and keys of the provider, cached for `METADATA_TTL`
+    async fn discover(&self, refresh: bool) -> Result<Arc<Discovered>, AppErr> {
+        if let Some(discovered) = self.discovered.read().await.as_ref() {
+            let age = discovered.fetched_at.elapsed();
+            if age < METADATA_TTL && (!refresh || age < MIN_REFRESH_INTERVAL) {
+                return Ok(discovered.clone());
+            }
+        }
+
+        let mut cached = self.discovered.write().await;
+        // another request may have fetched it meanwhile
+        if let Some(discovered) = cached
+            .as_ref()
+            .filter(|d| d.fetched_at.elapsed() < MIN_REFRESH_INTERVAL)
+        {
+            return Ok(discovered.clone());
+        }
+        let url = format!(
+            "{}/.well-known/openid-configuration",
+            self.config.issuer.trim_end_matches('/')
+        );
+        let metadata: ProviderMetadata = self.get_json(&url).await?;
+        // a mismatch would let ID tokens of another issuer pass
+        if metadata.issuer != self.config.issuer {
+            return Err(self.err(format!(
+                "discovered issuer {} is not {}",
+                metadata.issuer, self.config.issuer
+            )));
+        }
+        let jwks: JwkSet = self.get_json(&metadata.jwks_uri).await?;
+        info!(
+            "discovered oidc provider {} with {} keys",
+            self.config.name,
+            jwks.keys.len()
+        );
+
+        let discovered = Arc::new(Discovered {
+            metadata,
+            jwks,
+            fetched_at: Instant::now(),
+        });
+        *cached = Some(discovered.clone());
+
+        Ok(discovered)
+    }
+
+    async fn get_json<T: serde::de::DeserializeOwned>(&self, url: &str) -> Result<T, AppErr> {
+        self.client
+            .get(url)
+            .send()
+            .await
+            .and_then(|res| res.error_for_status())
+            .map_err(|e| self.err(format!("request of {} failed: {}", url, e)))?
+            .json()
+            .await
+            .map_err(|e| self.err(format!("invalid response of {}: {}", url, e)))
+    }
+
+    fn err(&self, msg: String) -> AppErr {
+        AppErr::OidcErr(format!("{}: {}", self.config.name, msg))
+    }
+}
+
+impl Discovered {
+    fn find_key(&self, kid: Option<&str>) -> Option<&jsonwebtoken::jwk::Jwk> {
+        match kid {
+            Some(kid) => self.jwks.find(kid),
+            // without a key id the provider must have a single key
+            None if self.jwks.keys.len() == 1 => self.jwks.keys.first(),
+            None => None,
+        }
+    }
+
+    fn uses_basic_auth(&self) -> bool {
+        let methods = &self.metadata.token_endpoint_auth_methods_supported;
+        methods.is_empty() || methods.iter().any(|m| m == "client_secret_basic")
+    }
+}
+
+/// S256 code challenge of the PKCE verifier
+fn code_challenge(code_verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+    };
+
+    use axum::{
+        extract::State,
+        http::StatusCode,
+        response::{IntoResponse, Response},
+        routing::{get, post},
+        Form, Json, Router,
+    };
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use serde_json::json;
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    const CLIENT_ID: &str = "chat-client";
+
+    /// Local identity provider, `login` plays the user signing in on its login page
+    #[derive(Clone)]
+    struct MockIdp {
+        issuer: String,
+        // code -> (code challenge, nonce)
+        codes: Arc<Mutex<HashMap<String, (String, String)>>>,
+    }
+
+    impl MockIdp {
+        async fn start() -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let idp = Self {
+                issuer: format!("http://{}", listener.local_addr().unwrap()),
+                codes: Arc::default(),
+            };
+            let app = Router::new()
+                .route("/.well-known/openid-configuration", get(discovery))
+                .route("/jwks", get(jwks))
+                .route("/token", post(token))
+                .with_state(idp.clone());
+            tokio::spawn(async move { axum::serve(listener, app).await });
+            idp
+        }
+
+        fn provider(&self) -> OidcProvider {
+            OidcProvider::new(OidcProviderConfig {
+                name: "mock".to_string(),
+                issuer: self.issuer.clone(),
+                client_id: CLIENT_ID.to_string(),
+                client_secret: Some("secret".to_string()),
+                redirect_uri: "http://localhost:3000/oidc/mock/callback".to_string(),
+                scopes: vec!["openid".to_string(), "email".to_string()],
+            })
+        }
+
+        fn login(&self, authorization_url: &str) -> String {
+            let url = Url::parse(authorization_url).unwrap();
+            let param = |name: &str| {
+                url.query_pairs()
+                    .find(|(k, _)| k == name)
+                    .map(|(_, v)| v.to_string())
+                    .unwrap()
+            };
+            assert_eq!(param("client_id"), CLIENT_ID);
+            assert_eq!(param("code_challenge_method"), "S256");
+
+            let code = generate_token();
+            self.codes
+                .lock()
+                .unwrap()
+                .insert(code.clone(), (param("code_challenge"), param("nonce")));
+            code
+        }
+    }
+
+    async fn discovery(State(idp): State<MockIdp>) -> impl IntoResponse {
+        Json(json!({
+            "issuer": idp.issuer,
+            "authorization_endpoint": format!("{}/authorize", idp.issuer),
+            "token_endpoint": format!("{}/token", idp.issuer),
+            "jwks_uri": format!("{}/jwks", idp.issuer),
+            "token_endpoint_auth_methods_supported": ["client_secret_post"],
+        }))
+    }
+
+    async fn jwks() -> impl IntoResponse {
+        // the raw key is the tail of the SubjectPublicKeyInfo
+        let pem = include_str!("../../fixtures/public.pem");
+        let der: String = pem.lines().filter(|l| !l.starts_with("-----")).collect();
+        let der = base64::engine::general_purpose::STANDARD
+            .decode(der)
+            .unwrap();
+        Json(json!({
+            "keys": [{
+                "kty": "OKP",
+                "crv": "Ed25519",
+                "kid": "k1",
+                "alg": "EdDSA",
+                "x": URL_SAFE_NO_PAD.encode(&der[der.len() - 32..]),
+            }]
+        }))
+    }
+
+    async fn token(
+        State(idp): State<MockIdp>,
+        Form(form): Form<HashMap<String, String>>,
+    ) -> Response {
+        assert_eq!(form["client_secret"], "secret");
+        let Some((challenge, nonce)) = idp.codes.lock().unwrap().remove(&form["code"]) else {
+            return (StatusCode::BAD_REQUEST, "invalid_grant").into_response();
+        };
+        if code_challenge(&form["code_verifier"]) != challenge {
+            return (StatusCode::BAD_REQUEST, "invalid_grant").into_response();
+        }
+
+        let claims = json!({
+            "iss": idp.issuer,
+            "aud": CLIENT_ID,
+            "sub": "alice",
+            "exp": chrono::Utc::now().timestamp() + 300,
+            "email": "alice@acme.com",
+            "email_verified": "true",
+            "nonce": nonce,
+        });
+        let mut header = Header::new(Algorithm::EdDSA);
+        header.kid = Some("k1".to_string());
+        let key = EncodingKey::from_ed_pem(include_bytes!("../../fixtures/private.pem")).unwrap();
+        let id_token = encode(&header, &claims, &key).unwrap();
+
+        Json(json!({ "id_token": id_token, "token_type": "Bearer" })).into_response()
+    }
+
+    #[tokio::test]
+    async fn test_authorization_code_flow() {
+        let idp = MockIdp::start().await;
+        let provider = idp.provider();
+
+        let request = provider.authorization_request().await.unwrap();
+        assert!(request
+            .url
+            .starts_with(&format!("{}/authorize?", idp.issuer)));
+        let code = idp.login(&request.url);
+
+        let claims = provider
+            .exchange_code(&code, &request.code_verifier, &request.nonce)
+            .await
+            .unwrap();
+        assert_eq!(claims.sub, "alice");
+        assert_eq!(claims.email.as_deref(), Some("alice@acme.com"));
+        assert!(claims.email_verified);
+
+        // codes are single use
+        let err = provider
+            .exchange_code(&code, &request.code_verifier, &request.nonce)
+            .await;
+        assert!(matches!(err, Err(AppErr::AuthErr(_))));
+    }
+
+    #[tokio::test]
+    async fn test_reject_wrong_verifier_and_nonce() {
+        let idp = MockIdp::start().await;
+        let provider = idp.provider();
+
+        let request = provider.authorization_request().await.unwrap();
+        let code = idp.login(&request.url);
+        let err = provider
+            .exchange_code(&code, &generate_token(), &request.nonce)
+            .await;
+        assert!(matches!(err, Err(AppErr::AuthErr(_))));
+
+        let request = provider.authorization_request().await.unwrap();
+        let code = idp.login(&request.url);
+        let err = provider
+            .exchange_code(&code, &request.code_verifier, "another nonce")
+            .await;
+        assert!(matches!(err, Err(AppErr::AuthErr(msg)) if msg.contains("nonce")));
+    }
+
+    #[tokio::test]
+    async fn test_reject_issuer_mismatch() {
+        let idp = MockIdp::start().await;
+        let provider = OidcProvider::new(OidcProviderConfig {
+            issuer: format!("{}/", idp.issuer),
+            ..idp.provider().config
+        });
+
+        let err = provider.authorization_request().await;
+        assert!(matches!(err, Err(AppErr::OidcErr(_))));
+    }
+}
@@ -0,0 +1,142 @@
+use std::{path::Path, time::Duration};
+
+use async_trait::async_trait;
+use tokio::{
+    fs,
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    time,
+};
+
+use crate::AppErr;
+
+use super::{ScanResult, Scanner};
+
+// clamd rejects chunks larger than its StreamMaxLength, keep them small
+const CHUNK_SIZE: usize = 64 * 1024;
+const SCAN_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Client of clamd, streaming files with the INSTREAM command,
+/// see https://docs.clamav.net/manual/Usage/Scanning.html#clamd
+#[derive(Debug, Clone)]
+pub struct ClamAvScanner {
+    addr: String,
+}
+
+impl ClamAvScanner {
+    pub fn new(addr: String) -> Self {
+        Self { addr }
+    }
+
+    async fn instream(&self, path: &Path) -> Result<String, AppErr> {
+        let mut file = fs::File::open(path).await?;
+        let mut conn = TcpStream::connect(&self.addr).await?;
+        conn.write_all(b"zINSTREAM\0").await?;
+
+        let mut buf = vec![0; CHUNK_SIZE];
+        loop {
+            let n = file.read(&mut buf).await?;
+            conn.write_all(&(n as u32).to_be_bytes()).await?;
+            if n == 0 {
+                break;
+            }
+            conn.write_all(&buf[..n]).await?;
+        }
+        conn.flush().await?;
+
+        let mut reply = vec![];
+        conn.read_to_end(&mut reply).await?;
+        Ok(String::from_utf8_lossy(&reply)
+            .trim_end_matches(['\0', '\n'])
+            .to_string())
+    }
+}
+
+#[async_trait]
+impl Scanner for ClamAvScanner {
+    async fn scan(&self, path: &Path) -> Result<ScanResult, AppErr> {
+        let reply = time::timeout(SCAN_TIMEOUT, self.instream(path))
+            .await
+            .map_err(|_| AppErr::StorageErr(format!("clamd {} timed out", self.addr)))??;
+
+        parse_reply(&reply)
+    }
+}
+
+/// Parse replies like `stream: OK` or `stream: Eicar-Signature FOUND`
+fn parse_reply(reply: &str) -> Result<ScanResult, AppErr> {
+    let result = reply.strip_prefix("stream: ").unwrap_or(reply);
+
+    if result == "OK" {
+        Ok(ScanResult::Clean)
+    } else if let Some(signature) = result.strip_suffix(" FOUND") {
+        Ok(ScanResult::Infected(signature.to_string()))
+    } else {
+        Err(AppErr::StorageErr(format!("clamd error: {}", reply)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    // answers like clamd, files containing `EICAR` are reported as infected
+    async fn stub_clamd() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+
+        tokio::spawn(async move {
+            loop {
+                let (mut conn, _) = listener.accept().await.unwrap();
+                let mut command = [0; 10];
+                conn.read_exact(&mut command).await.unwrap();
+                assert_eq!(&command, b"zINSTREAM\0");
+
+                let mut data = vec![];
+                loop {
+                    let len = conn.read_u32().await.unwrap() as usize;
+                    if len == 0 {
+                        break;
+                    }
+                    let mut chunk = vec![0; len];
+                    conn.read_exact(&mut chunk).await.unwrap();
+                    data.extend(chunk);
+                }
+
+                let reply: &[u8] = if data.windows(5).any(|w| w == b"EICAR") {
+                    b"stream: Eicar-Signature FOUND\0"
+                } else {
+                    b"stream: OK\0"
+                };
+                conn.write_all(reply).await.unwrap();
+            }
+        });
+
+        addr
+    }
+
+    #[tokio::test]
+    async fn test_clamav_scan() {
+        let scanner = ClamAvScanner::new(stub_clamd().await);
+        let dir = std::env::temp_dir().join(format!("clamav-test-{}", uuid::Uuid::now_v7()));
+        fs::create_dir_all(&dir).await.unwrap();
+
+        let clean = dir.join("clean.txt");
+        fs::write(&clean, vec![b'a'; CHUNK_SIZE + 1]).await.unwrap();
+        assert_eq!(scanner.scan(&clean).await.unwrap(), ScanResult::Clean);
+
+        let infected = dir.join("infected.txt");
+        fs::write(&infected, b"X5O!P%@AP EICAR-STANDARD-ANTIVIRUS-TEST-FILE")
+            .await
+            .unwrap();
+        assert_eq!(
+            scanner.scan(&infected).await.unwrap(),
+            ScanResult::Infected("Eicar-Signature".to_string())
+        );
+
+        assert!(parse_reply("INSTREAM size limit exceeded. ERROR").is_err());
+        fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
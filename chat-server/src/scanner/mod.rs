@@ -0,0 +1,31 @@
+mod clamav;
+
+use std::{path::Path, sync::Arc};
+
+use async_trait::async_trait;
+use chat_core::ScannerConfig;
+
+use crate::AppErr;
+
+pub(crate) use clamav::ClamAvScanner;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScanResult {
+    Clean,
+    /// Name of the detected signature
+    Infected(String),
+}
+
+/// Malware scanner of uploaded files
+#[async_trait]
+pub trait Scanner: Send + Sync {
+    async fn scan(&self, path: &Path) -> Result<ScanResult, AppErr>;
+}
+
+pub fn new_scanner(config: Option<&ScannerConfig>) -> Option<Arc<dyn Scanner>> {
+    let scanner: Arc<dyn Scanner> = match config? {
+        ScannerConfig::ClamAv { addr } => Arc::new(ClamAvScanner::new(addr.clone())),
+    };
+
+    Some(scanner)
+}
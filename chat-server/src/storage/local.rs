@@ -0,0 +1,68 @@
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use tokio::fs;
+use tokio_util::io::ReaderStream;
+
+use super::{Storage, StorageObject};
+use crate::AppErr;
+
+pub struct LocalStorage {
+    dir: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+}
+
+#[async_trait]
+impl Storage for LocalStorage {
+    async fn put(&self, key: &str, path: &Path) -> Result<(), AppErr> {
+        let dst = self.dir.join(key);
+        if let Some(parent) = dst.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+
+        // copy then rename, so that a half written blob is never visible
+        let tmp = dst.with_extension(format!("tmp-{}", uuid::Uuid::now_v7()));
+        fs::copy(path, &tmp).await?;
+        fs::rename(&tmp, &dst).await?;
+
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<StorageObject>, AppErr> {
+        let file = match fs::File::open(self.dir.join(key)).await {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        let size = file.metadata().await?.len();
+        Ok(Some(StorageObject {
+            size,
+            stream: Box::pin(ReaderStream::new(file)),
+        }))
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), AppErr> {
+        match fs::remove_file(self.dir.join(key)).await {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    async fn exists(&self, key: &str) -> Result<bool, AppErr> {
+        Ok(fs::try_exists(self.dir.join(key)).await?)
+    }
+
+    async fn presign(&self, _key: &str, _expires_in: Duration) -> Result<Option<String>, AppErr> {
+        Ok(None)
+    }
+}
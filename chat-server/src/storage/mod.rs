@@ -0,0 +1,64 @@
+mod local;
+mod s3;
+mod sigv4;
+
+use std::{path::Path, pin::Pin, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use chat_core::StorageBackend;
+use futures::Stream;
+
+use crate::AppErr;
+
+pub(crate) use local::LocalStorage;
+pub(crate) use s3::S3Storage;
+
+pub type ByteStream = Pin<Box<dyn Stream<Item = std::io::Result<Bytes>> + Send>>;
+
+pub struct StorageObject {
+    pub size: u64,
+    pub stream: ByteStream,
+}
+
+/// Blob storage of attachments
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// Store the content of a local file under the key
+    async fn put(&self, key: &str, path: &Path) -> Result<(), AppErr>;
+
+    /// Return `None` if the key does not exist
+    async fn get(&self, key: &str) -> Result<Option<StorageObject>, AppErr>;
+
+    async fn delete(&self, key: &str) -> Result<(), AppErr>;
+
+    async fn exists(&self, key: &str) -> Result<bool, AppErr>;
+
+    /// URL to download the blob from the backend directly,
+    /// `None` if the blob can only be served through chat-server
+    async fn presign(&self, key: &str, expires_in: Duration) -> Result<Option<String>, AppErr>;
+}
+
+pub fn new_storage(backend: &StorageBackend) -> Result<Arc<dyn Storage>, AppErr> {
+    let storage: Arc<dyn Storage> = match backend {
+        StorageBackend::Local { dir } => Arc::new(LocalStorage::new(dir.clone())),
+        StorageBackend::S3(config) => Arc::new(S3Storage::try_new(config.clone())?),
+    };
+
+    Ok(storage)
+}
+
+/// Storage key of a blob with the given content hash
+pub fn blob_key(hash: &str) -> String {
+    format!("{}/{}/{}", &hash[..2], &hash[2..4], hash)
+}
+
+/// Storage key of a JPEG thumbnail of an image blob
+pub fn thumbnail_key(hash: &str, size: u32) -> String {
+    format!("{}_{}.jpg", blob_key(hash), size)
+}
+
+/// Storage key of a rendered avatar, content addressed by the uploaded image
+pub fn avatar_key(hash: &str, size: u32) -> String {
+    format!("avatars/{}_{}.jpg", hash, size)
+}
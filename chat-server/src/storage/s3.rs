@@ -0,0 +1,297 @@
+use std::{io, path::Path, time::Duration};
+
+use async_trait::async_trait;
+use chat_core::S3Config;
+use chrono::Utc;
+use futures::TryStreamExt;
+use reqwest::{Body, Client, Method, Response, StatusCode, Url};
+use tokio::fs;
+use tokio_util::io::ReaderStream;
+
+use super::{
+    sigv4::{self, Credentials, UNSIGNED_PAYLOAD},
+    Storage, StorageObject,
+};
+use crate::AppErr;
+
+/// Storage of S3 compatible services, e.g. AWS S3, MinIO
+pub struct S3Storage {
+    client: Client,
+    config: S3Config,
+    endpoint: Url,
+}
+
+impl S3Storage {
+    pub fn try_new(config: S3Config) -> Result<Self, AppErr> {
+        let endpoint = Url::parse(&config.endpoint)
+            .map_err(|e| AppErr::StorageErr(format!("invalid s3 endpoint: {}", e)))?;
+        if endpoint.host_str().is_none() {
+            return Err(AppErr::StorageErr(format!(
+                "invalid s3 endpoint: {}",
+                config.endpoint
+            )));
+        }
+
+        Ok(Self {
+            client: Client::new(),
+            config,
+            endpoint,
+        })
+    }
+
+    fn credentials(&self) -> Credentials<'_> {
+        Credentials {
+            access_key: &self.config.access_key,
+            secret_key: &self.config.secret_key,
+            region: &self.config.region,
+        }
+    }
+
+    /// Host header and path of the object
+    fn locate(&self, key: &str) -> (String, String) {
+        let mut host = self.endpoint.host_str().unwrap_or_default().to_string();
+        if let Some(port) = self.endpoint.port() {
+            host = format!("{}:{}", host, port);
+        }
+
+        if self.config.path_style {
+            (host, format!("/{}/{}", self.config.bucket, key))
+        } else {
+            (
+                format!("{}.{}", self.config.bucket, host),
+                format!("/{}", key),
+            )
+        }
+    }
+
+    async fn send(
+        &self,
+        method: Method,
+        key: &str,
+        body: Option<(Body, u64)>,
+    ) -> Result<Response, AppErr> {
+        let (host, path) = self.locate(key);
+        let now = Utc::now();
+        let date = sigv4::amz_date(now);
+
+        let headers = [
+            ("host", host.as_str()),
+            ("x-amz-content-sha256", UNSIGNED_PAYLOAD),
+            ("x-amz-date", date.as_str()),
+        ];
+        let authorization = sigv4::authorization(
+            &self.credentials(),
+            method.as_str(),
+            &path,
+            &[],
+            &headers,
+            UNSIGNED_PAYLOAD,
+            now,
+        );
+
+        let url = format!(
+            "{}://{}{}",
+            self.endpoint.scheme(),
+            host,
+            sigv4::uri_encode(&path, false)
+        );
+        let mut req = self
+            .client
+            .request(method, url)
+            .header("x-amz-content-sha256", UNSIGNED_PAYLOAD)
+            .header("x-amz-date", date)
+            .header("authorization", authorization);
+        if let Some((body, len)) = body {
+            req = req.header("content-length", len).body(body);
+        }
+
+        req.send()
+            .await
+            .map_err(|e| AppErr::StorageErr(format!("s3 request of {} failed: {}", key, e)))
+    }
+}
+
+async fn ensure_success(res: Response, key: &str) -> Result<Response, AppErr> {
+    if res.status().is_success() {
+        return Ok(res);
+    }
+
+    let status = res.status();
+    let body = res.text().await.unwrap_or_default();
+    Err(AppErr::StorageErr(format!(
+        "s3 request of {} failed with {}: {}",
+        key, status, body
+    )))
+}
+
+#[async_trait]
+impl Storage for S3Storage {
+    async fn put(&self, key: &str, path: &Path) -> Result<(), AppErr> {
+        let file = fs::File::open(path).await?;
+        let len = file.metadata().await?.len();
+        let body = Body::wrap_stream(ReaderStream::new(file));
+
+        let res = self.send(Method::PUT, key, Some((body, len))).await?;
+        ensure_success(res, key).await?;
+
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<StorageObject>, AppErr> {
+        let res = self.send(Method::GET, key, None).await?;
+        if res.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+
+        let res = ensure_success(res, key).await?;
+        let size = res.content_length().unwrap_or_default();
+        let stream = res.bytes_stream().map_err(io::Error::other);
+
+        Ok(Some(StorageObject {
+            size,
+            stream: Box::pin(stream),
+        }))
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), AppErr> {
+        let res = self.send(Method::DELETE, key, None).await?;
+        ensure_success(res, key).await?;
+
+        Ok(())
+    }
+
+    async fn exists(&self, key: &str) -> Result<bool, AppErr> {
+        let res = self.send(Method::HEAD, key, None).await?;
+        if res.status() == StatusCode::NOT_FOUND {
+            return Ok(false);
+        }
+
+        ensure_success(res, key).await?;
+        Ok(true)
+    }
+
+    async fn presign(&self, key: &str, expires_in: Duration) -> Result<Option<String>, AppErr> {
+        let (host, path) = self.locate(key);
+        let query = sigv4::presign(
+            &self.credentials(),
+            "GET",
+            &host,
+            &path,
+            expires_in.as_secs(),
+            Utc::now(),
+        );
+
+        Ok(Some(format!(
+            "{}://{}{}?{}",
+            self.endpoint.scheme(),
+            host,
+            sigv4::uri_encode(&path, false),
+            query
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+    };
+
+    use anyhow::Result;
+    use axum::{
+        body::Bytes,
+        extract::{Path as UrlPath, State},
+        http::{HeaderMap, Method as HttpMethod, StatusCode as HttpStatus},
+        response::IntoResponse,
+        routing::any,
+        Router,
+    };
+    use futures::StreamExt;
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    type Objects = Arc<Mutex<HashMap<String, Bytes>>>;
+
+    // a minimal MinIO-like stand-in, objects are kept in memory
+    async fn object_handler(
+        State(objects): State<Objects>,
+        method: HttpMethod,
+        headers: HeaderMap,
+        UrlPath(path): UrlPath<String>,
+        body: Bytes,
+    ) -> impl IntoResponse {
+        let signed = headers
+            .get("authorization")
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.starts_with("AWS4-HMAC-SHA256 Credential=minio/"));
+        if !signed {
+            return (HttpStatus::FORBIDDEN, Bytes::new());
+        }
+
+        let mut objects = objects.lock().unwrap();
+        match method {
+            HttpMethod::PUT => {
+                objects.insert(path, body);
+                (HttpStatus::OK, Bytes::new())
+            }
+            HttpMethod::GET | HttpMethod::HEAD => match objects.get(&path) {
+                Some(data) => (HttpStatus::OK, data.clone()),
+                None => (HttpStatus::NOT_FOUND, Bytes::new()),
+            },
+            HttpMethod::DELETE => {
+                objects.remove(&path);
+                (HttpStatus::NO_CONTENT, Bytes::new())
+            }
+            _ => (HttpStatus::METHOD_NOT_ALLOWED, Bytes::new()),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_s3_storage_against_stand_in() -> Result<()> {
+        let objects = Objects::default();
+        let app = Router::new()
+            .route("/*path", any(object_handler))
+            .with_state(objects.clone());
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let storage = S3Storage::try_new(S3Config {
+            endpoint: format!("http://{}", addr),
+            bucket: "chat".to_string(),
+            region: "us-east-1".to_string(),
+            access_key: "minio".to_string(),
+            secret_key: "minio123".to_string(),
+            path_style: true,
+        })?;
+
+        let path = std::env::temp_dir().join(format!("s3-test-{}", uuid::Uuid::now_v7()));
+        fs::write(&path, b"hello s3").await?;
+
+        let key = "ab/cd/abcd";
+        assert!(!storage.exists(key).await?);
+        storage.put(key, &path).await?;
+        assert!(storage.exists(key).await?);
+        assert!(objects.lock().unwrap().contains_key("chat/ab/cd/abcd"));
+
+        let obj = storage.get(key).await?.expect("object should exist");
+        let data: Vec<Bytes> = obj.stream.map(|chunk| chunk.unwrap()).collect().await;
+        assert_eq!(data.concat(), b"hello s3");
+
+        let url = storage
+            .presign(key, Duration::from_secs(60))
+            .await?
+            .expect("s3 should presign");
+        assert!(url.starts_with(&format!("http://{}/chat/ab/cd/abcd?", addr)));
+        assert!(url.contains("X-Amz-Signature="));
+
+        storage.delete(key).await?;
+        assert!(!storage.exists(key).await?);
+        assert!(storage.get(key).await?.is_none());
+
+        fs::remove_file(&path).await?;
+        Ok(())
+    }
+}
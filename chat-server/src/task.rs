@@ -0,0 +1,92 @@
+use std::{future::Future, time::Duration};
+
+use tokio::time;
+use tracing::warn;
+
+use crate::{AppErr, AppState};
+
+const REMINDER_INTERVAL: Duration = Duration::from_secs(10);
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Spawn periodic background tasks of chat-server
+pub fn spawn_tasks(state: &AppState) {
+    spawn_periodic(
+        state,
+        "send due reminders",
+        REMINDER_INTERVAL,
+        |state| async move { state.send_due_reminders().await },
+    );
+    spawn_periodic(
+        state,
+        "purge bookmarks",
+        PURGE_INTERVAL,
+        |state| async move { state.purge_bookmarks().await },
+    );
+    spawn_periodic(state, "purge files", PURGE_INTERVAL, |state| async move {
+        state.purge_files().await
+    });
+    spawn_periodic(state, "purge uploads", PURGE_INTERVAL, |state| async move {
+        state.purge_uploads().await
+    });
+    spawn_periodic(
+        state,
+        "purge refresh tokens",
+        PURGE_INTERVAL,
+        |state| async move { state.purge_refresh_tokens().await },
+    );
+    spawn_periodic(
+        state,
+        "purge revoked tokens",
+        PURGE_INTERVAL,
+        |state| async move { state.purge_revoked_tokens().await },
+    );
+    spawn_periodic(
+        state,
+        "purge email verifications",
+        PURGE_INTERVAL,
+        |state| async move { state.purge_email_verifications().await },
+    );
+    spawn_periodic(
+        state,
+        "purge password resets",
+        PURGE_INTERVAL,
+        |state| async move { state.purge_passwd_resets().await },
+    );
+    spawn_periodic(
+        state,
+        "purge login challenges",
+        PURGE_INTERVAL,
+        |state| async move { state.purge_login_challenges().await },
+    );
+    spawn_periodic(
+        state,
+        "purge oidc states",
+        PURGE_INTERVAL,
+        |state| async move { state.purge_oidc_states().await },
+    );
+    spawn_periodic(
+        state,
+        "listen revoked tokens",
+        RECONNECT_INTERVAL,
+        |state| async move { state.listen_revoked_tokens().await },
+    );
+}
+
+fn spawn_periodic<F, Fut>(state: &AppState, name: &'static str, period: Duration, task: F)
+where
+    F: Fn(AppState) -> Fut + Send + 'static,
+    Fut: Future<Output = Result<(), AppErr>> + Send,
+{
+    let state = state.clone();
+
+    tokio::spawn(async move {
+        let mut interval = time::interval(period);
+        loop {
+            interval.tick().await;
+            if let Err(e) = task(state.clone()).await {
+                warn!("failed to {}: {}", name, e);
+            }
+        }
+    });
+}
@@ -0,0 +1,48 @@
+use std::{collections::HashMap, sync::RwLock};
+
+/// Ids of revoked tokens until they expire, checked on every request without a database query.
+/// Entries are dropped once the token would be rejected as expired anyway,
+/// so the size is bounded by the revocations within one token lifetime.
+#[derive(Debug, Default)]
+pub struct TokenDenylist {
+    // jti -> exp
+    entries: RwLock<HashMap<String, i64>>,
+}
+
+impl TokenDenylist {
+    pub fn insert(&self, jti: String, exp: i64) {
+        let mut entries = self.entries.write().unwrap_or_else(|e| e.into_inner());
+        entries.insert(jti, exp);
+    }
+
+    pub fn contains(&self, jti: &str, now: i64) -> bool {
+        let entries = self.entries.read().unwrap_or_else(|e| e.into_inner());
+        entries.get(jti).is_some_and(|exp| *exp > now)
+    }
+
+    /// Drop the entries of expired tokens
+    pub fn prune(&self, now: i64) {
+        let mut entries = self.entries.write().unwrap_or_else(|e| e.into_inner());
+        entries.retain(|_, exp| *exp > now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_denylist() {
+        let denylist = TokenDenylist::default();
+        denylist.insert("a".to_string(), 100);
+        denylist.insert("b".to_string(), 200);
+
+        assert!(denylist.contains("a", 50));
+        assert!(!denylist.contains("a", 100));
+        assert!(!denylist.contains("c", 50));
+
+        denylist.prune(150);
+        assert_eq!(denylist.entries.read().unwrap().len(), 1);
+        assert!(denylist.contains("b", 150));
+    }
+}
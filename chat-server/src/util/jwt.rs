@@ -1,9 +1,23 @@
-use anyhow::Error;
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Error};
+use base64::{
+    engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD},
+    Engine,
+};
+use chat_core::AuthConfig;
 use chrono::Utc;
 use jsonwebtoken::{
-    decode, encode, errors::ErrorKind, Algorithm, DecodingKey, EncodingKey, Validation,
+    decode, decode_header, encode,
+    errors::ErrorKind,
+    jwk::{
+        AlgorithmParameters, CommonParameters, EllipticCurve, Jwk, JwkSet, KeyAlgorithm,
+        OctetKeyPairParameters, OctetKeyPairType, PublicKeyUse,
+    },
+    Algorithm, DecodingKey, EncodingKey, Header, Validation,
 };
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
 use crate::{model::SessionUser, AppErr};
 
@@ -11,61 +25,124 @@ use super::{JWT_AUD, JWT_EXPIRATION_TIME, JWT_ISS};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
+    jti: String,
+    // session of the sign in, absent in tokens issued before sessions
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sid: Option<String>,
     uid: i64,
     username: String,
     email: String,
+    // whether the email is verified, tokens issued before verification existed are
+    #[serde(default = "default_verified")]
+    vrf: bool,
     iss: String,
     aud: String,
     exp: i64,
 }
 
+fn default_verified() -> bool {
+    true
+}
+
+/// Id and expiry of a verified access token, so that it can be revoked before it expires
+#[derive(Debug, Clone)]
+pub struct AccessToken {
+    pub jti: String,
+    pub sid: Option<String>,
+    pub exp: i64,
+}
+
+// DER prefix of an Ed25519 public key in SubjectPublicKeyInfo, followed by the 32 key bytes
+const ED25519_SPKI_PREFIX: &[u8] = &[
+    0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00,
+];
+
 // private key
 // openssl genpkey -algorithm ed25519 -out private.pem
-pub struct JwtEncodingKey(EncodingKey);
+pub struct JwtEncodingKey {
+    kid: String,
+    key: EncodingKey,
+}
 
 impl JwtEncodingKey {
-    pub fn load(pem: &str) -> Result<Self, Error> {
-        let ek = EncodingKey::from_ed_pem(pem.as_bytes())?;
-        Ok(Self(ek))
+    pub fn load(pem: &str, kid: &str) -> Result<Self, Error> {
+        let key = EncodingKey::from_ed_pem(pem.as_bytes())?;
+        Ok(Self {
+            kid: kid.to_string(),
+            key,
+        })
     }
 
-    pub fn sign(&self, user: impl Into<SessionUser>) -> Result<String, AppErr> {
+    pub fn sign(&self, user: impl Into<SessionUser>, sid: &str) -> Result<String, AppErr> {
         let session_user = user.into();
 
         let claims = Claims {
+            jti: uuid::Uuid::now_v7().to_string(),
+            sid: Some(sid.to_string()),
             uid: session_user.id,
             username: session_user.username,
             email: session_user.email,
+            vrf: session_user.verified,
             iss: JWT_ISS.to_string(),
             aud: JWT_AUD.to_string(),
             exp: Utc::now().timestamp() + JWT_EXPIRATION_TIME,
         };
 
-        let token = encode(
-            &jsonwebtoken::Header::new(Algorithm::EdDSA),
-            &claims,
-            &self.0,
-        )?;
+        let mut header = Header::new(Algorithm::EdDSA);
+        header.kid = Some(self.kid.clone());
+        let token = encode(&header, &claims, &self.key)?;
         Ok(token)
     }
 }
 
-// public key
+// public keys of the active and retired signing keys, selected by the kid of tokens
 // openssl pkey -in private.pem -outform PEM -pubout -out public.pem
-pub struct JwtDecodingKey(DecodingKey);
+pub struct JwtKeyRing {
+    active_kid: String,
+    keys: HashMap<String, DecodingKey>,
+    // published at /.well-known/jwks.json for other services
+    jwks: JwkSet,
+}
 
-impl JwtDecodingKey {
-    pub fn load(pem: &str) -> Result<Self, Error> {
-        let dk = DecodingKey::from_ed_pem(pem.as_bytes())?;
-        Ok(Self(dk))
+impl JwtKeyRing {
+    pub fn load(config: &AuthConfig) -> Result<Self, Error> {
+        let mut ring = Self {
+            active_kid: String::new(),
+            keys: HashMap::new(),
+            jwks: JwkSet { keys: Vec::new() },
+        };
+        ring.active_kid = ring.add(config.kid.as_deref(), &config.public_key)?;
+        for retired in &config.retired_keys {
+            ring.add(retired.kid.as_deref(), &retired.public_key)?;
+        }
+
+        Ok(ring)
+    }
+
+    /// Kid of the key new tokens are signed with
+    pub fn active_kid(&self) -> &str {
+        &self.active_kid
     }
 
-    pub fn verify(&self, token: &str) -> Result<SessionUser, AppErr> {
+    pub fn jwks(&self) -> &JwkSet {
+        &self.jwks
+    }
+
+    pub fn verify(&self, token: &str) -> Result<(SessionUser, AccessToken), AppErr> {
+        let invalid = || AppErr::AuthErr("invalid token".to_string());
+        let header = decode_header(token).map_err(|_| invalid())?;
+        // tokens issued before key ids existed were signed with the active key
+        let kid = header.kid.as_deref().unwrap_or(&self.active_kid);
+        let key = self
+            .keys
+            .get(kid)
+            .ok_or_else(|| AppErr::AuthErr("unknown signing key".to_string()))?;
+
         let mut validation = Validation::new(Algorithm::EdDSA);
         validation.set_issuer(&[JWT_ISS]);
         validation.set_audience(&[JWT_AUD]);
 
-        let token_data = match decode::<Claims>(token, &self.0, &validation) {
+        let token_data = match decode::<Claims>(token, key, &validation) {
             Ok(td) => td,
             Err(e) => {
                 let msg = match *e.kind() {
@@ -79,8 +156,65 @@ impl JwtDecodingKey {
         };
 
         let claims = token_data.claims;
-        Ok(SessionUser::new(claims.uid, claims.username, claims.email))
+        let access_token = AccessToken {
+            jti: claims.jti,
+            sid: claims.sid,
+            exp: claims.exp,
+        };
+        Ok((
+            SessionUser::new(claims.uid, claims.username, claims.email, claims.vrf),
+            access_token,
+        ))
     }
+
+    /// Add a public key, return its kid
+    fn add(&mut self, kid: Option<&str>, pem: &str) -> Result<String, Error> {
+        let x = URL_SAFE_NO_PAD.encode(ed25519_public_key(pem)?);
+        let kid = kid
+            .map(str::to_string)
+            .unwrap_or_else(|| jwk_thumbprint(&x));
+        if self.keys.contains_key(&kid) {
+            return Err(anyhow!("duplicate jwt key id {}", kid));
+        }
+
+        self.keys
+            .insert(kid.clone(), DecodingKey::from_ed_pem(pem.as_bytes())?);
+        self.jwks.keys.push(Jwk {
+            common: CommonParameters {
+                public_key_use: Some(PublicKeyUse::Signature),
+                key_algorithm: Some(KeyAlgorithm::EdDSA),
+                key_id: Some(kid.clone()),
+                ..Default::default()
+            },
+            algorithm: AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                key_type: OctetKeyPairType::OctetKeyPair,
+                curve: EllipticCurve::Ed25519,
+                x,
+            }),
+        });
+
+        Ok(kid)
+    }
+}
+
+/// Raw 32 bytes of an Ed25519 public key in PEM
+fn ed25519_public_key(pem: &str) -> Result<Vec<u8>, Error> {
+    let body: String = pem
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with("-----"))
+        .collect();
+    let der = STANDARD.decode(body)?;
+    match der.strip_prefix(ED25519_SPKI_PREFIX) {
+        Some(key) if key.len() == 32 => Ok(key.to_vec()),
+        _ => Err(anyhow!("public key is not an Ed25519 key")),
+    }
+}
+
+/// RFC 7638 thumbprint of an Ed25519 JWK, its members in lexicographic order
+fn jwk_thumbprint(x: &str) -> String {
+    let jwk = format!(r#"{{"crv":"Ed25519","kty":"OKP","x":"{}"}}"#, x);
+    URL_SAFE_NO_PAD.encode(Sha256::digest(jwk))
 }
 
 #[cfg(test)]
@@ -97,6 +231,7 @@ mod tests {
                 email,
                 passwd: "".to_string(),
                 avatar: "".to_string(),
+                email_verified_at: Some(Utc::now()),
                 created_at: Utc::now(),
                 updated_at: Utc::now(),
             }
@@ -107,16 +242,75 @@ mod tests {
     fn test_jwt_sign_and_verify() -> Result<()> {
         let user = User::new_for_test(0, "foo".to_string(), "foo@acme.com".to_string());
 
-        let ek = JwtEncodingKey::load(include_str!("../../fixtures/private.pem"))?;
-        let token = ek.sign(user.clone())?;
-        assert!(!token.is_empty());
+        let dk = JwtKeyRing::load(&auth_config(None))?;
+        let ek = JwtEncodingKey::load(include_str!("../../fixtures/private.pem"), dk.active_kid())?;
+        let token = ek.sign(user.clone(), "sid")?;
+        assert_eq!(decode_header(&token)?.kid.as_deref(), Some(dk.active_kid()));
 
-        let dk = JwtDecodingKey::load(include_str!("../../fixtures/public.pem"))?;
-        let session_user = dk.verify(&token)?;
+        let (session_user, access_token) = dk.verify(&token)?;
+        assert!(!access_token.jti.is_empty());
+        assert_eq!(access_token.sid.as_deref(), Some("sid"));
         assert_eq!(session_user.id, user.id);
         assert_eq!(session_user.username, user.username);
         assert_eq!(session_user.email, user.email);
+        assert!(session_user.verified);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_key_rotation() -> Result<()> {
+        let user = User::new_for_test(0, "foo".to_string(), "foo@acme.com".to_string());
+        let old = JwtKeyRing::load(&auth_config(None))?;
+        let old_ek =
+            JwtEncodingKey::load(include_str!("../../fixtures/private.pem"), old.active_kid())?;
+        let old_token = old_ek.sign(user.clone(), "sid")?;
+
+        // the retired key still verifies the tokens it signed
+        let dk = JwtKeyRing::load(&auth_config(Some(old.active_kid())))?;
+        assert_ne!(dk.active_kid(), old.active_kid());
+        assert_eq!(dk.jwks().keys.len(), 2);
+        assert!(dk.verify(&old_token).is_ok());
+
+        let ek = JwtEncodingKey::load(
+            include_str!("../../fixtures/next_private.pem"),
+            dk.active_kid(),
+        )?;
+        assert!(dk.verify(&ek.sign(user.clone(), "sid")?).is_ok());
+        // a token of the new key is rejected by a service which only knows the old one
+        assert!(old.verify(&ek.sign(user, "sid")?).is_err());
 
         Ok(())
     }
+
+    #[test]
+    fn test_jwk_thumbprint() {
+        // RFC 8037 appendix A.3
+        assert_eq!(
+            jwk_thumbprint("11qYAYKxCrfVS_7TyWQHOg7hcvPapiMlrwIaaPcHURo"),
+            "kPrK_qmxVWaYVA9wwBF6Iuo3vVzz7TxHCTwXBygrS4k"
+        );
+    }
+
+    /// Fixture keys, `next_public.pem` is the active one once `private.pem` is retired
+    fn auth_config(retired_kid: Option<&str>) -> AuthConfig {
+        let (public_key, retired_keys) = match retired_kid {
+            None => (include_str!("../../fixtures/public.pem"), vec![]),
+            Some(kid) => (
+                include_str!("../../fixtures/next_public.pem"),
+                vec![chat_core::JwtKeyConfig {
+                    kid: Some(kid.to_string()),
+                    public_key: include_str!("../../fixtures/public.pem").to_string(),
+                }],
+            ),
+        };
+        AuthConfig {
+            private_key: String::new(),
+            public_key: public_key.to_string(),
+            kid: None,
+            retired_keys,
+            argon2: Default::default(),
+            oidc: vec![],
+        }
+    }
 }
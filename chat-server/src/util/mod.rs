@@ -1,8 +1,30 @@
+mod denylist;
 mod jwt;
+mod passwd;
+mod signed_url;
+mod token;
+mod totp;
+mod validation;
 
-pub(crate) use jwt::{JwtDecodingKey, JwtEncodingKey};
+pub(crate) use denylist::TokenDenylist;
+pub(crate) use jwt::{AccessToken, JwtEncodingKey, JwtKeyRing};
+pub(crate) use passwd::PasswdHasher;
+pub(crate) use signed_url::UrlSigner;
+pub(crate) use token::{generate_token, hash_token};
+#[cfg(test)]
+pub(crate) use totp::totp_code_at;
+pub(crate) use totp::{base32_encode, generate_totp_secret, totp_qr_svg, totp_uri, verify_totp};
+pub(crate) use validation::{
+    email_errors, is_username_char, passwd_errors, username_errors, FieldErrors, MAX_USERNAME_LEN,
+};
 
 const JWT_ISS: &str = "easy-chat";
 const JWT_AUD: &str = "chat-client";
 // jwt expire in 30 minutes
-const JWT_EXPIRATION_TIME: i64 = 30 * 60;
+pub(crate) const JWT_EXPIRATION_TIME: i64 = 30 * 60;
+// refresh token expire in 30 days, renewed on every rotation
+pub(crate) const REFRESH_TOKEN_EXPIRATION_TIME: i64 = 30 * 24 * 60 * 60;
+// sign in challenge of 2FA expire in 5 minutes
+pub(crate) const LOGIN_CHALLENGE_EXPIRATION_TIME: i64 = 5 * 60;
+// signed download url expire in 10 minutes
+pub(crate) const SIGNED_URL_EXPIRATION_TIME: i64 = 10 * 60;
@@ -0,0 +1,149 @@
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Algorithm, Argon2, Params, Version,
+};
+use chat_core::Argon2Config;
+
+use crate::AppErr;
+
+/// Argon2id hasher of passwords with the configured cost
+#[derive(Debug, Clone, Default)]
+pub struct PasswdHasher {
+    argon2: Argon2<'static>,
+}
+
+impl PasswdHasher {
+    pub fn new(config: &Argon2Config) -> Result<Self, AppErr> {
+        let params = Params::new(
+            config.memory_kib,
+            config.iterations,
+            config.parallelism,
+            None,
+        )
+        .map_err(argon2::password_hash::Error::from)?;
+
+        Ok(Self {
+            argon2: Argon2::new(Algorithm::Argon2id, Version::V0x13, params),
+        })
+    }
+
+    pub fn hash(&self, passwd: &str) -> Result<String, AppErr> {
+        let salt = SaltString::generate(&mut OsRng);
+        let passwd_hash = self
+            .argon2
+            .hash_password(passwd.as_bytes(), &salt)?
+            .to_string();
+
+        Ok(passwd_hash)
+    }
+
+    /// Verify with the parameters stored in the hash, which may differ from the configured ones
+    pub fn verify(&self, passwd: &str, passwd_hash: &str) -> Result<bool, AppErr> {
+        let passwd_hash = PasswordHash::new(passwd_hash)?;
+
+        let is_valid = self
+            .argon2
+            .verify_password(passwd.as_bytes(), &passwd_hash)
+            .is_ok();
+
+        Ok(is_valid)
+    }
+
+    /// `hash` on the blocking thread pool, as hashing is slow by design
+    pub async fn hash_async(&self, passwd: &str) -> Result<String, AppErr> {
+        let hasher = self.clone();
+        let passwd = passwd.to_string();
+        tokio::task::spawn_blocking(move || hasher.hash(&passwd))
+            .await
+            .map_err(|e| AppErr::AnyhowErr(e.into()))?
+    }
+
+    /// `verify` on the blocking thread pool
+    pub async fn verify_async(&self, passwd: &str, passwd_hash: &str) -> Result<bool, AppErr> {
+        let hasher = self.clone();
+        let (passwd, passwd_hash) = (passwd.to_string(), passwd_hash.to_string());
+        tokio::task::spawn_blocking(move || hasher.verify(&passwd, &passwd_hash))
+            .await
+            .map_err(|e| AppErr::AnyhowErr(e.into()))?
+    }
+
+    /// Whether the hash was created with another algorithm or a lower cost than the configured one
+    pub fn needs_rehash(&self, passwd_hash: &str) -> Result<bool, AppErr> {
+        let passwd_hash = PasswordHash::new(passwd_hash)?;
+        if passwd_hash.algorithm != Algorithm::Argon2id.ident()
+            || passwd_hash.version != Some(Version::V0x13.into())
+        {
+            return Ok(true);
+        }
+
+        let stored = Params::try_from(&passwd_hash)?;
+        let current = self.argon2.params();
+        Ok(stored.m_cost() < current.m_cost()
+            || stored.t_cost() < current.t_cost()
+            || stored.p_cost() < current.p_cost())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{Ok, Result};
+
+    #[test]
+    fn test_hash_and_verify_passwd() -> Result<()> {
+        let hasher = PasswdHasher::default();
+        let passwd = "test_password";
+        let passwd_hash = hasher.hash(passwd).expect("Failed to hash password");
+
+        assert!(hasher
+            .verify(passwd, &passwd_hash)
+            .expect("Failed to verify password"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_invalid_passwd() -> Result<()> {
+        let hasher = PasswdHasher::default();
+        let passwd = "test_password";
+        let invalid_passwd = "invalid_password";
+        let passwd_hash = hasher.hash(passwd).expect("Failed to hash password");
+
+        assert!(!hasher
+            .verify(invalid_passwd, &passwd_hash)
+            .expect("Failed to verify password"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_needs_rehash() -> Result<()> {
+        let weak = PasswdHasher::new(&Argon2Config {
+            memory_kib: 8 * 1024,
+            iterations: 1,
+            parallelism: 1,
+        })?;
+        let strong = PasswdHasher::default();
+        let weak_hash = weak.hash("test_password")?;
+        let strong_hash = strong.hash("test_password")?;
+
+        assert!(strong.needs_rehash(&weak_hash)?);
+        assert!(!strong.needs_rehash(&strong_hash)?);
+        assert!(!weak.needs_rehash(&strong_hash)?);
+        // a hash of another cost is still verified with its own parameters
+        assert!(strong.verify("test_password", &weak_hash)?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_hash_and_verify_async() -> Result<()> {
+        let hasher = PasswdHasher::default();
+        let passwd_hash = hasher.hash_async("test_password").await?;
+
+        assert!(hasher.verify_async("test_password", &passwd_hash).await?);
+        assert!(
+            !hasher
+                .verify_async("invalid_password", &passwd_hash)
+                .await?
+        );
+        Ok(())
+    }
+}
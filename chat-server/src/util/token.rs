@@ -0,0 +1,36 @@
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use sha2::{Digest, Sha256};
+
+const OPAQUE_TOKEN_LEN: usize = 32;
+
+/// Random url safe token for refresh, verification and reset links
+pub fn generate_token() -> String {
+    let mut buf = [0u8; OPAQUE_TOKEN_LEN];
+    OsRng.fill_bytes(&mut buf);
+    URL_SAFE_NO_PAD.encode(buf)
+}
+
+/// Only the digest of a token is stored, so that a leaked database does not leak usable tokens
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate_token() {
+        let token = generate_token();
+        assert_eq!(
+            URL_SAFE_NO_PAD.decode(&token).unwrap().len(),
+            OPAQUE_TOKEN_LEN
+        );
+        assert_ne!(token, generate_token());
+
+        let hash = hash_token(&token);
+        assert_eq!(hash.len(), 64);
+        assert_eq!(hash, hash_token(&token));
+    }
+}
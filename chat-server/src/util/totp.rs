@@ -0,0 +1,150 @@
+//! RFC 6238 time based one-time passwords, the HMAC-SHA1, 6 digits and 30 seconds variant
+//! which all authenticator apps support.
+
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use hmac::{Hmac, Mac};
+use qrcode::{render::svg, QrCode};
+use sha1::Sha1;
+
+use crate::AppErr;
+
+type HmacSha1 = Hmac<Sha1>;
+
+const TOTP_SECRET_LEN: usize = 20;
+const TOTP_STEP: i64 = 30;
+const TOTP_DIGITS: u32 = 6;
+// codes of the previous and the next step are accepted as well, for clock drift
+const TOTP_SKEW: i64 = 1;
+
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+pub fn generate_totp_secret() -> Vec<u8> {
+    let mut secret = vec![0u8; TOTP_SECRET_LEN];
+    OsRng.fill_bytes(&mut secret);
+    secret
+}
+
+/// Step of the code if it is valid at `now` (unix seconds)
+pub fn verify_totp(secret: &[u8], code: &str, now: i64) -> Option<i64> {
+    let current = now / TOTP_STEP;
+    (current - TOTP_SKEW..=current + TOTP_SKEW).find(|step| totp_code(secret, *step) == code)
+}
+
+/// Code of the step of `now`, as shown by an authenticator app
+#[cfg(test)]
+pub fn totp_code_at(secret: &[u8], now: i64) -> String {
+    totp_code(secret, now / TOTP_STEP)
+}
+
+/// `otpauth://` uri of the secret, the payload of the QR code scanned by authenticator apps,
+/// see https://github.com/google/google-authenticator/wiki/Key-Uri-Format
+pub fn totp_uri(secret: &[u8], issuer: &str, account: &str) -> String {
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        percent_encode(issuer),
+        percent_encode(account),
+        base32_encode(secret),
+        percent_encode(issuer),
+        TOTP_DIGITS,
+        TOTP_STEP
+    )
+}
+
+/// QR code of the uri as an SVG image
+pub fn totp_qr_svg(uri: &str) -> Result<String, AppErr> {
+    let code = QrCode::new(uri.as_bytes()).map_err(|e| AppErr::AnyhowErr(e.into()))?;
+    Ok(code.render::<svg::Color>().min_dimensions(200, 200).build())
+}
+
+/// Secret in the form typed into authenticator apps when the QR code can not be scanned
+pub fn base32_encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(5) * 8);
+    for chunk in data.chunks(5) {
+        let mut buf = [0u8; 5];
+        buf[..chunk.len()].copy_from_slice(chunk);
+        let bits = buf.iter().fold(0u64, |acc, b| acc << 8 | *b as u64);
+        // every 5 bits of the chunk is a character, without padding
+        let chars = (chunk.len() * 8).div_ceil(5);
+        for i in 0..chars {
+            let index = (bits >> (35 - i * 5)) & 0x1F;
+            out.push(BASE32_ALPHABET[index as usize] as char);
+        }
+    }
+    out
+}
+
+/// RFC 4226 HOTP of the step
+fn totp_code(secret: &[u8], step: i64) -> String {
+    let mut mac = HmacSha1::new_from_slice(secret).expect("HMAC accepts keys of any size");
+    mac.update(&step.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+
+    // dynamic truncation
+    let offset = (hash[hash.len() - 1] & 0x0F) as usize;
+    let bin = u32::from_be_bytes([
+        hash[offset] & 0x7F,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+    format!(
+        "{:0width$}",
+        bin % 10u32.pow(TOTP_DIGITS),
+        width = TOTP_DIGITS as usize
+    )
+}
+
+fn percent_encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // test vectors of RFC 6238 appendix B, truncated to 6 digits
+    const RFC_SECRET: &[u8] = b"12345678901234567890";
+
+    #[test]
+    fn test_totp_code() {
+        assert_eq!(totp_code(RFC_SECRET, 59 / TOTP_STEP), "287082");
+        assert_eq!(totp_code(RFC_SECRET, 1111111109 / TOTP_STEP), "081804");
+        assert_eq!(totp_code(RFC_SECRET, 1234567890 / TOTP_STEP), "005924");
+        assert_eq!(totp_code(RFC_SECRET, 20000000000 / TOTP_STEP), "353130");
+
+        assert_eq!(
+            verify_totp(RFC_SECRET, "081804", 1111111109),
+            Some(37037036)
+        );
+        // accepted within one step of drift
+        assert_eq!(
+            verify_totp(RFC_SECRET, "081804", 1111111109 + 30),
+            Some(37037036)
+        );
+        assert_eq!(verify_totp(RFC_SECRET, "081804", 1111111109 + 60), None);
+        assert_eq!(verify_totp(RFC_SECRET, "000000", 1111111109), None);
+    }
+
+    #[test]
+    fn test_totp_uri() {
+        assert_eq!(base32_encode(b"foobar"), "MZXW6YTBOI");
+        assert_eq!(
+            base32_encode(RFC_SECRET),
+            "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ"
+        );
+
+        let uri = totp_uri(RFC_SECRET, "easy chat", "foo@acme.com");
+        assert_eq!(
+            uri,
+            "otpauth://totp/easy%20chat:foo%40acme.com?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&issuer=easy%20chat&algorithm=SHA1&digits=6&period=30"
+        );
+        assert!(totp_qr_svg(&uri).unwrap().starts_with("<?xml"));
+    }
+}
@@ -0,0 +1,152 @@
+use std::{collections::BTreeMap, fmt};
+
+use lettre::Address;
+use serde::{Deserialize, Serialize};
+
+use crate::AppErr;
+
+// limits of the t_user columns
+pub(crate) const MAX_USERNAME_LEN: usize = 64;
+const MIN_USERNAME_LEN: usize = 2;
+const MAX_EMAIL_LEN: usize = 64;
+const MIN_PASSWD_LEN: usize = 8;
+// only the hash is stored, long passwords just cost hashing time
+const MAX_PASSWD_LEN: usize = 128;
+// besides letters and digits
+const USERNAME_PUNCTUATION: &[char] = &[' ', '_', '-', '.'];
+
+/// Errors of the invalid fields of a form, answered with 422
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct FieldErrors(BTreeMap<String, Vec<String>>);
+
+impl FieldErrors {
+    pub fn add(&mut self, field: &str, errors: Vec<String>) {
+        if !errors.is_empty() {
+            self.0.entry(field.to_string()).or_default().extend(errors);
+        }
+    }
+
+    pub fn into_result(self) -> Result<(), AppErr> {
+        if self.0.is_empty() {
+            Ok(())
+        } else {
+            Err(AppErr::ValidationErr(self))
+        }
+    }
+}
+
+impl fmt::Display for FieldErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let fields: Vec<&str> = self.0.keys().map(String::as_str).collect();
+        f.write_str(&fields.join(", "))
+    }
+}
+
+pub fn username_errors(username: &str) -> Vec<String> {
+    let mut errors = Vec::new();
+    let len = username.chars().count();
+    if !(MIN_USERNAME_LEN..=MAX_USERNAME_LEN).contains(&len) {
+        errors.push(format!(
+            "must have {} to {} characters",
+            MIN_USERNAME_LEN, MAX_USERNAME_LEN
+        ));
+    }
+    if !username.chars().all(is_username_char) {
+        errors.push("may only contain letters, digits, spaces, '_', '-' and '.'".to_string());
+    }
+    if username.trim() != username {
+        errors.push("must not start or end with a space".to_string());
+    }
+    errors
+}
+
+pub fn is_username_char(c: char) -> bool {
+    c.is_alphanumeric() || USERNAME_PUNCTUATION.contains(&c)
+}
+
+pub fn email_errors(email: &str) -> Vec<String> {
+    let mut errors = Vec::new();
+    if email.chars().count() > MAX_EMAIL_LEN {
+        errors.push(format!("must have at most {} characters", MAX_EMAIL_LEN));
+    }
+    // mail is only delivered to domains with a dot, not to hosts like localhost
+    match email.parse::<Address>() {
+        Ok(addr) if addr.domain().contains('.') => {}
+        _ => errors.push("is not a valid email address".to_string()),
+    }
+    errors
+}
+
+/// Strength rules of new passwords, which must not be the username or email given with them
+pub fn passwd_errors(passwd: &str, user_inputs: &[&str]) -> Vec<String> {
+    let mut errors = Vec::new();
+    let len = passwd.chars().count();
+    if len < MIN_PASSWD_LEN {
+        errors.push(format!("must have at least {} characters", MIN_PASSWD_LEN));
+    } else if len > MAX_PASSWD_LEN {
+        errors.push(format!("must have at most {} characters", MAX_PASSWD_LEN));
+    }
+    if !passwd.chars().any(char::is_alphabetic) || !passwd.chars().any(|c| c.is_ascii_digit()) {
+        errors.push("must contain both letters and digits".to_string());
+    }
+    let lower = passwd.to_lowercase();
+    let guessable = user_inputs.iter().any(|input| {
+        let input = input.to_lowercase();
+        lower == input
+            || input
+                .split_once('@')
+                .is_some_and(|(local, _)| lower == local)
+    });
+    if guessable {
+        errors.push("must not be the username or email".to_string());
+    }
+    errors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_username_and_email_errors() {
+        assert!(username_errors("Foo Bar_1.-").is_empty());
+        assert!(username_errors("张三").is_empty());
+        assert_eq!(username_errors("x").len(), 1);
+        assert_eq!(username_errors(&"x".repeat(65)).len(), 1);
+        assert_eq!(username_errors(" foo<script>").len(), 2);
+
+        assert!(email_errors("foo@acme.com").is_empty());
+        assert_eq!(email_errors("foo").len(), 1);
+        assert_eq!(email_errors("foo@localhost").len(), 1);
+        assert_eq!(email_errors("a b@acme.com").len(), 1);
+        assert_eq!(
+            email_errors(&format!("{}@acme.com", "x".repeat(60))).len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_passwd_errors() {
+        assert!(passwd_errors("hunter2hunter", &["foo", "foo@acme.com"]).is_empty());
+        assert_eq!(passwd_errors("abc1", &[]).len(), 1);
+        assert_eq!(passwd_errors("abcdefghij", &[]).len(), 1);
+        assert_eq!(passwd_errors("foobar123", &["FooBar123"]).len(), 1);
+        assert_eq!(passwd_errors("foobar123", &["foobar123@acme.com"]).len(), 1);
+        assert_eq!(passwd_errors("short", &["short"]).len(), 3);
+    }
+
+    #[test]
+    fn test_field_errors() {
+        let mut errors = FieldErrors::default();
+        errors.add("email", vec![]);
+        assert!(errors.0.is_empty());
+
+        errors.add("passwd", passwd_errors("abc", &[]));
+        errors.add("email", email_errors("foo"));
+        assert_eq!(errors.to_string(), "email, passwd");
+        assert!(matches!(
+            errors.into_result(),
+            Err(AppErr::ValidationErr(_))
+        ));
+    }
+}
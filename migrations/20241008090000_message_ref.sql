@@ -0,0 +1,16 @@
+-- message id generated by database
+ALTER TABLE t_message ALTER COLUMN id ADD GENERATED BY DEFAULT AS IDENTITY;
+
+-- forward and quote reference for message table
+ALTER TABLE t_message
+    ADD COLUMN forward_msg_id BIGINT,
+    ADD COLUMN forward_chat_id BIGINT,
+    ADD COLUMN forward_sender_id BIGINT,
+    ADD COLUMN quote_msg_id BIGINT,
+    ADD COLUMN deleted_at TIMESTAMPTZ;
+
+COMMENT ON COLUMN t_message.forward_msg_id IS '转发来源消息ID';
+COMMENT ON COLUMN t_message.forward_chat_id IS '转发来源聊天ID';
+COMMENT ON COLUMN t_message.forward_sender_id IS '转发来源发送者ID';
+COMMENT ON COLUMN t_message.quote_msg_id IS '引用消息ID';
+COMMENT ON COLUMN t_message.deleted_at IS '删除时间';
@@ -0,0 +1,16 @@
+-- draft table, one unsent draft per user / chat / thread
+CREATE TABLE IF NOT EXISTS t_draft (
+    user_id BIGINT NOT NULL,
+    chat_id BIGINT NOT NULL,
+    thread_id BIGINT NOT NULL DEFAULT 0,
+    content TEXT NOT NULL DEFAULT '',
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (user_id, chat_id, thread_id)
+);
+
+COMMENT ON TABLE t_draft IS '草稿表';
+COMMENT ON COLUMN t_draft.user_id IS '用户ID';
+COMMENT ON COLUMN t_draft.chat_id IS '聊天ID';
+COMMENT ON COLUMN t_draft.thread_id IS '话题ID（0 表示不属于话题）';
+COMMENT ON COLUMN t_draft.content IS '草稿内容（空表示已清除）';
+COMMENT ON COLUMN t_draft.updated_at IS '更新时间（客户端时间，后写者胜）';
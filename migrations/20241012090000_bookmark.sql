@@ -0,0 +1,21 @@
+-- bookmark table
+CREATE TABLE IF NOT EXISTS t_bookmark (
+    id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+    user_id BIGINT NOT NULL,
+    msg_id BIGINT NOT NULL,
+    remind_at TIMESTAMPTZ,
+    reminded_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (user_id, msg_id)
+);
+
+COMMENT ON TABLE t_bookmark IS '消息收藏表';
+COMMENT ON COLUMN t_bookmark.id IS '收藏ID';
+COMMENT ON COLUMN t_bookmark.user_id IS '用户ID';
+COMMENT ON COLUMN t_bookmark.msg_id IS '消息ID';
+COMMENT ON COLUMN t_bookmark.remind_at IS '提醒时间';
+COMMENT ON COLUMN t_bookmark.reminded_at IS '已提醒时间';
+COMMENT ON COLUMN t_bookmark.created_at IS '创建时间';
+
+-- create index for pending reminders
+CREATE INDEX idx_bookmark_remind_at ON t_bookmark (remind_at) WHERE reminded_at IS NULL;
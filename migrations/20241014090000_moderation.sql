@@ -0,0 +1,58 @@
+-- workspace of chat
+ALTER TABLE t_chat ADD COLUMN ws_id BIGINT NOT NULL DEFAULT 0;
+
+COMMENT ON COLUMN t_chat.ws_id IS '工作区ID';
+
+-- moderation rule scope enum: workspace / chat
+CREATE TYPE moderation_scope AS ENUM ('workspace', 'chat');
+
+-- moderation decision enum: allow / reject / flag
+CREATE TYPE moderation_decision AS ENUM ('allow', 'reject', 'flag');
+
+-- moderation rule table
+CREATE TABLE IF NOT EXISTS t_moderation_rule (
+    id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+    scope moderation_scope NOT NULL,
+    scope_id BIGINT NOT NULL,
+    rule JSONB NOT NULL,
+    enabled BOOLEAN NOT NULL DEFAULT TRUE,
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
+);
+
+COMMENT ON TABLE t_moderation_rule IS '内容审核规则表';
+COMMENT ON COLUMN t_moderation_rule.id IS '规则ID';
+COMMENT ON COLUMN t_moderation_rule.scope IS '作用范围';
+COMMENT ON COLUMN t_moderation_rule.scope_id IS '作用范围ID（工作区ID或聊天ID）';
+COMMENT ON COLUMN t_moderation_rule.rule IS '规则配置';
+COMMENT ON COLUMN t_moderation_rule.enabled IS '是否启用';
+COMMENT ON COLUMN t_moderation_rule.created_at IS '创建时间';
+COMMENT ON COLUMN t_moderation_rule.updated_at IS '更新时间';
+
+CREATE INDEX idx_moderation_rule_scope ON t_moderation_rule (scope, scope_id);
+
+-- moderation log table
+CREATE TABLE IF NOT EXISTS t_moderation_log (
+    id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+    chat_id BIGINT NOT NULL,
+    sender_id BIGINT NOT NULL,
+    msg_id BIGINT,
+    decision moderation_decision NOT NULL,
+    rule VARCHAR(64) NOT NULL DEFAULT '',
+    reason TEXT NOT NULL DEFAULT '',
+    content TEXT NOT NULL,
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
+);
+
+COMMENT ON TABLE t_moderation_log IS '内容审核日志表';
+COMMENT ON COLUMN t_moderation_log.id IS '日志ID';
+COMMENT ON COLUMN t_moderation_log.chat_id IS '聊天ID';
+COMMENT ON COLUMN t_moderation_log.sender_id IS '发送者ID';
+COMMENT ON COLUMN t_moderation_log.msg_id IS '消息ID（被拒绝时为空）';
+COMMENT ON COLUMN t_moderation_log.decision IS '审核结果';
+COMMENT ON COLUMN t_moderation_log.rule IS '命中规则';
+COMMENT ON COLUMN t_moderation_log.reason IS '原因';
+COMMENT ON COLUMN t_moderation_log.content IS '消息内容';
+COMMENT ON COLUMN t_moderation_log.created_at IS '创建时间';
+
+CREATE INDEX idx_moderation_log_chat_id ON t_moderation_log (chat_id, created_at DESC);
@@ -0,0 +1,69 @@
+-- user role enum: member / moderator / admin
+CREATE TYPE user_role AS ENUM ('member', 'moderator', 'admin');
+
+ALTER TABLE t_user
+    ADD COLUMN role user_role NOT NULL DEFAULT 'member',
+    ADD COLUMN suspended_until TIMESTAMPTZ;
+
+COMMENT ON COLUMN t_user.role IS '角色';
+COMMENT ON COLUMN t_user.suspended_until IS '禁言截止时间';
+
+-- review source enum: report / moderation
+CREATE TYPE review_source AS ENUM ('report', 'moderation');
+
+-- review status enum: pending / resolved
+CREATE TYPE review_status AS ENUM ('pending', 'resolved');
+
+-- review action enum: dismiss / delete_message / warn_sender / suspend_sender
+CREATE TYPE review_action AS ENUM ('dismiss', 'delete_message', 'warn_sender', 'suspend_sender');
+
+-- review queue table
+CREATE TABLE IF NOT EXISTS t_review_item (
+    id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+    msg_id BIGINT NOT NULL,
+    chat_id BIGINT NOT NULL,
+    sender_id BIGINT NOT NULL,
+    source review_source NOT NULL,
+    reporter_id BIGINT,
+    reason TEXT NOT NULL DEFAULT '',
+    status review_status NOT NULL DEFAULT 'pending',
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
+);
+
+COMMENT ON TABLE t_review_item IS '审核队列表';
+COMMENT ON COLUMN t_review_item.id IS '审核项ID';
+COMMENT ON COLUMN t_review_item.msg_id IS '消息ID';
+COMMENT ON COLUMN t_review_item.chat_id IS '聊天ID';
+COMMENT ON COLUMN t_review_item.sender_id IS '发送者ID';
+COMMENT ON COLUMN t_review_item.source IS '来源（用户举报或审核规则标记）';
+COMMENT ON COLUMN t_review_item.reporter_id IS '举报者ID';
+COMMENT ON COLUMN t_review_item.reason IS '原因';
+COMMENT ON COLUMN t_review_item.status IS '状态';
+COMMENT ON COLUMN t_review_item.created_at IS '创建时间';
+
+CREATE INDEX idx_review_item_status ON t_review_item (status, created_at);
+
+-- a user can report a message only once
+CREATE UNIQUE INDEX idx_review_item_report ON t_review_item (msg_id, reporter_id) WHERE source = 'report';
+
+-- review audit table
+CREATE TABLE IF NOT EXISTS t_review_audit (
+    id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+    item_id BIGINT NOT NULL,
+    moderator_id BIGINT NOT NULL,
+    action review_action NOT NULL,
+    note TEXT NOT NULL DEFAULT '',
+    suspended_until TIMESTAMPTZ,
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
+);
+
+COMMENT ON TABLE t_review_audit IS '审核处理记录表';
+COMMENT ON COLUMN t_review_audit.id IS '记录ID';
+COMMENT ON COLUMN t_review_audit.item_id IS '审核项ID';
+COMMENT ON COLUMN t_review_audit.moderator_id IS '处理人ID';
+COMMENT ON COLUMN t_review_audit.action IS '处理方式';
+COMMENT ON COLUMN t_review_audit.note IS '备注';
+COMMENT ON COLUMN t_review_audit.suspended_until IS '禁言截止时间';
+COMMENT ON COLUMN t_review_audit.created_at IS '创建时间';
+
+CREATE INDEX idx_review_audit_item_id ON t_review_audit (item_id);
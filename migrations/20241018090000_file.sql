@@ -0,0 +1,22 @@
+-- file table, blobs are stored once per content hash and referenced by chat
+CREATE TABLE IF NOT EXISTS t_file (
+    id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+    chat_id BIGINT NOT NULL,
+    uploader_id BIGINT NOT NULL,
+    hash CHAR(64) NOT NULL,
+    ext VARCHAR(16) NOT NULL,
+    size BIGINT NOT NULL,
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (chat_id, hash, ext, uploader_id)
+);
+
+COMMENT ON TABLE t_file IS '文件表';
+COMMENT ON COLUMN t_file.id IS '文件ID';
+COMMENT ON COLUMN t_file.chat_id IS '聊天ID';
+COMMENT ON COLUMN t_file.uploader_id IS '上传者ID';
+COMMENT ON COLUMN t_file.hash IS '文件内容SHA-256';
+COMMENT ON COLUMN t_file.ext IS '文件扩展名';
+COMMENT ON COLUMN t_file.size IS '文件大小（字节）';
+COMMENT ON COLUMN t_file.created_at IS '创建时间';
+
+CREATE INDEX idx_file_hash ON t_file (hash);
@@ -0,0 +1,17 @@
+-- metadata of uploaded images, shared by all references of the same content
+CREATE TABLE IF NOT EXISTS t_image (
+    hash CHAR(64) PRIMARY KEY,
+    width INT NOT NULL,
+    height INT NOT NULL,
+    blurhash VARCHAR(64) NOT NULL,
+    thumbnails INT[] NOT NULL DEFAULT '{}',
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
+);
+
+COMMENT ON TABLE t_image IS '图片元数据表';
+COMMENT ON COLUMN t_image.hash IS '文件内容SHA-256';
+COMMENT ON COLUMN t_image.width IS '宽度';
+COMMENT ON COLUMN t_image.height IS '高度';
+COMMENT ON COLUMN t_image.blurhash IS '模糊占位图';
+COMMENT ON COLUMN t_image.thumbnails IS '已生成的缩略图尺寸';
+COMMENT ON COLUMN t_image.created_at IS '创建时间';
@@ -0,0 +1,24 @@
+-- quota scope enum: user / workspace
+CREATE TYPE quota_scope AS ENUM ('user', 'workspace');
+
+-- storage usage table, bytes of files uploaded by a user or into chats of a workspace
+CREATE TABLE IF NOT EXISTS t_storage_usage (
+    scope quota_scope NOT NULL,
+    scope_id BIGINT NOT NULL,
+    bytes BIGINT NOT NULL DEFAULT 0,
+    updated_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (scope, scope_id)
+);
+
+COMMENT ON TABLE t_storage_usage IS '存储用量表';
+COMMENT ON COLUMN t_storage_usage.scope IS '统计范围';
+COMMENT ON COLUMN t_storage_usage.scope_id IS '用户ID或工作区ID';
+COMMENT ON COLUMN t_storage_usage.bytes IS '已用字节数';
+COMMENT ON COLUMN t_storage_usage.updated_at IS '更新时间';
+
+-- backfill from uploaded files
+INSERT INTO t_storage_usage (scope, scope_id, bytes)
+SELECT 'user', uploader_id, SUM(size) FROM t_file GROUP BY uploader_id;
+
+INSERT INTO t_storage_usage (scope, scope_id, bytes)
+SELECT 'workspace', c.ws_id, SUM(f.size) FROM t_file f JOIN t_chat c ON c.id = f.chat_id GROUP BY c.ws_id;
@@ -0,0 +1,25 @@
+-- resumable upload table (tus protocol), the content is kept on local disk until complete
+CREATE TABLE IF NOT EXISTS t_upload (
+    id VARCHAR(36) PRIMARY KEY,
+    chat_id BIGINT NOT NULL,
+    uploader_id BIGINT NOT NULL,
+    filename VARCHAR(255) NOT NULL,
+    length BIGINT NOT NULL,
+    upload_offset BIGINT NOT NULL DEFAULT 0,
+    file_url VARCHAR(255),
+    expires_at TIMESTAMPTZ NOT NULL,
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
+);
+
+COMMENT ON TABLE t_upload IS '断点续传上传表';
+COMMENT ON COLUMN t_upload.id IS '上传ID';
+COMMENT ON COLUMN t_upload.chat_id IS '聊天ID';
+COMMENT ON COLUMN t_upload.uploader_id IS '上传者ID';
+COMMENT ON COLUMN t_upload.filename IS '文件名';
+COMMENT ON COLUMN t_upload.length IS '文件总大小（字节）';
+COMMENT ON COLUMN t_upload.upload_offset IS '已上传字节数';
+COMMENT ON COLUMN t_upload.file_url IS '上传完成后的文件引用';
+COMMENT ON COLUMN t_upload.expires_at IS '过期时间';
+COMMENT ON COLUMN t_upload.created_at IS '创建时间';
+
+CREATE INDEX idx_upload_expires_at ON t_upload (expires_at);
@@ -0,0 +1,19 @@
+-- uploads flagged by the virus scanner, the content is moved out of storage for review
+CREATE TABLE IF NOT EXISTS t_quarantine (
+    id BIGSERIAL PRIMARY KEY,
+    chat_id BIGINT NOT NULL,
+    uploader_id BIGINT NOT NULL,
+    filename VARCHAR(255) NOT NULL,
+    signature VARCHAR(255) NOT NULL,
+    path VARCHAR(255) NOT NULL,
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
+);
+
+COMMENT ON TABLE t_quarantine IS '隔离文件表';
+COMMENT ON COLUMN t_quarantine.id IS '隔离记录ID';
+COMMENT ON COLUMN t_quarantine.chat_id IS '聊天ID';
+COMMENT ON COLUMN t_quarantine.uploader_id IS '上传者ID';
+COMMENT ON COLUMN t_quarantine.filename IS '文件名';
+COMMENT ON COLUMN t_quarantine.signature IS '扫描命中的病毒特征';
+COMMENT ON COLUMN t_quarantine.path IS '隔离文件路径';
+COMMENT ON COLUMN t_quarantine.created_at IS '创建时间';
@@ -0,0 +1,3 @@
+-- user ids were assigned by hand, generate them on signup
+ALTER TABLE t_user ALTER COLUMN id ADD GENERATED BY DEFAULT AS IDENTITY;
+SELECT setval(pg_get_serial_sequence('t_user', 'id'), COALESCE(MAX(id), 0) + 1, false) FROM t_user;
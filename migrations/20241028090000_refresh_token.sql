@@ -0,0 +1,24 @@
+-- refresh token table, tokens issued by rotating one another form a family
+CREATE TABLE IF NOT EXISTS t_refresh_token (
+    id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+    user_id BIGINT NOT NULL,
+    family_id VARCHAR(36) NOT NULL,
+    token_hash CHAR(64) NOT NULL UNIQUE,
+    expires_at TIMESTAMPTZ NOT NULL,
+    used_at TIMESTAMPTZ,
+    revoked_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
+);
+
+COMMENT ON TABLE t_refresh_token IS '刷新令牌表';
+COMMENT ON COLUMN t_refresh_token.id IS '令牌ID';
+COMMENT ON COLUMN t_refresh_token.user_id IS '用户ID';
+COMMENT ON COLUMN t_refresh_token.family_id IS '令牌族ID（同一次登录轮换产生的令牌）';
+COMMENT ON COLUMN t_refresh_token.token_hash IS '令牌的SHA-256摘要';
+COMMENT ON COLUMN t_refresh_token.expires_at IS '过期时间';
+COMMENT ON COLUMN t_refresh_token.used_at IS '轮换使用时间';
+COMMENT ON COLUMN t_refresh_token.revoked_at IS '撤销时间';
+COMMENT ON COLUMN t_refresh_token.created_at IS '创建时间';
+
+CREATE INDEX idx_refresh_token_family_id ON t_refresh_token (family_id);
+CREATE INDEX idx_refresh_token_expires_at ON t_refresh_token (expires_at);
@@ -0,0 +1,15 @@
+-- access tokens revoked before they expire, e.g. on logout
+CREATE TABLE IF NOT EXISTS t_revoked_token (
+    jti VARCHAR(36) PRIMARY KEY,
+    user_id BIGINT NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL,
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
+);
+
+COMMENT ON TABLE t_revoked_token IS '已撤销的访问令牌表';
+COMMENT ON COLUMN t_revoked_token.jti IS '令牌ID';
+COMMENT ON COLUMN t_revoked_token.user_id IS '用户ID';
+COMMENT ON COLUMN t_revoked_token.expires_at IS '令牌过期时间';
+COMMENT ON COLUMN t_revoked_token.created_at IS '创建时间';
+
+CREATE INDEX idx_revoked_token_expires_at ON t_revoked_token (expires_at);
@@ -0,0 +1,32 @@
+-- signed in devices, the id is the family id of the refresh tokens rotated from the sign in
+CREATE TABLE IF NOT EXISTS t_session (
+    id VARCHAR(36) PRIMARY KEY,
+    user_id BIGINT NOT NULL,
+    device_name VARCHAR(64) NOT NULL DEFAULT '',
+    user_agent VARCHAR(255) NOT NULL DEFAULT '',
+    ip VARCHAR(45) NOT NULL DEFAULT '',
+    last_seen_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP,
+    revoked_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
+);
+
+COMMENT ON TABLE t_session IS '登录会话表';
+COMMENT ON COLUMN t_session.id IS '会话ID（即刷新令牌族ID）';
+COMMENT ON COLUMN t_session.user_id IS '用户ID';
+COMMENT ON COLUMN t_session.device_name IS '设备名称';
+COMMENT ON COLUMN t_session.user_agent IS '登录时的User-Agent';
+COMMENT ON COLUMN t_session.ip IS '登录时的IP';
+COMMENT ON COLUMN t_session.last_seen_at IS '最近活跃时间';
+COMMENT ON COLUMN t_session.revoked_at IS '撤销时间';
+COMMENT ON COLUMN t_session.created_at IS '创建时间';
+
+CREATE INDEX idx_session_user_id ON t_session (user_id);
+
+-- sessions of the refresh tokens issued so far
+INSERT INTO t_session (id, user_id, last_seen_at, revoked_at, created_at)
+SELECT family_id, MIN(user_id), MAX(created_at), MIN(revoked_at), MIN(created_at)
+FROM t_refresh_token GROUP BY family_id
+ON CONFLICT DO NOTHING;
+
+-- revoked sessions are denied like revoked tokens until their access tokens expire
+COMMENT ON COLUMN t_revoked_token.jti IS '令牌ID或会话ID';
@@ -0,0 +1,25 @@
+-- users signed up so far are trusted, new ones have limited access until they verify their email
+ALTER TABLE t_user ADD COLUMN IF NOT EXISTS email_verified_at TIMESTAMPTZ;
+UPDATE t_user SET email_verified_at = created_at WHERE email_verified_at IS NULL;
+
+COMMENT ON COLUMN t_user.email_verified_at IS '邮箱验证时间';
+
+-- email verification table, a token is mailed as a link on signup and on every resend
+CREATE TABLE IF NOT EXISTS t_email_verification (
+    id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+    user_id BIGINT NOT NULL,
+    token_hash CHAR(64) NOT NULL UNIQUE,
+    expires_at TIMESTAMPTZ NOT NULL,
+    used_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
+);
+
+COMMENT ON TABLE t_email_verification IS '邮箱验证表';
+COMMENT ON COLUMN t_email_verification.id IS '验证ID';
+COMMENT ON COLUMN t_email_verification.user_id IS '用户ID';
+COMMENT ON COLUMN t_email_verification.token_hash IS '验证令牌的SHA-256摘要';
+COMMENT ON COLUMN t_email_verification.expires_at IS '过期时间';
+COMMENT ON COLUMN t_email_verification.used_at IS '使用时间';
+COMMENT ON COLUMN t_email_verification.created_at IS '创建时间（用于重发限流）';
+
+CREATE INDEX idx_email_verification_user_id ON t_email_verification (user_id, created_at);
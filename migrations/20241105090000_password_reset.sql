@@ -0,0 +1,19 @@
+-- password reset table, a single use token is mailed on request
+CREATE TABLE IF NOT EXISTS t_password_reset (
+    id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+    user_id BIGINT NOT NULL,
+    token_hash CHAR(64) NOT NULL UNIQUE,
+    expires_at TIMESTAMPTZ NOT NULL,
+    used_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
+);
+
+COMMENT ON TABLE t_password_reset IS '密码重置表';
+COMMENT ON COLUMN t_password_reset.id IS '重置ID';
+COMMENT ON COLUMN t_password_reset.user_id IS '用户ID';
+COMMENT ON COLUMN t_password_reset.token_hash IS '重置令牌的SHA-256摘要';
+COMMENT ON COLUMN t_password_reset.expires_at IS '过期时间';
+COMMENT ON COLUMN t_password_reset.used_at IS '使用时间';
+COMMENT ON COLUMN t_password_reset.created_at IS '创建时间（用于限流）';
+
+CREATE INDEX idx_password_reset_user_id ON t_password_reset (user_id, created_at);
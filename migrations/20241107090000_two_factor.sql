@@ -0,0 +1,57 @@
+-- totp secret of a user, 2FA is on once the enrollment is confirmed with a code
+CREATE TABLE IF NOT EXISTS t_totp (
+    user_id BIGINT PRIMARY KEY,
+    secret BYTEA NOT NULL,
+    last_step BIGINT,
+    enabled_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
+);
+
+COMMENT ON TABLE t_totp IS 'TOTP两步验证表';
+COMMENT ON COLUMN t_totp.user_id IS '用户ID';
+COMMENT ON COLUMN t_totp.secret IS 'TOTP密钥';
+COMMENT ON COLUMN t_totp.last_step IS '最近使用的时间步（防止验证码重放）';
+COMMENT ON COLUMN t_totp.enabled_at IS '启用时间，为空表示待确认';
+COMMENT ON COLUMN t_totp.created_at IS '创建时间';
+
+-- one-time recovery codes of 2FA, in case the authenticator is lost
+CREATE TABLE IF NOT EXISTS t_recovery_code (
+    id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+    user_id BIGINT NOT NULL,
+    code_hash CHAR(64) NOT NULL,
+    used_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
+);
+
+COMMENT ON TABLE t_recovery_code IS '两步验证恢复码表';
+COMMENT ON COLUMN t_recovery_code.id IS '恢复码ID';
+COMMENT ON COLUMN t_recovery_code.user_id IS '用户ID';
+COMMENT ON COLUMN t_recovery_code.code_hash IS '恢复码的SHA-256摘要';
+COMMENT ON COLUMN t_recovery_code.used_at IS '使用时间';
+COMMENT ON COLUMN t_recovery_code.created_at IS '创建时间';
+
+CREATE INDEX idx_recovery_code_user_id ON t_recovery_code (user_id);
+
+-- sign in waiting for the second factor, the password is already verified
+CREATE TABLE IF NOT EXISTS t_login_challenge (
+    id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+    user_id BIGINT NOT NULL,
+    token_hash CHAR(64) NOT NULL UNIQUE,
+    device_name VARCHAR(64) NOT NULL DEFAULT '',
+    attempts INT NOT NULL DEFAULT 0,
+    expires_at TIMESTAMPTZ NOT NULL,
+    used_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
+);
+
+COMMENT ON TABLE t_login_challenge IS '两步验证登录挑战表';
+COMMENT ON COLUMN t_login_challenge.id IS '挑战ID';
+COMMENT ON COLUMN t_login_challenge.user_id IS '用户ID';
+COMMENT ON COLUMN t_login_challenge.token_hash IS '挑战令牌的SHA-256摘要';
+COMMENT ON COLUMN t_login_challenge.device_name IS '登录设备名称';
+COMMENT ON COLUMN t_login_challenge.attempts IS '验证码错误次数';
+COMMENT ON COLUMN t_login_challenge.expires_at IS '过期时间';
+COMMENT ON COLUMN t_login_challenge.used_at IS '完成或作废时间';
+COMMENT ON COLUMN t_login_challenge.created_at IS '创建时间';
+
+CREATE INDEX idx_login_challenge_user_id ON t_login_challenge (user_id, created_at);
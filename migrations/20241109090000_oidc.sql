@@ -0,0 +1,38 @@
+-- pending sign in through an OpenID Connect provider, from the authorization request to the callback
+CREATE TABLE IF NOT EXISTS t_oidc_state (
+    state_hash CHAR(64) PRIMARY KEY,
+    provider VARCHAR(64) NOT NULL,
+    nonce VARCHAR(64) NOT NULL,
+    code_verifier VARCHAR(128) NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL,
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
+);
+
+COMMENT ON TABLE t_oidc_state IS 'OIDC登录状态表';
+COMMENT ON COLUMN t_oidc_state.state_hash IS 'state参数的SHA-256摘要';
+COMMENT ON COLUMN t_oidc_state.provider IS '身份提供方名称';
+COMMENT ON COLUMN t_oidc_state.nonce IS 'ID令牌的nonce';
+COMMENT ON COLUMN t_oidc_state.code_verifier IS 'PKCE校验码';
+COMMENT ON COLUMN t_oidc_state.expires_at IS '过期时间';
+COMMENT ON COLUMN t_oidc_state.created_at IS '创建时间';
+
+-- accounts of providers linked to users
+CREATE TABLE IF NOT EXISTS t_user_identity (
+    id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+    user_id BIGINT NOT NULL,
+    provider VARCHAR(64) NOT NULL,
+    subject VARCHAR(255) NOT NULL,
+    email VARCHAR(64) NOT NULL DEFAULT '',
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (provider, subject)
+);
+
+COMMENT ON TABLE t_user_identity IS '第三方身份绑定表';
+COMMENT ON COLUMN t_user_identity.id IS '绑定ID';
+COMMENT ON COLUMN t_user_identity.user_id IS '用户ID';
+COMMENT ON COLUMN t_user_identity.provider IS '身份提供方名称';
+COMMENT ON COLUMN t_user_identity.subject IS '提供方的用户标识（sub）';
+COMMENT ON COLUMN t_user_identity.email IS '绑定时提供方返回的邮箱';
+COMMENT ON COLUMN t_user_identity.created_at IS '创建时间';
+
+CREATE INDEX idx_user_identity_user_id ON t_user_identity (user_id);
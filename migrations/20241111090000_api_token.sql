@@ -0,0 +1,29 @@
+-- long lived api tokens of integrations and bots, acting as their user within their scopes
+CREATE TABLE IF NOT EXISTS t_api_token (
+    id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+    user_id BIGINT NOT NULL,
+    created_by BIGINT NOT NULL,
+    name VARCHAR(64) NOT NULL,
+    token_hash CHAR(64) NOT NULL UNIQUE,
+    token_prefix VARCHAR(16) NOT NULL,
+    scopes VARCHAR(32)[] NOT NULL DEFAULT '{}',
+    expires_at TIMESTAMPTZ,
+    last_used_at TIMESTAMPTZ,
+    revoked_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
+);
+
+COMMENT ON TABLE t_api_token IS 'API令牌表';
+COMMENT ON COLUMN t_api_token.id IS '令牌ID';
+COMMENT ON COLUMN t_api_token.user_id IS '令牌代表的用户ID';
+COMMENT ON COLUMN t_api_token.created_by IS '创建者ID（管理员可为其他用户创建）';
+COMMENT ON COLUMN t_api_token.name IS '令牌名称';
+COMMENT ON COLUMN t_api_token.token_hash IS '令牌的SHA-256摘要';
+COMMENT ON COLUMN t_api_token.token_prefix IS '令牌前几位（用于辨认）';
+COMMENT ON COLUMN t_api_token.scopes IS '权限范围，例如read:messages';
+COMMENT ON COLUMN t_api_token.expires_at IS '过期时间，为空表示不过期';
+COMMENT ON COLUMN t_api_token.last_used_at IS '最近使用时间';
+COMMENT ON COLUMN t_api_token.revoked_at IS '撤销时间';
+COMMENT ON COLUMN t_api_token.created_at IS '创建时间';
+
+CREATE INDEX idx_api_token_user_id ON t_api_token (user_id);
@@ -0,0 +1,23 @@
+-- usernames and emails are unique regardless of case,
+-- later users of a duplicated username get their id appended
+UPDATE t_user u SET username = LEFT(u.username, 43) || '-' || u.id
+WHERE EXISTS (
+    SELECT 1 FROM t_user o WHERE LOWER(o.username) = LOWER(u.username) AND o.id < u.id
+);
+
+-- a duplicated email belongs to one person, which account to keep can not be decided here
+DO $$
+DECLARE
+    duplicates TEXT;
+BEGIN
+    SELECT string_agg(DISTINCT LOWER(u.email), ', ') INTO duplicates FROM t_user u
+    WHERE EXISTS (
+        SELECT 1 FROM t_user o WHERE LOWER(o.email) = LOWER(u.email) AND o.id <> u.id
+    );
+    IF duplicates IS NOT NULL THEN
+        RAISE EXCEPTION 'emails used by several users, merge or change the accounts before migrating: %', duplicates;
+    END IF;
+END $$;
+
+CREATE UNIQUE INDEX idx_user_username ON t_user (LOWER(username));
+CREATE UNIQUE INDEX idx_user_email ON t_user (LOWER(email));
@@ -0,0 +1,6 @@
+-- an upload is claimed by a lease while a chunk is written, instead of a row lock held by an open transaction
+ALTER TABLE t_upload ADD COLUMN lease_id VARCHAR(36);
+ALTER TABLE t_upload ADD COLUMN leased_until TIMESTAMPTZ;
+
+COMMENT ON COLUMN t_upload.lease_id IS '写入租约ID';
+COMMENT ON COLUMN t_upload.leased_until IS '写入租约到期时间';
@@ -0,0 +1,13 @@
+-- wrong codes given outside of a sign in, e.g. to disable 2FA, they count towards the same lockout
+CREATE TABLE IF NOT EXISTS t_totp_failure (
+    id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+    user_id BIGINT NOT NULL,
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
+);
+
+COMMENT ON TABLE t_totp_failure IS '两步验证失败记录表';
+COMMENT ON COLUMN t_totp_failure.id IS '记录ID';
+COMMENT ON COLUMN t_totp_failure.user_id IS '用户ID';
+COMMENT ON COLUMN t_totp_failure.created_at IS '创建时间';
+
+CREATE INDEX idx_totp_failure_user_id ON t_totp_failure (user_id, created_at);
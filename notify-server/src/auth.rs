@@ -0,0 +1,131 @@
+use axum::{
+    extract::{FromRequestParts, Query, Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use axum_extra::{
+    headers::{authorization::Bearer, Authorization},
+    TypedHeader,
+};
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use serde::Deserialize;
+use std::time::Duration;
+use tracing::warn;
+
+use crate::{config::AuthConfig, jwks::JwksClient, AppErr, AppState};
+
+// must be the same as the ones chat-server signs tokens with
+const JWT_ISS: &str = "easy-chat";
+const JWT_AUD: &str = "chat-client";
+
+#[derive(Debug, Clone)]
+pub struct AuthUser {
+    pub id: i64,
+    /// Session of the token, its connections are closed when it is revoked
+    pub sid: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Claims {
+    uid: i64,
+    #[serde(default)]
+    sid: Option<String>,
+}
+
+/// Keys chat-server signs tokens with
+pub enum JwtDecodingKey {
+    // tokens of rotated keys are rejected until the key is configured
+    Fixed(DecodingKey),
+    // fetched from chat-server, keys are selected by the kid of tokens
+    Jwks(JwksClient),
+}
+
+impl JwtDecodingKey {
+    pub fn load(config: &AuthConfig) -> Result<Self, AppErr> {
+        match (&config.jwks_url, &config.public_key) {
+            (Some(url), _) => Ok(Self::Jwks(JwksClient::new(
+                url.clone(),
+                Duration::from_secs(config.jwks_ttl),
+            )?)),
+            (None, Some(pem)) => Ok(Self::Fixed(DecodingKey::from_ed_pem(pem.as_bytes())?)),
+            (None, None) => Err(AppErr::JwksErr(
+                "either auth.jwks_url or auth.public_key must be set".to_string(),
+            )),
+        }
+    }
+
+    pub async fn verify(&self, token: &str) -> Result<AuthUser, AppErr> {
+        let key = match self {
+            Self::Fixed(key) => key.clone(),
+            Self::Jwks(jwks) => jwks.find(decode_header(token)?.kid.as_deref()).await?,
+        };
+
+        let mut validation = Validation::new(Algorithm::EdDSA);
+        validation.set_issuer(&[JWT_ISS]);
+        validation.set_audience(&[JWT_AUD]);
+
+        let token_data = decode::<Claims>(token, &key, &validation)?;
+        Ok(AuthUser {
+            id: token_data.claims.uid,
+            sid: token_data.claims.sid,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Params {
+    token: String,
+}
+
+pub async fn verify_token(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let (mut parts, body) = req.into_parts();
+
+    // EventSource can not set headers, so the token is passed in query in most cases
+    let token =
+        match TypedHeader::<Authorization<Bearer>>::from_request_parts(&mut parts, &state).await {
+            Ok(TypedHeader(Authorization(bearer))) => bearer.token().to_string(),
+            Err(_) => match Query::<Params>::from_request_parts(&mut parts, &state).await {
+                Ok(params) => params.token.clone(),
+                Err(e) => {
+                    let err_msg = format!("Failed to parse token from query: {}", e);
+                    warn!(err_msg);
+                    return (StatusCode::UNAUTHORIZED, err_msg).into_response();
+                }
+            },
+        };
+
+    let user = match state.dk.verify(&token).await {
+        Ok(user) => user,
+        Err(e) => {
+            let err_msg = format!("Failed to verify token: {}", e);
+            warn!(err_msg);
+            return (StatusCode::UNAUTHORIZED, err_msg).into_response();
+        }
+    };
+
+    match is_session_revoked(&state, &user).await {
+        Ok(false) => {
+            let mut req = Request::from_parts(parts, body);
+            req.extensions_mut().insert(user);
+            next.run(req).await
+        }
+        Ok(true) => (StatusCode::UNAUTHORIZED, "Session is revoked").into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+async fn is_session_revoked(state: &AppState, user: &AuthUser) -> Result<bool, AppErr> {
+    let Some(sid) = &user.sid else {
+        return Ok(false);
+    };
+
+    let revoked = sqlx::query_scalar(
+        "SELECT EXISTS(SELECT 1 FROM t_session WHERE id = $1 AND revoked_at IS NOT NULL)",
+    )
+    .bind(sid)
+    .fetch_one(&state.pg)
+    .await?;
+
+    Ok(revoked)
+}
@@ -0,0 +1,46 @@
+use anyhow::{bail, Result};
+use chat_core::{DbConfig, ServerConfig};
+use serde::Deserialize;
+use std::fs::File;
+
+#[derive(Debug, Deserialize)]
+pub struct AppConfig {
+    pub server: ServerConfig,
+    pub db: DbConfig,
+    pub auth: AuthConfig,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AuthConfig {
+    // public key of chat-server, only needed when keys are not fetched from jwks_url
+    #[serde(default)]
+    pub public_key: Option<String>,
+    // e.g. http://localhost:3000/.well-known/jwks.json, so that signing keys can be rotated
+    #[serde(default)]
+    pub jwks_url: Option<String>,
+    // seconds fetched keys are used before they are fetched again
+    #[serde(default = "default_jwks_ttl")]
+    pub jwks_ttl: u64,
+}
+
+fn default_jwks_ttl() -> u64 {
+    5 * 60
+}
+
+impl AppConfig {
+    pub fn try_load() -> Result<Self> {
+        // read from ./application.yaml or /etc/config/easy-chat-notify.yaml or env EASY_CHAT_NOTIFY_CONFIG
+        let ret = match (
+            File::open("./application.yaml"),
+            File::open("/etc/config/easy-chat-notify.yaml"),
+            std::env::var("EASY_CHAT_NOTIFY_CONFIG"),
+        ) {
+            (Ok(file), _, _) => serde_yaml::from_reader(&file)?,
+            (_, Ok(file), _) => serde_yaml::from_reader(&file)?,
+            (_, _, Ok(path)) => serde_yaml::from_reader(&File::open(path)?)?,
+            _ => bail!("Failed to open config file"),
+        };
+
+        Ok(ret)
+    }
+}
@@ -0,0 +1,37 @@
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum AppErr {
+    #[error("io error: {0}")]
+    IoErr(#[from] std::io::Error),
+
+    #[error("sqlx error: {0}")]
+    SqlXErr(#[from] sqlx::Error),
+
+    #[error("anyhow error: {0}")]
+    AnyhowErr(#[from] anyhow::Error),
+
+    #[error("jwt error: {0}")]
+    JwtErr(#[from] jsonwebtoken::errors::Error),
+
+    #[error("jwks error: {0}")]
+    JwksErr(String),
+}
+
+impl IntoResponse for AppErr {
+    fn into_response(self) -> Response {
+        let status = match &self {
+            Self::IoErr(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::SqlXErr(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::AnyhowErr(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::JwtErr(_) => StatusCode::UNAUTHORIZED,
+            Self::JwksErr(_) => StatusCode::SERVICE_UNAVAILABLE,
+        };
+
+        (status, self.to_string()).into_response()
+    }
+}
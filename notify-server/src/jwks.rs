@@ -0,0 +1,253 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+
+use jsonwebtoken::{jwk::JwkSet, DecodingKey};
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+use crate::AppErr;
+
+// unknown kids refetch the keys at most this often, so that bogus tokens can not flood chat-server
+const MIN_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+const HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Signing keys of chat-server fetched from its JWKS endpoint, refreshed once they are stale
+pub struct JwksClient {
+    url: String,
+    ttl: Duration,
+    min_refresh: Duration,
+    client: reqwest::Client,
+    // only locked to read or replace the cache, never across a fetch
+    cached: RwLock<Cached>,
+    // one fetch at a time, the others wait for its result
+    fetching: Mutex<()>,
+}
+
+#[derive(Default, Clone)]
+struct Cached {
+    keys: Option<Arc<FetchedKeys>>,
+    // last fetch whether it failed or not, fetches are rate limited from it
+    attempted_at: Option<Instant>,
+}
+
+struct FetchedKeys {
+    keys: HashMap<String, DecodingKey>,
+    // chat-server lists its active key first, tokens without a kid were signed with it
+    active_kid: Option<String>,
+    fetched_at: Instant,
+}
+
+impl JwksClient {
+    pub fn new(url: String, ttl: Duration) -> Result<Self, AppErr> {
+        let client = reqwest::Client::builder()
+            .timeout(HTTP_TIMEOUT)
+            .build()
+            .map_err(|e| AppErr::JwksErr(e.to_string()))?;
+
+        Ok(Self {
+            url,
+            ttl,
+            min_refresh: MIN_REFRESH_INTERVAL,
+            client,
+            cached: RwLock::default(),
+            fetching: Mutex::new(()),
+        })
+    }
+
+    /// Key of the kid, the active key if there is none
+    pub async fn find(&self, kid: Option<&str>) -> Result<DecodingKey, AppErr> {
+        let keys = self.keys(false).await?;
+        if let Some(key) = keys.find(kid) {
+            return Ok(key);
+        }
+
+        // chat-server may have rotated its keys
+        self.keys(true)
+            .await?
+            .find(kid)
+            .ok_or_else(|| AppErr::JwksErr(format!("unknown signing key {:?}", kid)))
+    }
+
+    /// Cached keys, fetched again when they are stale or `refresh` is asked for
+    async fn keys(&self, refresh: bool) -> Result<Arc<FetchedKeys>, AppErr> {
+        if let Some(keys) = self.cached_keys(refresh)? {
+            return Ok(keys);
+        }
+
+        let _fetching = self.fetching.lock().await;
+        // another request may have fetched them meanwhile
+        if let Some(keys) = self.cached_keys(refresh)? {
+            return Ok(keys);
+        }
+
+        let ret = self.fetch().await;
+        let mut cached = self.cached.write().unwrap_or_else(|e| e.into_inner());
+        cached.attempted_at = Some(Instant::now());
+        match ret {
+            Ok(keys) => {
+                let keys = Arc::new(keys);
+                cached.keys = Some(keys.clone());
+                Ok(keys)
+            }
+            // keep verifying with the stale keys while chat-server is unreachable
+            Err(e) => match cached.keys.as_ref() {
+                Some(keys) => {
+                    warn!("failed to refresh jwks, stale keys are used: {}", e);
+                    Ok(keys.clone())
+                }
+                None => Err(e),
+            },
+        }
+    }
+
+    /// The cached keys unless they should be fetched again, an error if there are none
+    /// and they were fetched too recently
+    fn cached_keys(&self, refresh: bool) -> Result<Option<Arc<FetchedKeys>>, AppErr> {
+        let cached = self
+            .cached
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone();
+        let throttled = cached
+            .attempted_at
+            .is_some_and(|at| at.elapsed() < self.min_refresh);
+
+        match cached.keys {
+            Some(keys) if throttled || (!refresh && keys.fetched_at.elapsed() < self.ttl) => {
+                Ok(Some(keys))
+            }
+            None if throttled => Err(AppErr::JwksErr(format!(
+                "no signing keys, fetching {} failed recently",
+                self.url
+            ))),
+            _ => Ok(None),
+        }
+    }
+
+    async fn fetch(&self) -> Result<FetchedKeys, AppErr> {
+        let err = |e: reqwest::Error| AppErr::JwksErr(format!("fetch {} fail: {}", self.url, e));
+        let jwks: JwkSet = self
+            .client
+            .get(&self.url)
+            .send()
+            .await
+            .and_then(|res| res.error_for_status())
+            .map_err(err)?
+            .json()
+            .await
+            .map_err(err)?;
+
+        let mut keys = HashMap::new();
+        for jwk in &jwks.keys {
+            let Some(kid) = &jwk.common.key_id else {
+                continue;
+            };
+            match DecodingKey::from_jwk(jwk) {
+                Ok(key) => {
+                    keys.insert(kid.clone(), key);
+                }
+                Err(e) => warn!("jwk {} is skipped: {}", kid, e),
+            }
+        }
+        info!("fetched {} signing keys from {}", keys.len(), self.url);
+
+        Ok(FetchedKeys {
+            keys,
+            active_kid: jwks.keys.first().and_then(|jwk| jwk.common.key_id.clone()),
+            fetched_at: Instant::now(),
+        })
+    }
+}
+
+impl FetchedKeys {
+    fn find(&self, kid: Option<&str>) -> Option<DecodingKey> {
+        let kid = kid.or(self.active_kid.as_deref())?;
+        self.keys.get(kid).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use axum::{extract::State, http::StatusCode, routing::get, Json, Router};
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex as StdMutex,
+    };
+
+    #[derive(Default)]
+    struct JwksServer {
+        kids: StdMutex<Vec<&'static str>>,
+        down: StdMutex<bool>,
+        hits: AtomicUsize,
+    }
+
+    async fn jwks_handler(
+        State(server): State<Arc<JwksServer>>,
+    ) -> Result<Json<serde_json::Value>, StatusCode> {
+        server.hits.fetch_add(1, Ordering::SeqCst);
+        if *server.down.lock().unwrap() {
+            return Err(StatusCode::SERVICE_UNAVAILABLE);
+        }
+
+        let keys: Vec<_> = server
+            .kids
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|kid| {
+                serde_json::json!({
+                    "kty": "OKP",
+                    "crv": "Ed25519",
+                    "x": "11qYAYKxCrfVS_7TyWQHOg7hcvPapiMlrwIaaPcHURo",
+                    "kid": kid,
+                })
+            })
+            .collect();
+        Ok(Json(serde_json::json!({ "keys": keys })))
+    }
+
+    async fn serve(server: Arc<JwksServer>) -> Result<String> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let url = format!("http://{}/.well-known/jwks.json", listener.local_addr()?);
+        let app = Router::new()
+            .route("/.well-known/jwks.json", get(jwks_handler))
+            .with_state(server);
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        Ok(url)
+    }
+
+    #[tokio::test]
+    async fn test_jwks_client() -> Result<()> {
+        let server = Arc::new(JwksServer::default());
+        *server.kids.lock().unwrap() = vec!["a"];
+        let mut client = JwksClient::new(serve(server.clone()).await?, Duration::from_secs(300))?;
+        client.min_refresh = Duration::from_millis(200);
+
+        client.find(Some("a")).await?;
+        client.find(None).await?;
+        assert_eq!(server.hits.load(Ordering::SeqCst), 1);
+
+        // an unknown kid refetches the keys, at most once per interval
+        *server.kids.lock().unwrap() = vec!["b", "a"];
+        assert!(client.find(Some("b")).await.is_err());
+        tokio::time::sleep(client.min_refresh).await;
+        client.find(Some("b")).await?;
+        assert_eq!(server.hits.load(Ordering::SeqCst), 2);
+
+        // stale keys are used while the server is down, a failed fetch is not retried at once
+        client.ttl = Duration::ZERO;
+        *server.down.lock().unwrap() = true;
+        tokio::time::sleep(client.min_refresh).await;
+        client.find(Some("a")).await?;
+        client.find(Some("b")).await?;
+        assert_eq!(server.hits.load(Ordering::SeqCst), 3);
+
+        Ok(())
+    }
+}
@@ -1,13 +1,68 @@
+mod auth;
+mod config;
+mod error;
+mod jwks;
+mod notif;
 mod sse;
 
-use axum::{response::IntoResponse, routing::get, Router};
+use std::{ops::Deref, sync::Arc};
+
+use auth::{verify_token, JwtDecodingKey};
+use axum::{middleware::from_fn_with_state, response::IntoResponse, routing::get, Router};
 use axum_extra::response::Html;
+use dashmap::DashMap;
+use notif::{setup_pg_listener, Notification};
+use sqlx::PgPool;
 use sse::sse_handler;
+use tokio::sync::broadcast;
 
-pub fn init_router() -> Router {
-    Router::new()
-        .route("/", get(index_handler))
+pub use config::AppConfig;
+pub use error::AppErr;
+
+#[derive(Clone)]
+pub struct AppState(Arc<AppStateInner>);
+
+pub struct AppStateInner {
+    pub(crate) config: AppConfig,
+    pub(crate) dk: JwtDecodingKey,
+    pub(crate) pg: PgPool,
+    // connected users, one broadcast channel shared by all devices of a user
+    pub(crate) users: DashMap<i64, broadcast::Sender<Arc<Notification>>>,
+}
+
+impl Deref for AppState {
+    type Target = AppStateInner;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl AppState {
+    pub fn try_new(config: AppConfig) -> Result<Self, AppErr> {
+        let dk = JwtDecodingKey::load(&config.auth)?;
+        let pg = PgPool::connect_lazy(&config.db.dsn)?;
+
+        Ok(Self(Arc::new(AppStateInner {
+            config,
+            dk,
+            pg,
+            users: DashMap::new(),
+        })))
+    }
+}
+
+pub async fn init_router(config: AppConfig) -> Result<Router, AppErr> {
+    let state = AppState::try_new(config)?;
+    setup_pg_listener(state.clone()).await?;
+
+    let app = Router::new()
         .route("/events", get(sse_handler))
+        .layer(from_fn_with_state(state.clone(), verify_token))
+        .route("/", get(index_handler))
+        .with_state(state);
+
+    Ok(app)
 }
 
 async fn index_handler() -> impl IntoResponse {
@@ -1,5 +1,5 @@
 use anyhow::Result;
-use notify_server::init_router;
+use notify_server::{init_router, AppConfig};
 use tokio::net::TcpListener;
 use tracing::level_filters::LevelFilter;
 use tracing_subscriber::{fmt::Layer, prelude::*};
@@ -9,11 +9,12 @@ async fn main() -> Result<()> {
     let layer = Layer::new().with_filter(LevelFilter::INFO);
     tracing_subscriber::registry().with(layer).init();
 
-    let addr = "0.0.0.0:3001";
+    let config = AppConfig::try_load()?;
+    let addr = format!("0.0.0.0:{}", config.server.port);
 
-    let app = init_router();
+    let app = init_router(config).await?;
 
-    let listener = TcpListener::bind(addr).await?;
+    let listener = TcpListener::bind(&addr).await?;
     println!("listening on {}", addr);
 
     axum::serve(listener, app.into_make_service()).await?;
@@ -0,0 +1,64 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use futures::StreamExt;
+use serde::Deserialize;
+use sqlx::postgres::PgListener;
+use tracing::{info, warn};
+
+use crate::AppState;
+
+// chat-server publishes user events on this channel
+const USER_EVENT_CHANNEL: &str = "user_event";
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Notification {
+    user_ids: Vec<i64>,
+    pub origin_device: Option<String>,
+    pub event: String,
+    pub data: serde_json::Value,
+}
+
+pub async fn setup_pg_listener(state: AppState) -> Result<()> {
+    let mut listener = PgListener::connect(&state.config.db.dsn).await?;
+    listener.listen(USER_EVENT_CHANNEL).await?;
+
+    let mut stream = listener.into_stream();
+
+    tokio::spawn(async move {
+        while let Some(ret) = stream.next().await {
+            let notif = match ret {
+                Ok(notif) => notif,
+                Err(e) => {
+                    warn!("failed to receive notification: {}", e);
+                    continue;
+                }
+            };
+
+            match serde_json::from_str::<Notification>(notif.payload()) {
+                Ok(notif) => dispatch(&state, Arc::new(notif)),
+                Err(e) => warn!("failed to parse notification: {}", e),
+            }
+        }
+        info!("pg listener stopped");
+    });
+
+    Ok(())
+}
+
+fn dispatch(state: &AppState, notif: Arc<Notification>) {
+    for user_id in &notif.user_ids {
+        let Some(tx) = state.users.get(user_id) else {
+            continue;
+        };
+
+        if tx.send(notif.clone()).is_err() {
+            // all connections of the user are closed
+            drop(tx);
+            state
+                .users
+                .remove_if(user_id, |_, tx| tx.receiver_count() == 0);
+        }
+    }
+}
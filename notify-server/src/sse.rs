@@ -1,22 +1,51 @@
-use std::{convert::Infallible, time::Duration};
+use std::{convert::Infallible, sync::Arc, time::Duration};
 
-use axum::response::{
-    sse::{Event, KeepAlive},
-    Sse,
+use axum::{
+    extract::{Query, State},
+    response::{
+        sse::{Event, KeepAlive},
+        Sse,
+    },
+    Extension,
 };
 use axum_extra::{headers, TypedHeader};
-use futures::stream::{self, Stream};
-use tokio_stream::StreamExt;
+use futures::stream::Stream;
+use serde::Deserialize;
+use tokio::sync::broadcast;
+use tokio_stream::{wrappers::BroadcastStream, StreamExt};
 use tracing::info;
 
+use crate::{auth::AuthUser, notif::Notification, AppState};
+
+const CHANNEL_CAPACITY: usize = 256;
+// sent by chat-server when a session is signed out
+const SESSION_REVOKED_EVENT: &str = "sessionRevoked";
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct SseParams {
+    device_id: Option<String>,
+}
+
 pub(crate) async fn sse_handler(
+    Extension(user): Extension<AuthUser>,
+    State(state): State<AppState>,
+    Query(params): Query<SseParams>,
     TypedHeader(user_agent): TypedHeader<headers::UserAgent>,
 ) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
-    info!("{} connected", user_agent.as_str());
+    info!("user {} connected: {}", user.id, user_agent.as_str());
+
+    let rx = state
+        .users
+        .entry(user.id)
+        .or_insert_with(|| broadcast::channel(CHANNEL_CAPACITY).0)
+        .subscribe();
 
-    let stream = stream::repeat_with(|| Event::default().data("hello"))
-        .map(Ok)
-        .throttle(Duration::from_secs(1));
+    let stream = device_notifications(rx, user.sid.clone(), params.device_id).map(|notif| {
+        Ok(Event::default()
+            .event(&notif.event)
+            .data(notif.data.to_string()))
+    });
 
     Sse::new(stream).keep_alive(
         KeepAlive::new()
@@ -24,3 +53,65 @@ pub(crate) async fn sse_handler(
             .text("keep-alive-message"),
     )
 }
+
+/// Notifications of the user for the device, until its session is revoked
+fn device_notifications(
+    rx: broadcast::Receiver<Arc<Notification>>,
+    sid: Option<String>,
+    device_id: Option<String>,
+) -> impl Stream<Item = Arc<Notification>> {
+    BroadcastStream::new(rx)
+        // the connection of a revoked session is closed, the device has to sign in again
+        .take_while(move |ret| !matches!(ret, Ok(notif) if closes_session(notif, sid.as_deref())))
+        .filter_map(move |ret| {
+            let notif = ret.ok()?;
+
+            // the device which made the change already knows about it
+            if notif.origin_device.is_some() && notif.origin_device == device_id {
+                return None;
+            }
+
+            Some(notif)
+        })
+}
+
+fn closes_session(notif: &Notification, sid: Option<&str>) -> bool {
+    notif.event == SESSION_REVOKED_EVENT
+        && sid.is_some()
+        && notif.data.get("sessionId").and_then(|v| v.as_str()) == sid
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn notification(
+        event: &str,
+        data: serde_json::Value,
+        origin: Option<&str>,
+    ) -> Arc<Notification> {
+        let notif = json!({ "userIds": [1], "originDevice": origin, "event": event, "data": data });
+        Arc::new(serde_json::from_value(notif).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_session_revoked_ends_stream() {
+        let (tx, rx) = broadcast::channel(CHANNEL_CAPACITY);
+        let stream = device_notifications(rx, Some("s1".to_string()), Some("d1".to_string()));
+
+        for notif in [
+            notification("newMessage", json!({ "id": 1 }), None),
+            notification("draftUpdated", json!({}), Some("d1")),
+            notification(SESSION_REVOKED_EVENT, json!({ "sessionId": "s2" }), None),
+            notification(SESSION_REVOKED_EVENT, json!({ "sessionId": "s1" }), None),
+            notification("newMessage", json!({ "id": 2 }), None),
+        ] {
+            tx.send(notif).unwrap();
+        }
+
+        // the sender is still alive, the stream ends at the revocation of its own session
+        let events: Vec<_> = stream.map(|notif| notif.event.clone()).collect().await;
+        assert_eq!(events, vec!["newMessage", SESSION_REVOKED_EVENT]);
+    }
+}